@@ -34,16 +34,42 @@ export const disableHTTPMethods = derived(isGraphQL, $isGraphQL => $isGraphQL);
 // Referência para o loader
 let endpointsLoaderRef;
 
+// Token de administração (MOCKIAPI_ADMIN_TOKEN), guardado apenas durante a sessão
+const ADMIN_TOKEN_KEY = 'mockiapi-admin-token';
+
 export function setEndpointsLoader(ref) {
     endpointsLoaderRef = ref;
 }
 
+// Chama uma rota de gestão com o token de administração, pedindo-o quando o servidor responde 401.
+// Com MOCKIAPI_ADMIN_USER/PASSWORD o próprio navegador pede as credenciais Basic.
+export async function adminFetch(url, options = {}) {
+    const withToken = (token) => fetch(url, {
+        ...options,
+        headers: { ...options.headers, ...(token ? { Authorization: `Bearer ${token}` } : {}) }
+    });
+
+    let response = await withToken(sessionStorage.getItem(ADMIN_TOKEN_KEY));
+    const challenge = response.headers.get('WWW-Authenticate') || '';
+    if (response.status === 401 && challenge.startsWith('Bearer')) {
+        const token = prompt('Admin token (MOCKIAPI_ADMIN_TOKEN):');
+        if (token) {
+            sessionStorage.setItem(ADMIN_TOKEN_KEY, token);
+            response = await withToken(token);
+        }
+    }
+    return response;
+}
+
 // Carrega todos os endpoints registrados
 export async function loadEndpoints() {
     showLoader(true);
 
     try {
-        const response = await fetch('/list');
+        const response = await adminFetch('/list');
+        if (!response.ok) {
+            throw new Error('Failed to load endpoints');
+        }
         const data = await response.json();
 
         // Converte objeto para array
@@ -102,7 +128,7 @@ export async function registerEndpoint(formData) {
     showLoader(true);
 
     try {
-        const response = await fetch('/register', {
+        const response = await adminFetch('/register', {
             method: 'POST',
             body: formData
         });
@@ -128,7 +154,7 @@ export async function deleteEndpoint(pathToDelete) {
         showLoader(true);
 
         try {
-            const response = await fetch(`/delete/${encodeURIComponent(pathToDelete)}`, {
+            const response = await adminFetch(`/delete/${encodeURIComponent(pathToDelete)}`, {
                 method: 'DELETE'
             });
 
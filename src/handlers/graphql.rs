@@ -1,138 +1,621 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use graphql_parser::parse_query;
-use graphql_parser::query::{Definition, OperationDefinition, Selection, SelectionSet};
-use serde_json::Value;
+use graphql_parser::query::{
+    Definition, Directive, Document, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+    TypeCondition, Value as GqlValue, VariableDefinition,
+};
+use std::time::Duration;
+use graphql_parser::Pos;
+use log::info;
+use serde_json::{json, Map, Value};
+use tokio::time::sleep;
 use warp::http::{HeaderValue, Response, StatusCode};
 use warp::http::header::CONTENT_TYPE;
 use warp::Rejection;
-use crate::models::{Endpoint, GraphQLRequest, InvalidGraphQLRequest};
+use crate::handlers::graphql_schema::{self, GraphQLSchema};
+use crate::middlewares::dynamic_vars;
+use crate::middlewares::persisted_queries::{query_hash, PersistedQueryStore};
+use crate::models::{Endpoint, GraphQLPayload, GraphQLRequest, GraphQLResponse, InvalidGraphQLRequest};
 
-pub fn handle_graphql(
-    body_str: &str,
+/// Answers a GraphQL request, or a batch of them, from the endpoint's mocks (and schema).
+///
+/// Operations without a mock get a GraphQL error.
+pub async fn handle_graphql(
+    payload: GraphQLPayload,
     endpoint: &Endpoint,
     json_file_content: &str,
-) -> Option<Response<String>> {
-    if body_str.contains("\"query\"") {
-        if let Ok(Some(gql_data)) = process_graphql(body_str, json_file_content) {
-            let status_code = StatusCode::from_u16(endpoint.status_code.unwrap_or(200))
-                .unwrap_or(StatusCode::OK);
-            let response = Response::builder()
-                .status(status_code)
-                .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
-                .body(gql_data.into())
-                .ok()?;
-            return Some(response);
+    schema: Option<&GraphQLSchema>,
+    persisted_queries: &PersistedQueryStore,
+) -> Response<String> {
+    let gql_response = match payload {
+        GraphQLPayload::Single(request) => {
+            match resolve_persisted_query(request, persisted_queries).await {
+                Ok(request) => run_request(&request, json_file_content, schema)
+                    .unwrap_or_else(|| request_error("No mock for this operation.".to_string())),
+                Err(response) => response,
+            }
+        }
+        GraphQLPayload::Batch(requests) => {
+            let mut responses = Vec::with_capacity(requests.len());
+            for request in requests {
+                let response = match resolve_persisted_query(request, persisted_queries).await {
+                    Ok(request) => run_request(&request, json_file_content, schema)
+                        .unwrap_or_else(|| request_error("No mock for this operation.".to_string())),
+                    Err(response) => response,
+                };
+                responses.push(response);
+            }
+            batch_response(responses)
         }
+    };
+
+    if let Some(delay) = gql_response.delay {
+        info!("⏳ Applying operation delay of {} ms", delay);
+        sleep(Duration::from_millis(delay)).await;
+    }
+
+    let status_code = StatusCode::from_u16(gql_response.status.or(endpoint.status_code).unwrap_or(200))
+        .unwrap_or(StatusCode::OK);
+    let mut response = Response::new(gql_response.body.to_string());
+    *response.status_mut() = status_code;
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// Reads a GraphQL POST body: a request object, or an array of them for a batch.
+///
+/// `None` when the body is not a GraphQL request.
+pub fn read_graphql_body(body: &str) -> Option<GraphQLPayload> {
+    match serde_json::from_str::<Value>(body).ok()? {
+        Value::Array(items) if !items.is_empty() => items.into_iter()
+            .map(read_graphql_request)
+            .collect::<Option<Vec<_>>>()
+            .map(GraphQLPayload::Batch),
+        item @ Value::Object(_) => read_graphql_request(item).map(GraphQLPayload::Single),
+        _ => None,
+    }
+}
+
+/// Reads a GraphQL GET request from its query string: `query` and `operationName` as they
+/// are, `variables` and `extensions` JSON-encoded.
+pub fn read_graphql_query_params(query_params: &HashMap<String, String>) -> Option<GraphQLPayload> {
+    let mut request = Map::new();
+    for (param, value) in query_params {
+        let value = match param.as_str() {
+            "query" | "operationName" => Value::String(value.clone()),
+            "variables" | "extensions" => serde_json::from_str(value).ok()?,
+            _ => continue,
+        };
+        request.insert(param.clone(), value);
+    }
+    read_graphql_request(Value::Object(request)).map(GraphQLPayload::Single)
+}
+
+/// A request needs a query, or the hash of a persisted one.
+pub(crate) fn read_graphql_request(item: Value) -> Option<GraphQLRequest> {
+    let request: GraphQLRequest = serde_json::from_value(item).ok()?;
+    (!request.query.is_empty() || request.persisted_query().is_some()).then_some(request)
+}
+
+/// Applies Automatic Persisted Queries: a request sending only the hash of a query gets the
+/// query registered under it, and a request sending both registers the query.
+///
+/// The error is the response to answer instead, like `PersistedQueryNotFound` which makes
+/// the client send the hash again along with the query.
+pub(crate) async fn resolve_persisted_query(
+    mut request: GraphQLRequest,
+    persisted_queries: &PersistedQueryStore,
+) -> Result<GraphQLRequest, GraphQLResponse> {
+    let Some(persisted) = request.persisted_query() else {
+        return Ok(request);
+    };
+    if persisted.version != 1 {
+        return Err(persisted_query_error("Unsupported persisted query version", "PERSISTED_QUERY_NOT_SUPPORTED", Some(400)));
+    }
+
+    if request.query.is_empty() {
+        return match persisted_queries.get(&persisted.sha256_hash).await {
+            Some(query) => {
+                request.query = query;
+                Ok(request)
+            }
+            None => Err(persisted_query_error("PersistedQueryNotFound", "PERSISTED_QUERY_NOT_FOUND", None)),
+        };
+    }
+
+    if !query_hash(&request.query).eq_ignore_ascii_case(&persisted.sha256_hash) {
+        return Err(persisted_query_error("provided sha does not match query", "BAD_REQUEST", Some(400)));
+    }
+    info!("📌 Persisting query {}", persisted.sha256_hash);
+    persisted_queries.insert(&persisted.sha256_hash, request.query.clone()).await
+        .map_err(|message| persisted_query_error(&message, "BAD_REQUEST", Some(400)))?;
+    Ok(request)
+}
+
+fn persisted_query_error(message: &str, code: &str, status: Option<u16>) -> GraphQLResponse {
+    GraphQLResponse {
+        body: json!({ "errors": [{ "message": message, "extensions": { "code": code } }] }),
+        status,
+        delay: None,
+    }
+}
+
+/// Runs a request against the schema when there is one, else against the mocks alone.
+fn run_request(request: &GraphQLRequest, json_file_content: &str, schema: Option<&GraphQLSchema>) -> Option<GraphQLResponse> {
+    match schema {
+        Some(schema) => Some(graphql_schema::execute_request(schema, request, json_file_content)),
+        None => resolve_request(request, json_file_content).ok()?,
+    }
+}
+
+/// Gathers the results of a batch into one array. The operations run side by side, so the
+/// batch takes as long as the slowest one, and answers with the highest status they simulate.
+fn batch_response(responses: Vec<GraphQLResponse>) -> GraphQLResponse {
+    GraphQLResponse {
+        status: responses.iter().filter_map(|response| response.status).max(),
+        delay: responses.iter().filter_map(|response| response.delay).max(),
+        body: Value::Array(responses.into_iter().map(|response| response.body).collect()),
     }
-    None
 }
 
 /// Extract the operation name and load the mocked data
 pub fn process_graphql(body: &str, json_data: &str) -> Result<Option<String>, Rejection> {
     // Parse the GraphQL request body
-    let gql_req: GraphQLRequest = serde_json::from_str(body)
-        .map_err(|_| warp::reject::custom(InvalidGraphQLRequest))?;
+    let gql_req = serde_json::from_str(body).ok()
+        .and_then(read_graphql_request)
+        .ok_or_else(|| warp::reject::custom(InvalidGraphQLRequest))?;
+    Ok(resolve_request(&gql_req, json_data)?.map(|response| response.body.to_string()))
+}
 
+/// Builds the response of the operation mock: its data shaped after the selection set,
+/// its `errors` and `extensions`, and the status and delay it simulates.
+///
+/// Mocks that are not JSON are rejected; queries that do not parse or do not name a
+/// single operation get a GraphQL error response. `None` means no mock matched.
+fn resolve_request(gql_req: &GraphQLRequest, json_data: &str) -> Result<Option<GraphQLResponse>, Rejection> {
     // Parse the JSON mock
     let json: Value = serde_json::from_str(json_data)
         .map_err(|_| warp::reject::custom(InvalidGraphQLRequest))?;
 
-    // Extract operation name from the query
-    let operation_name = extract_operation_name(&gql_req.query)
-        .ok_or_else(|| warp::reject::custom(InvalidGraphQLRequest))?;
-
-    let requested_fields = extract_requested_fields(&gql_req.query);
+    let document = match parse_query::<&str>(&gql_req.query) {
+        Ok(document) => document,
+        Err(e) => return Ok(Some(request_error(format!("Syntax Error: {}", e.to_string().trim())))),
+    };
 
-    // Check if it's a query or mutation
-    let is_query = gql_req.query.trim_start().starts_with("query");
-    let is_mutation = gql_req.query.trim_start().starts_with("mutation");
+    // Pick the operation to run, by `operationName` when the document has several
+    let operation = match find_operation(&document, gql_req.operation_name.as_deref()) {
+        Ok(operation) => operation,
+        Err(message) => return Ok(Some(request_error(message))),
+    };
 
     // Try to get the relevant mock data
-    let maybe_target = if is_query {
-        json.get("query").and_then(|q| q.get(&operation_name))
-    } else if is_mutation {
-        json.get("mutation").and_then(|m| m.get(&operation_name))
-    } else {
-        None
+    let Some(target_data) = mock_key(&operation)
+        .and_then(|key| json.get(operation.kind)?.get(key)) else {
+        return Ok(None);
     };
 
-    let Some(target_data) = maybe_target else {
-        return Ok(None);
+    let variables = with_default_values(gql_req.variables.clone().unwrap_or_default(), &operation);
+    Ok(Some(respond(&document, &operation, &variables, target_data)))
+}
+
+/// Answers an operation from its mock: the case matching the arguments, its data shaped
+/// after the selection set and its errors located in the query.
+pub(crate) fn respond<'a>(
+    document: &Document<'a, &'a str>,
+    operation: &Operation<'_, 'a>,
+    variables: &Map<String, Value>,
+    operation_mock: &Value,
+) -> GraphQLResponse {
+    // Pick the mock matching the arguments, then fill in its templates
+    let mocked = MockedOperation::new(operation_mock, operation, variables);
+
+    // Shape the data after the selection set
+    let selection = Selector { fragments: fragments(document), variables };
+    let data = match &mocked.data {
+        Some(data) => Some(selection.shape(data, operation.selection_set, 0)),
+        // A mock of errors only fails before execution
+        None if mocked.errors.is_some() => None,
+        None => Some(Value::Object(Map::new())),
     };
 
-    // Filter by requested fields
-    let mut filtered_data = serde_json::Map::new();
-    if let Some(obj) = target_data.get("data")
-        .and_then(|v| v.as_object()) {
-        for field in &requested_fields {
-            if let Some(value) = obj.get(field) {
-                filtered_data.insert(field.clone(), value.clone());
-            }
-        }
+    mocked.into_response(data, &selection, operation.selection_set)
+}
+
+/// The parts of an operation definition the mock needs, whatever its kind.
+pub(crate) struct Operation<'d, 'a> {
+    /// `query`, `mutation` or `subscription`, as used for the sections of the mock file.
+    pub kind: &'static str,
+    pub name: Option<&'a str>,
+    pub variable_definitions: &'d [VariableDefinition<'a, &'a str>],
+    pub selection_set: &'d SelectionSet<'a, &'a str>,
+}
+
+/// Finds the operation to run: the one named `operationName`, or the only one of the document.
+/// The error is the message GraphQL servers answer with.
+pub(crate) fn find_operation<'d, 'a>(document: &'d Document<'a, &'a str>, operation_name: Option<&str>) -> Result<Operation<'d, 'a>, String> {
+    let mut operations = document.definitions.iter()
+        .filter_map(|definition| match definition {
+            Definition::Operation(OperationDefinition::SelectionSet(selection_set)) => Some(Operation {
+                kind: "query",
+                name: None,
+                variable_definitions: &[],
+                selection_set,
+            }),
+            Definition::Operation(OperationDefinition::Query(q)) => Some(Operation {
+                kind: "query",
+                name: q.name,
+                variable_definitions: &q.variable_definitions,
+                selection_set: &q.selection_set,
+            }),
+            Definition::Operation(OperationDefinition::Mutation(m)) => Some(Operation {
+                kind: "mutation",
+                name: m.name,
+                variable_definitions: &m.variable_definitions,
+                selection_set: &m.selection_set,
+            }),
+            Definition::Operation(OperationDefinition::Subscription(s)) => Some(Operation {
+                kind: "subscription",
+                name: s.name,
+                variable_definitions: &s.variable_definitions,
+                selection_set: &s.selection_set,
+            }),
+            Definition::Fragment(_) => None,
+        });
+
+    match operation_name {
+        Some(name) => operations.find(|operation| operation.name == Some(name))
+            .ok_or_else(|| format!("Unknown operation named \"{}\".", name)),
+        None => match (operations.next(), operations.next()) {
+            (Some(operation), None) => Ok(operation),
+            (Some(_), Some(_)) => Err("Must provide operation name if query contains multiple operations.".to_string()),
+            (None, _) => Err("Must provide an operation.".to_string()),
+        },
     }
+}
 
-    let final_json = serde_json::json!({
-        "data": filtered_data
-    });
+/// The key of an operation in the mock file: its name, or for an anonymous operation
+/// such as `{ user { id } }` the name of its first field.
+pub(crate) fn mock_key<'a>(operation: &Operation<'_, 'a>) -> Option<&'a str> {
+    operation.name.or_else(|| operation.selection_set.items.iter().find_map(|selection| match selection {
+        Selection::Field(field) => Some(field.name),
+        _ => None,
+    }))
+}
 
-    let result = serde_json::to_string(&final_json)
-        .map_err(|_| warp::reject::custom(InvalidGraphQLRequest))?;
+/// A response for a request that could not be executed, e.g. one that does not parse.
+pub(crate) fn request_error(message: String) -> GraphQLResponse {
+    GraphQLResponse { body: json!({ "errors": [{ "message": message }] }), status: None, delay: None }
+}
 
-    Ok(Some(result))
+pub(crate) fn fragments<'d, 'a>(document: &'d Document<'a, &'a str>) -> HashMap<&'a str, &'d FragmentDefinition<'a, &'a str>> {
+    document.definitions.iter()
+        .filter_map(|definition| match definition {
+            Definition::Fragment(fragment) => Some((fragment.name, fragment)),
+            _ => None,
+        })
+        .collect()
 }
 
-fn extract_operation_name(query: &str) -> Option<String> {
-    let ast = parse_query::<&str>(query).ok();
-    if let Some(ast) = ast {
-        for definition in ast.definitions {
-            if let Definition::Operation(OperationDefinition::Query(q)) = definition {
-                return q.name.map(|s| s.to_string());
-            } else if let Definition::Operation(OperationDefinition::Mutation(m)) = definition {
-                return m.name.map(|s| s.to_string());
+/// Adds the default values of the operation's variable definitions that were not sent.
+pub(crate) fn with_default_values(mut variables: Map<String, Value>, operation: &Operation) -> Map<String, Value> {
+    for definition in operation.variable_definitions {
+        if let Some(default) = &definition.default_value
+            && !variables.contains_key(definition.name) {
+            let default = to_json(default, &Map::new());
+            variables.insert(definition.name.to_string(), default);
+        }
+    }
+    variables
+}
+
+/// The response parts of an operation mock, from the case matching the arguments
+/// with its templates filled in.
+///
+/// ```json
+/// "getUser": {
+///   "data": { "user": { "id": "1", "email": null } },
+///   "errors": [{ "message": "Not allowed", "path": ["user", "email"], "extensions": { "code": "FORBIDDEN" } }],
+///   "status": 200,
+///   "delay": 500
+/// }
+/// ```
+pub(crate) struct MockedOperation {
+    pub data: Option<Value>,
+    pub errors: Option<Vec<Value>>,
+    pub extensions: Option<Value>,
+    pub status: Option<u16>,
+    pub delay: Option<u64>,
+}
+
+impl MockedOperation {
+    /// A selected case replaces the whole operation mock, so it must repeat what it keeps.
+    pub fn new(operation_mock: &Value, operation: &Operation, variables: &Map<String, Value>) -> Self {
+        let arguments = extract_arguments(operation, variables);
+        let params = template_params(&arguments);
+        let mock = select_case(operation_mock, &arguments);
+        let templated = |key: &str| mock.get(key).map(|value| apply_templates(value, &arguments, &params));
+
+        MockedOperation {
+            data: templated("data"),
+            errors: templated("errors").and_then(|errors| match errors {
+                Value::Array(errors) => Some(errors),
+                Value::Null => None,
+                error => Some(vec![error]),
+            }),
+            extensions: templated("extensions"),
+            status: mock.get("status").and_then(Value::as_u64).and_then(|status| u16::try_from(status).ok()),
+            delay: mock.get("delay").and_then(Value::as_u64),
+        }
+    }
+
+    /// Assembles the response around the resolved data. Error paths are rewritten to the
+    /// client's aliases, and the errors that only give a `path` are located.
+    pub fn into_response<'a>(self, data: Option<Value>, selection: &Selector<'_, 'a, '_>, selection_set: &SelectionSet<'a, &'a str>) -> GraphQLResponse {
+        let mut body = Map::new();
+        if let Some(mut errors) = self.errors {
+            for error in errors.iter_mut().filter_map(Value::as_object_mut) {
+                let position = match error.get_mut("path") {
+                    Some(Value::Array(path)) => selection.locate(selection_set, path, 0),
+                    _ => None,
+                };
+                if let Some(position) = position
+                    && !error.contains_key("locations") {
+                    error.insert("locations".to_string(), json!([{ "line": position.line, "column": position.column }]));
+                }
             }
+            body.insert("errors".to_string(), Value::Array(errors));
+        }
+        if let Some(data) = data {
+            body.insert("data".to_string(), data);
+        }
+        if let Some(extensions) = self.extensions {
+            body.insert("extensions".to_string(), extensions);
         }
+
+        GraphQLResponse { body: Value::Object(body), status: self.status, delay: self.delay }
     }
+}
 
-    // Fallback: Try simple regex
-    let tokens: Vec<&str> = query.trim().split_whitespace().collect();
-    if tokens.len() >= 2 && (tokens[0] == "query" || tokens[0] == "mutation") {
-        return Some(tokens[1].to_string());
+/// Collects the values the mock can be selected on: the request variables and the
+/// arguments of the top-level fields, variables resolved.
+pub(crate) fn extract_arguments(operation: &Operation, variables: &Map<String, Value>) -> Map<String, Value> {
+    let mut arguments = variables.clone();
+    for selection in &operation.selection_set.items {
+        if let Selection::Field(field) = selection {
+            for (name, value) in &field.arguments {
+                arguments.insert(name.to_string(), to_json(value, variables));
+            }
+        }
     }
+    arguments
+}
 
-    None
+/// Shapes mock data after a selection set, the way a GraphQL server resolves it.
+pub(crate) struct Selector<'d, 'a, 'v> {
+    pub fragments: HashMap<&'a str, &'d FragmentDefinition<'a, &'a str>>,
+    pub variables: &'v Map<String, Value>,
 }
 
-fn extract_requested_fields(query: &str) -> HashSet<String> {
-    let ast = parse_query::<&str>(query).expect("Failed to parse query");
-    let mut fields = HashSet::new();
+/// Guards against fragments spreading each other forever.
+pub(crate) const MAX_SELECTION_DEPTH: usize = 64;
+
+impl<'d, 'a> Selector<'d, 'a, '_> {
+    /// Keeps only the selected fields of `data`, under their alias, at every level.
+    /// Lists are shaped item by item and fields missing from the mock come out as `null`.
+    pub fn shape(&self, data: &Value, selection_set: &SelectionSet<'a, &'a str>, depth: usize) -> Value {
+        match data {
+            Value::Array(items) => Value::Array(items.iter().map(|item| self.shape(item, selection_set, depth)).collect()),
+            Value::Object(object) => {
+                let mut shaped = Map::new();
+                self.collect(object, selection_set, depth, &mut shaped);
+                Value::Object(shaped)
+            }
+            scalar => scalar.clone(),
+        }
+    }
+
+    fn collect(&self, object: &Map<String, Value>, selection_set: &SelectionSet<'a, &'a str>, depth: usize, shaped: &mut Map<String, Value>) {
+        if depth > MAX_SELECTION_DEPTH {
+            return;
+        }
+
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    if !self.is_included(&field.directives) {
+                        continue;
+                    }
+                    let key = field.alias.unwrap_or(field.name);
+                    let value = match object.get(field.name) {
+                        Some(value) if !field.selection_set.items.is_empty() => self.shape(value, &field.selection_set, depth + 1),
+                        Some(value) => value.clone(),
+                        None => Value::Null,
+                    };
+                    merge_field(shaped, key, value);
+                }
+                Selection::FragmentSpread(spread) => {
+                    if let Some(fragment) = self.fragments.get(spread.fragment_name)
+                        && self.is_included(&spread.directives)
+                        && applies_to(object, Some(&fragment.type_condition)) {
+                        self.collect(object, &fragment.selection_set, depth + 1, shaped);
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    if self.is_included(&fragment.directives) && applies_to(object, fragment.type_condition.as_ref()) {
+                        self.collect(object, &fragment.selection_set, depth + 1, shaped);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finds where the field at a `path` is selected in the query. Segments match response keys
+    /// or field names, as mocks are written without the client's aliases, and are rewritten to
+    /// the response keys once found; list indexes are skipped.
+    pub fn locate(&self, selection_set: &SelectionSet<'a, &'a str>, path: &mut [Value], depth: usize) -> Option<Pos> {
+        let key = match path.first()? {
+            Value::String(key) => key.clone(),
+            _ => return self.locate(selection_set, &mut path[1..], depth),
+        };
+        if depth > MAX_SELECTION_DEPTH {
+            return None;
+        }
 
-    for definition in ast.definitions {
-        if let Definition::Operation(operation) = definition {
-            let owned_selection_set = match operation {
-                OperationDefinition::Query(q) => q.selection_set,
-                OperationDefinition::Mutation(m) => m.selection_set,
-                OperationDefinition::Subscription(s) => s.selection_set,
-                OperationDefinition::SelectionSet(s) => s,
+        for selection in &selection_set.items {
+            let position = match selection {
+                Selection::Field(field) if field.alias.unwrap_or(field.name) == key || field.name == key => {
+                    let position = match path.len() {
+                        1 => Some(field.position),
+                        _ => self.locate(&field.selection_set, &mut path[1..], depth + 1),
+                    };
+                    if position.is_some() {
+                        path[0] = Value::String(field.alias.unwrap_or(field.name).to_string());
+                    }
+                    position
+                }
+                Selection::Field(_) => None,
+                Selection::FragmentSpread(spread) => match self.fragments.get(spread.fragment_name) {
+                    Some(fragment) => self.locate(&fragment.selection_set, path, depth + 1),
+                    None => None,
+                },
+                Selection::InlineFragment(fragment) => self.locate(&fragment.selection_set, path, depth + 1),
             };
+            if position.is_some() {
+                return position;
+            }
+        }
+        None
+    }
 
-            if owned_selection_set.items.is_empty() {
-                return HashSet::new();
+    /// Evaluates `@skip(if: ...)` and `@include(if: ...)`.
+    pub fn is_included(&self, directives: &[Directive<'a, &'a str>]) -> bool {
+        directives.iter().all(|directive| {
+            let condition = directive.arguments.iter()
+                .find(|(name, _)| *name == "if")
+                .map(|(_, value)| to_json(value, self.variables))
+                .and_then(|value| value.as_bool());
+            match directive.name {
+                "skip" => condition != Some(true),
+                "include" => condition != Some(false),
+                _ => true,
             }
+        })
+    }
+}
 
-            collect_field_names_owned(&owned_selection_set, &mut fields);
+/// A fragment applies unless the mock object names another `__typename`.
+fn applies_to<'a>(object: &Map<String, Value>, type_condition: Option<&TypeCondition<'a, &'a str>>) -> bool {
+    match (type_condition, object.get("__typename").and_then(|t| t.as_str())) {
+        (Some(TypeCondition::On(expected)), Some(actual)) => *expected == actual,
+        _ => true,
+    }
+}
+
+/// Merges a field selected several times (e.g. directly and through a fragment).
+pub(crate) fn merge_field(shaped: &mut Map<String, Value>, key: &str, value: Value) {
+    match (shaped.get_mut(key), value) {
+        (Some(Value::Object(existing)), Value::Object(more)) => {
+            for (k, v) in more {
+                merge_field(existing, &k, v);
+            }
+        }
+        (Some(Value::Array(existing)), Value::Array(more)) if existing.len() == more.len() => {
+            for (item, more_item) in existing.iter_mut().zip(more) {
+                if let (Value::Object(item), Value::Object(more_item)) = (item, more_item) {
+                    for (k, v) in more_item {
+                        merge_field(item, &k, v);
+                    }
+                }
+            }
+        }
+        (_, value) => {
+            shaped.insert(key.to_string(), value);
         }
     }
+}
 
-    fields
+/// Converts a GraphQL input value to JSON, replacing variables by their values.
+pub(crate) fn to_json<'a>(value: &GqlValue<'a, &'a str>, variables: &Map<String, Value>) -> Value {
+    match value {
+        GqlValue::Variable(name) => variables.get(*name).cloned().unwrap_or(Value::Null),
+        GqlValue::Int(number) => number.as_i64().map(Value::from).unwrap_or(Value::Null),
+        GqlValue::Float(float) => Value::from(*float),
+        GqlValue::String(string) => Value::String(string.clone()),
+        GqlValue::Boolean(boolean) => Value::Bool(*boolean),
+        GqlValue::Null => Value::Null,
+        GqlValue::Enum(name) => Value::String(name.to_string()),
+        GqlValue::List(items) => Value::Array(items.iter().map(|item| to_json(item, variables)).collect()),
+        GqlValue::Object(fields) => Value::Object(
+            fields.iter().map(|(key, item)| (key.to_string(), to_json(item, variables))).collect()
+        ),
+    }
 }
 
-fn collect_field_names_owned<'a>(selection_set: &SelectionSet<'a, &'a str>, fields: &mut HashSet<String>) {
-    for selection in &selection_set.items {
-        if let Selection::Field(field) = selection {
-            fields.insert(field.name.to_string());
-            if !field.selection_set.items.is_empty() {
-                collect_field_names_owned(&field.selection_set, fields);
+/// Returns the first of the operation's `cases` whose `when` values all match the
+/// arguments, or the operation mock itself as the default.
+///
+/// ```json
+/// "getUser": {
+///   "data": { "id": "1", "name": "Default" },
+///   "cases": [{ "when": { "id": "2" }, "data": { "id": "2", "name": "Jane" } }]
+/// }
+/// ```
+pub(crate) fn select_case<'a>(operation_mock: &'a Value, arguments: &Map<String, Value>) -> &'a Value {
+    operation_mock.get("cases")
+        .and_then(|cases| cases.as_array())
+        .and_then(|cases| cases.iter().find(|case| {
+            case.get("when")
+                .and_then(|when| when.as_object())
+                .is_some_and(|when| when.iter().all(|(name, expected)| {
+                    arguments.get(name).is_some_and(|actual| loosely_equal(expected, actual))
+                }))
+        }))
+        .unwrap_or(operation_mock)
+}
+
+/// Compares values the way they read, so an `ID` given as `2` matches the mock's `"2"`.
+fn loosely_equal(expected: &Value, actual: &Value) -> bool {
+    if expected == actual {
+        return true;
+    }
+    match (expected, actual) {
+        (Value::String(s), Value::Number(n)) | (Value::Number(n), Value::String(s)) => s.parse().ok() == Some(n.clone()),
+        (Value::String(s), Value::Bool(b)) | (Value::Bool(b), Value::String(s)) => s.parse().ok() == Some(*b),
+        _ => false,
+    }
+}
+
+/// Template keys for the arguments: `{{id}}`, or `{{input.name}}` for nested input objects.
+fn template_params(arguments: &Map<String, Value>) -> HashMap<String, String> {
+    arguments.iter()
+        .flat_map(|(name, value)| dynamic_vars::flatten_json(name, value))
+        .collect()
+}
+
+/// Fills `{{var}}` placeholders in the mock's strings. A string that is a single
+/// placeholder of a known argument takes the argument's JSON value, keeping its type.
+fn apply_templates(value: &Value, arguments: &Map<String, Value>, params: &HashMap<String, String>) -> Value {
+    match value {
+        Value::String(template) => {
+            let whole_placeholder = template.strip_prefix("{{")
+                .and_then(|rest| rest.strip_suffix("}}"))
+                .filter(|name| !name.contains("{{"));
+            match whole_placeholder.and_then(|name| argument_at(arguments, name)) {
+                Some(argument) => argument.clone(),
+                None => Value::String(dynamic_vars::replace_variables(template, params)),
             }
         }
+        Value::Array(items) => Value::Array(items.iter().map(|item| apply_templates(item, arguments, params)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields.iter().map(|(key, item)| (key.clone(), apply_templates(item, arguments, params))).collect()
+        ),
+        other => other.clone(),
     }
-}
\ No newline at end of file
+}
+
+/// The argument a dotted template key points to, e.g. `input.name`.
+fn argument_at<'a>(arguments: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    let mut parts = key.split('.');
+    let first = arguments.get(parts.next()?)?;
+    parts.try_fold(first, |value, part| value.get(part))
+}
@@ -0,0 +1,758 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, LazyLock, RwLock};
+use graphql_parser::{parse_query, parse_schema, Pos};
+use graphql_parser::query::{Document, FragmentDefinition, Selection, SelectionSet, TypeCondition, Value as GqlValue};
+use graphql_parser::schema::{
+    Definition, DirectiveDefinition, Field, InputValue, ScalarType, Type, TypeDefinition, TypeExtension,
+};
+use serde_json::{json, Map, Value};
+use crate::handlers::graphql::{find_operation, fragments, mock_key, request_error, MockedOperation, merge_field, to_json, with_default_values, Operation, Selector, MAX_SELECTION_DEPTH};
+use crate::models::{GraphQLRequest, GraphQLResponse};
+
+type SchemaType = TypeDefinition<'static, String>;
+
+const BUILT_IN_SCALARS: [&str; 5] = ["String", "Int", "Float", "Boolean", "ID"];
+/// How many items are generated for a list field the mock does not cover.
+const GENERATED_LIST_LEN: usize = 2;
+
+/// The schemas parsed on registration, by SDL file, so that requests do not parse them again.
+static SCHEMAS: LazyLock<RwLock<HashMap<String, Arc<GraphQLSchema>>>> = LazyLock::new(Default::default);
+
+/// A GraphQL schema uploaded as SDL, used to validate operations, generate the data the
+/// mock does not cover and answer introspection queries.
+#[derive(Debug)]
+pub struct GraphQLSchema {
+    types: BTreeMap<String, SchemaType>,
+    directives: Vec<DirectiveDefinition<'static, String>>,
+    query_type: String,
+    mutation_type: Option<String>,
+    subscription_type: Option<String>,
+}
+
+impl GraphQLSchema {
+    /// Parses SDL, checking that every referenced type is defined.
+    pub fn parse(sdl: &str) -> Result<Self, String> {
+        let document = parse_schema::<String>(sdl).map_err(|e| e.to_string())?.into_static();
+
+        let mut types = BTreeMap::new();
+        let mut extensions = Vec::new();
+        let mut directives = Vec::new();
+        let mut roots = (None, None, None);
+        for definition in document.definitions {
+            match definition {
+                Definition::SchemaDefinition(schema) => roots = (schema.query, schema.mutation, schema.subscription),
+                Definition::TypeDefinition(definition) => {
+                    types.insert(type_name(&definition).to_string(), definition);
+                }
+                Definition::TypeExtension(extension) => extensions.push(extension),
+                Definition::DirectiveDefinition(directive) => directives.push(directive),
+            }
+        }
+        for name in BUILT_IN_SCALARS {
+            types.entry(name.to_string())
+                .or_insert_with(|| TypeDefinition::Scalar(ScalarType::new(name.to_string())));
+        }
+        for extension in extensions {
+            extend(&mut types, extension)?;
+        }
+
+        let root = |declared: Option<String>, default: &str| {
+            declared.or_else(|| types.contains_key(default).then(|| default.to_string()))
+        };
+        let schema = GraphQLSchema {
+            query_type: root(roots.0, "Query").ok_or("The schema has no query type")?,
+            mutation_type: root(roots.1, "Mutation"),
+            subscription_type: root(roots.2, "Subscription"),
+            types,
+            directives,
+        };
+        schema.check_references()?;
+        Ok(schema)
+    }
+
+    /// Keeps the schema parsed on registration for the requests of the endpoint using `schema_file`.
+    pub fn cache(schema_file: &str, schema: GraphQLSchema) -> Arc<Self> {
+        let schema = Arc::new(schema);
+        let mut schemas = SCHEMAS.write().unwrap_or_else(|e| e.into_inner());
+        schemas.insert(schema_file.to_string(), Arc::clone(&schema));
+        schema
+    }
+
+    /// Forgets the schema of an endpoint that was removed or registered again.
+    pub fn evict(schema_file: &str) {
+        SCHEMAS.write().unwrap_or_else(|e| e.into_inner()).remove(schema_file);
+    }
+
+    /// Returns the schema of an endpoint's SDL file, reading and caching the file when it
+    /// was not parsed on registration. It was validated on upload, so failures are only logged.
+    pub async fn load(schema_file: &str) -> Option<Arc<Self>> {
+        if let Some(schema) = SCHEMAS.read().unwrap_or_else(|e| e.into_inner()).get(schema_file) {
+            return Some(Arc::clone(schema));
+        }
+        let sdl = tokio::fs::read_to_string(schema_file).await
+            .map_err(|e| log::warn!("Failed to read GraphQL schema {}: {}", schema_file, e))
+            .ok()?;
+        let schema = Self::parse(&sdl)
+            .map_err(|e| log::warn!("Invalid GraphQL schema {}: {}", schema_file, e))
+            .ok()?;
+        Some(Self::cache(schema_file, schema))
+    }
+
+    fn check_references(&self) -> Result<(), String> {
+        let roots = [Some(&self.query_type), self.mutation_type.as_ref(), self.subscription_type.as_ref()];
+        let mut referenced: Vec<&str> = roots.into_iter().flatten().map(String::as_str).collect();
+        for definition in self.types.values() {
+            match definition {
+                TypeDefinition::Object(object) => {
+                    referenced.extend(object.implements_interfaces.iter().map(String::as_str));
+                    referenced.extend(object.fields.iter().flat_map(field_references));
+                }
+                TypeDefinition::Interface(interface) => {
+                    referenced.extend(interface.fields.iter().flat_map(field_references));
+                }
+                TypeDefinition::Union(union) => referenced.extend(union.types.iter().map(String::as_str)),
+                TypeDefinition::InputObject(input) => {
+                    referenced.extend(input.fields.iter().map(|field| named_type(&field.value_type)));
+                }
+                TypeDefinition::Scalar(_) | TypeDefinition::Enum(_) => {}
+            }
+        }
+
+        match referenced.into_iter().find(|name| !self.types.contains_key(*name)) {
+            Some(unknown) => Err(format!("Unknown type \"{}\"", unknown)),
+            None => Ok(()),
+        }
+    }
+
+    fn root_type(&self, kind: &str) -> Option<&str> {
+        match kind {
+            "mutation" => self.mutation_type.as_deref(),
+            "subscription" => self.subscription_type.as_deref(),
+            _ => Some(&self.query_type),
+        }
+    }
+
+    fn field(&self, type_name: &str, field_name: &str) -> Option<&Field<'static, String>> {
+        let fields = match self.types.get(type_name)? {
+            TypeDefinition::Object(object) => &object.fields,
+            TypeDefinition::Interface(interface) => &interface.fields,
+            _ => return None,
+        };
+        fields.iter().find(|field| field.name == field_name)
+    }
+
+    fn is_leaf(&self, type_name: &str) -> bool {
+        matches!(self.types.get(type_name), Some(TypeDefinition::Scalar(_) | TypeDefinition::Enum(_)))
+    }
+
+    /// The object types an abstract type can resolve to; an object type resolves to itself.
+    fn possible_types<'t>(&'t self, type_name: &'t str) -> Vec<&'t str> {
+        match self.types.get(type_name) {
+            Some(TypeDefinition::Union(union)) => union.types.iter().map(String::as_str).collect(),
+            Some(TypeDefinition::Interface(_)) => self.types.values()
+                .filter_map(|definition| match definition {
+                    TypeDefinition::Object(object) if object.implements_interfaces.iter().any(|i| i == type_name) => {
+                        Some(object.name.as_str())
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![type_name],
+        }
+    }
+
+    /// Whether a fragment on `condition` applies to an object of type `object_type`.
+    fn applies(&self, condition: &str, object_type: &str) -> bool {
+        condition == object_type || self.possible_types(condition).contains(&object_type)
+    }
+}
+
+/// Runs a GraphQL request against a schema, answering from the mock overrides first.
+///
+/// Returns the complete GraphQL response: `data`, or `errors` when the query does not parse
+/// or does not validate against the schema, along with the mocked `errors`, status and delay.
+pub fn execute_request(schema: &GraphQLSchema, gql_req: &GraphQLRequest, json_data: &str) -> GraphQLResponse {
+    // The mock file is optional next to a schema
+    let mocks: Value = serde_json::from_str(json_data).unwrap_or(Value::Null);
+
+    let document = match parse_query::<&str>(&gql_req.query) {
+        Ok(document) => document,
+        Err(e) => return request_error(format!("Syntax Error: {}", e.to_string().trim())),
+    };
+    let operation = match find_operation(&document, gql_req.operation_name.as_deref()) {
+        Ok(operation) => operation,
+        Err(message) => return request_error(message),
+    };
+
+    let variables = with_default_values(gql_req.variables.clone().unwrap_or_default(), &operation);
+    let errors = validate(schema, &document, &operation, &variables);
+    if !errors.is_empty() {
+        return GraphQLResponse { body: json!({ "errors": errors }), status: None, delay: None };
+    }
+
+    let operation_mock = mock_key(&operation).and_then(|key| mocks.get(operation.kind)?.get(key));
+    respond(schema, &document, &operation, &variables, operation_mock)
+}
+
+/// Validates an operation against the schema, returning the GraphQL errors found.
+pub(crate) fn validate<'a>(
+    schema: &GraphQLSchema,
+    document: &Document<'a, &'a str>,
+    operation: &Operation<'_, 'a>,
+    variables: &Map<String, Value>,
+) -> Vec<Value> {
+    Validator::new(schema, document, variables).validate(operation)
+}
+
+/// Answers a validated operation from its mock, generating whatever the mock leaves out.
+pub(crate) fn respond<'a>(
+    schema: &GraphQLSchema,
+    document: &Document<'a, &'a str>,
+    operation: &Operation<'_, 'a>,
+    variables: &Map<String, Value>,
+    operation_mock: Option<&Value>,
+) -> GraphQLResponse {
+    let mocked = operation_mock.map(|operation_mock| MockedOperation::new(operation_mock, operation, variables));
+    let resolver = Resolver {
+        schema,
+        selector: Selector { fragments: fragments(document), variables },
+        counter: Cell::new(0),
+    };
+    let root_type = schema.root_type(operation.kind).unwrap_or_default();
+    let overrides = mocked.as_ref().and_then(|mocked| mocked.data.as_ref());
+    let data = match overrides {
+        Some(Value::Null) => Some(Value::Null),
+        // A mock of errors only fails before execution
+        None if mocked.as_ref().is_some_and(|mocked| mocked.errors.is_some()) => None,
+        _ => {
+            let mut data = Map::new();
+            resolver.collect(operation.selection_set, root_type, overrides.and_then(Value::as_object), 0, &mut data);
+            Some(Value::Object(data))
+        }
+    };
+
+    match mocked {
+        Some(mocked) => mocked.into_response(data, &resolver.selector, operation.selection_set),
+        None => GraphQLResponse { body: json!({ "data": data }), status: None, delay: None },
+    }
+}
+
+/// Checks an operation against the schema, collecting errors the way GraphQL servers report them.
+struct Validator<'s, 'd, 'a, 'v> {
+    schema: &'s GraphQLSchema,
+    fragments: HashMap<&'a str, &'d FragmentDefinition<'a, &'a str>>,
+    variables: &'v Map<String, Value>,
+    defined_variables: HashSet<&'a str>,
+    errors: Vec<Value>,
+}
+
+impl<'s, 'd, 'a, 'v> Validator<'s, 'd, 'a, 'v> {
+    fn new(schema: &'s GraphQLSchema, document: &'d Document<'a, &'a str>, variables: &'v Map<String, Value>) -> Self {
+        Validator { schema, fragments: fragments(document), variables, defined_variables: HashSet::new(), errors: Vec::new() }
+    }
+
+    fn validate(mut self, operation: &Operation<'d, 'a>) -> Vec<Value> {
+        let schema = self.schema;
+        let Some(root_type) = schema.root_type(operation.kind) else {
+            self.errors.push(error(format!("Schema is not configured for {}s.", operation.kind), None));
+            return self.errors;
+        };
+
+        for definition in operation.variable_definitions {
+            self.defined_variables.insert(definition.name);
+            if !self.schema.types.contains_key(named_type(&definition.var_type)) {
+                self.error(format!("Unknown type \"{}\".", named_type(&definition.var_type)), definition.position);
+            }
+            let provided = self.variables.get(definition.name).is_some_and(|value| !value.is_null());
+            if matches!(definition.var_type, Type::NonNullType(_)) && !provided {
+                self.error(
+                    format!("Variable \"${}\" of required type \"{}\" was not provided.", definition.name, definition.var_type),
+                    definition.position,
+                );
+            }
+        }
+
+        self.selection_set(operation.selection_set, root_type, root_type == schema.query_type, 0);
+        self.errors
+    }
+
+    fn selection_set(&mut self, selection_set: &'d SelectionSet<'a, &'a str>, type_name: &str, is_query_root: bool, depth: usize) {
+        if depth > MAX_SELECTION_DEPTH {
+            return;
+        }
+
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    self.arguments_use_defined_variables(field.arguments.iter().map(|(_, value)| value), field.position);
+                    if field.name == "__typename" || (is_query_root && matches!(field.name, "__schema" | "__type")) {
+                        continue;
+                    }
+
+                    let schema = self.schema;
+                    let Some(definition) = schema.field(type_name, field.name) else {
+                        self.error(format!("Cannot query field \"{}\" on type \"{}\".", field.name, type_name), field.position);
+                        continue;
+                    };
+
+                    for (name, _) in &field.arguments {
+                        if !definition.arguments.iter().any(|argument| argument.name == *name) {
+                            self.error(format!("Unknown argument \"{}\" on field \"{}.{}\".", name, type_name, field.name), field.position);
+                        }
+                    }
+                    for argument in &definition.arguments {
+                        let required = matches!(argument.value_type, Type::NonNullType(_)) && argument.default_value.is_none();
+                        if required && !field.arguments.iter().any(|(name, _)| *name == argument.name) {
+                            self.error(
+                                format!("Field \"{}\" argument \"{}\" of type \"{}\" is required, but it was not provided.",
+                                        field.name, argument.name, argument.value_type),
+                                field.position,
+                            );
+                        }
+                    }
+
+                    let field_type = named_type(&definition.field_type);
+                    let has_selection = !field.selection_set.items.is_empty();
+                    if schema.is_leaf(field_type) && has_selection {
+                        self.error(
+                            format!("Field \"{}\" must not have a selection since type \"{}\" has no subfields.", field.name, definition.field_type),
+                            field.position,
+                        );
+                    } else if !schema.is_leaf(field_type) && !has_selection {
+                        self.error(
+                            format!("Field \"{}\" of type \"{}\" must have a selection of subfields. Did you mean \"{} {{ ... }}\"?",
+                                    field.name, definition.field_type, field.name),
+                            field.position,
+                        );
+                    } else {
+                        self.selection_set(&field.selection_set, field_type, false, depth + 1);
+                    }
+                }
+                Selection::FragmentSpread(spread) => {
+                    match self.fragments.get(spread.fragment_name).copied() {
+                        Some(fragment) => {
+                            let TypeCondition::On(condition) = fragment.type_condition;
+                            if self.known_type(condition, fragment.position) {
+                                self.selection_set(&fragment.selection_set, condition, is_query_root && condition == type_name, depth + 1);
+                            }
+                        }
+                        None => self.error(format!("Unknown fragment \"{}\".", spread.fragment_name), spread.position),
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    let condition = match fragment.type_condition {
+                        Some(TypeCondition::On(condition)) => condition,
+                        None => type_name,
+                    };
+                    if self.known_type(condition, fragment.position) {
+                        self.selection_set(&fragment.selection_set, condition, is_query_root && condition == type_name, depth + 1);
+                    }
+                }
+            }
+        }
+    }
+
+    fn known_type(&mut self, name: &str, position: Pos) -> bool {
+        let known = self.schema.types.contains_key(name);
+        if !known {
+            self.error(format!("Unknown type \"{}\".", name), position);
+        }
+        known
+    }
+
+    fn arguments_use_defined_variables<'i>(&mut self, values: impl Iterator<Item = &'i GqlValue<'a, &'a str>>, position: Pos)
+    where 'a: 'i {
+        for value in values {
+            match value {
+                GqlValue::Variable(name) if !self.defined_variables.contains(name) => {
+                    self.error(format!("Variable \"${}\" is not defined.", name), position);
+                }
+                GqlValue::List(items) => self.arguments_use_defined_variables(items.iter(), position),
+                GqlValue::Object(fields) => self.arguments_use_defined_variables(fields.values(), position),
+                _ => {}
+            }
+        }
+    }
+
+    fn error(&mut self, message: String, position: Pos) {
+        self.errors.push(error(message, Some(position)));
+    }
+}
+
+fn error(message: String, position: Option<Pos>) -> Value {
+    match position {
+        Some(position) => json!({ "message": message, "locations": [{ "line": position.line, "column": position.column }] }),
+        None => json!({ "message": message }),
+    }
+}
+
+/// Resolves a validated operation: values come from the mock overrides when present and
+/// are generated from the field types otherwise.
+struct Resolver<'s, 'd, 'a, 'v> {
+    schema: &'s GraphQLSchema,
+    selector: Selector<'d, 'a, 'v>,
+    /// Makes generated values differ from one field to the next, e.g. list item IDs.
+    counter: Cell<usize>,
+}
+
+impl<'s, 'd, 'a> Resolver<'s, 'd, 'a, '_> {
+    fn collect(
+        &self,
+        selection_set: &SelectionSet<'a, &'a str>,
+        type_name: &str,
+        overrides: Option<&Map<String, Value>>,
+        depth: usize,
+        shaped: &mut Map<String, Value>,
+    ) {
+        if depth > MAX_SELECTION_DEPTH {
+            return;
+        }
+
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    if !self.selector.is_included(&field.directives) {
+                        continue;
+                    }
+                    let key = field.alias.unwrap_or(field.name);
+                    let value = match field.name {
+                        "__typename" => Value::String(type_name.to_string()),
+                        "__schema" => self.selector.shape(&introspect_schema(self.schema), &field.selection_set, 0),
+                        "__type" => {
+                            let requested = field.arguments.iter()
+                                .find(|(name, _)| *name == "name")
+                                .map(|(_, value)| to_json(value, self.selector.variables));
+                            let introspected = requested.as_ref()
+                                .and_then(Value::as_str)
+                                .and_then(|name| self.schema.types.get(name))
+                                .map(|definition| introspect_type(self.schema, definition))
+                                .unwrap_or(Value::Null);
+                            self.selector.shape(&introspected, &field.selection_set, 0)
+                        }
+                        name => match self.schema.field(type_name, name) {
+                            Some(definition) => match overrides.and_then(|overrides| overrides.get(name)) {
+                                Some(value) => self.complete(value, &definition.field_type, &field.selection_set, depth),
+                                None => self.generate(&definition.field_type, &field.selection_set, name, depth),
+                            },
+                            None => Value::Null,
+                        },
+                    };
+                    merge_field(shaped, key, value);
+                }
+                Selection::FragmentSpread(spread) => {
+                    if let Some(fragment) = self.selector.fragments.get(spread.fragment_name)
+                        && self.selector.is_included(&spread.directives)
+                        && let TypeCondition::On(condition) = fragment.type_condition
+                        && self.schema.applies(condition, type_name) {
+                        self.collect(&fragment.selection_set, type_name, overrides, depth + 1, shaped);
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    let applies = match fragment.type_condition {
+                        Some(TypeCondition::On(condition)) => self.schema.applies(condition, type_name),
+                        None => true,
+                    };
+                    if applies && self.selector.is_included(&fragment.directives) {
+                        self.collect(&fragment.selection_set, type_name, overrides, depth + 1, shaped);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Shapes a mock value after its field type, generating what its objects leave out.
+    fn complete(&self, value: &Value, field_type: &Type<'static, String>, selection_set: &SelectionSet<'a, &'a str>, depth: usize) -> Value {
+        match (field_type, value) {
+            (_, Value::Null) => Value::Null,
+            (Type::NonNullType(inner), _) => self.complete(value, inner, selection_set, depth),
+            (Type::ListType(inner), Value::Array(items)) => Value::Array(
+                items.iter().map(|item| self.complete(item, inner, selection_set, depth)).collect()
+            ),
+            (Type::NamedType(name), Value::Object(object)) if !self.schema.is_leaf(name) => {
+                let concrete = object.get("__typename")
+                    .and_then(Value::as_str)
+                    .filter(|typename| self.schema.applies(name, typename))
+                    .unwrap_or_else(|| self.concrete_type(name));
+                let mut shaped = Map::new();
+                self.collect(selection_set, concrete, Some(object), depth + 1, &mut shaped);
+                Value::Object(shaped)
+            }
+            _ => value.clone(),
+        }
+    }
+
+    /// Generates a value of the field type, covering the whole selection.
+    fn generate(&self, field_type: &Type<'static, String>, selection_set: &SelectionSet<'a, &'a str>, field_name: &str, depth: usize) -> Value {
+        match field_type {
+            Type::NonNullType(inner) => self.generate(inner, selection_set, field_name, depth),
+            Type::ListType(inner) => Value::Array(
+                (0..GENERATED_LIST_LEN).map(|_| self.generate(inner, selection_set, field_name, depth)).collect()
+            ),
+            Type::NamedType(name) => match self.schema.types.get(name) {
+                Some(TypeDefinition::Enum(enumeration)) => enumeration.values.first()
+                    .map(|value| Value::String(value.name.clone()))
+                    .unwrap_or(Value::Null),
+                Some(TypeDefinition::Scalar(_)) => self.generate_scalar(name, field_name),
+                Some(_) => {
+                    let mut shaped = Map::new();
+                    self.collect(selection_set, self.concrete_type(name), None, depth + 1, &mut shaped);
+                    Value::Object(shaped)
+                }
+                None => Value::Null,
+            },
+        }
+    }
+
+    fn generate_scalar(&self, scalar: &str, field_name: &str) -> Value {
+        let n = self.counter.get() + 1;
+        self.counter.set(n);
+        match scalar {
+            "ID" => Value::String(n.to_string()),
+            "Int" => Value::from(n),
+            "Float" => Value::from(n as f64 + 0.5),
+            "Boolean" => Value::Bool(n % 2 == 1),
+            // Strings and custom scalars
+            _ => Value::String(format!("{} {}", field_name, n)),
+        }
+    }
+
+    fn concrete_type<'n>(&self, type_name: &'n str) -> &'n str
+    where 's: 'n {
+        self.schema.possible_types(type_name).first().copied().unwrap_or(type_name)
+    }
+}
+
+/// The `__schema` introspection object, shaped afterwards by the requested selection.
+fn introspect_schema(schema: &GraphQLSchema) -> Value {
+    let root = |name: &Option<String>| name.as_ref().map(|name| json!({ "name": name })).unwrap_or(Value::Null);
+    let built_in_directives = [
+        ("include", "Directs the executor to include this field or fragment only when the `if` argument is true."),
+        ("skip", "Directs the executor to skip this field or fragment when the `if` argument is true."),
+    ]
+        .map(|(name, description)| json!({
+            "name": name,
+            "description": description,
+            "locations": ["FIELD", "FRAGMENT_SPREAD", "INLINE_FRAGMENT"],
+            "args": [{
+                "name": "if",
+                "description": null,
+                "type": { "kind": "NON_NULL", "name": null, "ofType": { "kind": "SCALAR", "name": "Boolean", "ofType": null } },
+                "defaultValue": null,
+                "isDeprecated": false,
+                "deprecationReason": null
+            }],
+            "isRepeatable": false
+        }));
+    let deprecated = json!({
+        "name": "deprecated",
+        "description": "Marks an element of a GraphQL schema as no longer supported.",
+        "locations": ["FIELD_DEFINITION", "ARGUMENT_DEFINITION", "INPUT_FIELD_DEFINITION", "ENUM_VALUE"],
+        "args": [{
+            "name": "reason",
+            "description": null,
+            "type": { "kind": "SCALAR", "name": "String", "ofType": null },
+            "defaultValue": "\"No longer supported\"",
+            "isDeprecated": false,
+            "deprecationReason": null
+        }],
+        "isRepeatable": false
+    });
+
+    let mut directives: Vec<Value> = built_in_directives.into_iter().chain([deprecated]).collect();
+    directives.extend(schema.directives.iter().map(|directive| json!({
+        "name": directive.name,
+        "description": directive.description,
+        "locations": directive.locations.iter().map(|location| location.as_str()).collect::<Vec<_>>(),
+        "args": directive.arguments.iter().map(|argument| introspect_input_value(schema, argument)).collect::<Vec<_>>(),
+        "isRepeatable": directive.repeatable
+    })));
+
+    json!({
+        "description": null,
+        "queryType": { "name": schema.query_type },
+        "mutationType": root(&schema.mutation_type),
+        "subscriptionType": root(&schema.subscription_type),
+        "types": schema.types.values().map(|definition| introspect_type(schema, definition)).collect::<Vec<_>>(),
+        "directives": directives
+    })
+}
+
+fn introspect_type(schema: &GraphQLSchema, definition: &SchemaType) -> Value {
+    let fields = |fields: &[Field<'static, String>]| Value::Array(fields.iter().map(|field| {
+        let (is_deprecated, reason) = deprecation(&field.directives);
+        json!({
+            "name": field.name,
+            "description": field.description,
+            "args": field.arguments.iter().map(|argument| introspect_input_value(schema, argument)).collect::<Vec<_>>(),
+            "type": introspect_type_ref(schema, &field.field_type),
+            "isDeprecated": is_deprecated,
+            "deprecationReason": reason
+        })
+    }).collect());
+    let type_refs = |names: &[String]| Value::Array(names.iter().map(|name| named_type_ref(schema, name)).collect());
+    let possible_types = |name: &str| Value::Array(
+        schema.possible_types(name).into_iter().map(|name| named_type_ref(schema, name)).collect()
+    );
+
+    let mut introspected = json!({
+        "kind": kind(definition),
+        "name": type_name(definition),
+        "description": description(definition),
+        "specifiedByURL": null,
+        "fields": null,
+        "inputFields": null,
+        "interfaces": null,
+        "enumValues": null,
+        "possibleTypes": null,
+        "ofType": null
+    });
+    match definition {
+        TypeDefinition::Object(object) => {
+            introspected["fields"] = fields(&object.fields);
+            introspected["interfaces"] = type_refs(&object.implements_interfaces);
+        }
+        TypeDefinition::Interface(interface) => {
+            introspected["fields"] = fields(&interface.fields);
+            introspected["interfaces"] = type_refs(&interface.implements_interfaces);
+            introspected["possibleTypes"] = possible_types(&interface.name);
+        }
+        TypeDefinition::Union(union) => introspected["possibleTypes"] = possible_types(&union.name),
+        TypeDefinition::Enum(enumeration) => {
+            introspected["enumValues"] = Value::Array(enumeration.values.iter().map(|value| {
+                let (is_deprecated, reason) = deprecation(&value.directives);
+                json!({
+                    "name": value.name,
+                    "description": value.description,
+                    "isDeprecated": is_deprecated,
+                    "deprecationReason": reason
+                })
+            }).collect());
+        }
+        TypeDefinition::InputObject(input) => {
+            introspected["inputFields"] = Value::Array(
+                input.fields.iter().map(|field| introspect_input_value(schema, field)).collect()
+            );
+        }
+        TypeDefinition::Scalar(_) => {}
+    }
+    introspected
+}
+
+fn introspect_input_value(schema: &GraphQLSchema, input: &InputValue<'static, String>) -> Value {
+    json!({
+        "name": input.name,
+        "description": input.description,
+        "type": introspect_type_ref(schema, &input.value_type),
+        "defaultValue": input.default_value.as_ref().map(|value| value.to_string()),
+        "isDeprecated": false,
+        "deprecationReason": null
+    })
+}
+
+fn introspect_type_ref(schema: &GraphQLSchema, field_type: &Type<'static, String>) -> Value {
+    match field_type {
+        Type::NamedType(name) => named_type_ref(schema, name),
+        Type::ListType(inner) => json!({ "kind": "LIST", "name": null, "ofType": introspect_type_ref(schema, inner) }),
+        Type::NonNullType(inner) => json!({ "kind": "NON_NULL", "name": null, "ofType": introspect_type_ref(schema, inner) }),
+    }
+}
+
+fn named_type_ref(schema: &GraphQLSchema, name: &str) -> Value {
+    json!({ "kind": schema.types.get(name).map(kind), "name": name, "ofType": null })
+}
+
+/// Reads `@deprecated(reason: ...)`, defaulting the reason like the specification does.
+fn deprecation(directives: &[graphql_parser::schema::Directive<'static, String>]) -> (bool, Option<String>) {
+    match directives.iter().find(|directive| directive.name == "deprecated") {
+        Some(directive) => {
+            let reason = directive.arguments.iter()
+                .find(|(name, _)| name == "reason")
+                .and_then(|(_, value)| match value {
+                    GqlValue::String(reason) => Some(reason.clone()),
+                    _ => None,
+                })
+                .unwrap_or_else(|| "No longer supported".to_string());
+            (true, Some(reason))
+        }
+        None => (false, None),
+    }
+}
+
+fn kind(definition: &SchemaType) -> &'static str {
+    match definition {
+        TypeDefinition::Scalar(_) => "SCALAR",
+        TypeDefinition::Object(_) => "OBJECT",
+        TypeDefinition::Interface(_) => "INTERFACE",
+        TypeDefinition::Union(_) => "UNION",
+        TypeDefinition::Enum(_) => "ENUM",
+        TypeDefinition::InputObject(_) => "INPUT_OBJECT",
+    }
+}
+
+fn type_name<'t>(definition: &'t TypeDefinition<'static, String>) -> &'t str {
+    match definition {
+        TypeDefinition::Scalar(scalar) => &scalar.name,
+        TypeDefinition::Object(object) => &object.name,
+        TypeDefinition::Interface(interface) => &interface.name,
+        TypeDefinition::Union(union) => &union.name,
+        TypeDefinition::Enum(enumeration) => &enumeration.name,
+        TypeDefinition::InputObject(input) => &input.name,
+    }
+}
+
+fn description(definition: &SchemaType) -> Option<&str> {
+    match definition {
+        TypeDefinition::Scalar(scalar) => scalar.description.as_deref(),
+        TypeDefinition::Object(object) => object.description.as_deref(),
+        TypeDefinition::Interface(interface) => interface.description.as_deref(),
+        TypeDefinition::Union(union) => union.description.as_deref(),
+        TypeDefinition::Enum(enumeration) => enumeration.description.as_deref(),
+        TypeDefinition::InputObject(input) => input.description.as_deref(),
+    }
+}
+
+/// Merges `extend type ...` definitions into the types they extend.
+fn extend(types: &mut BTreeMap<String, SchemaType>, extension: TypeExtension<'static, String>) -> Result<(), String> {
+    let unknown = |name: &str| format!("Cannot extend unknown type \"{}\"", name);
+    match extension {
+        TypeExtension::Object(extension) => match types.get_mut(&extension.name) {
+            Some(TypeDefinition::Object(object)) => {
+                object.fields.extend(extension.fields);
+                object.implements_interfaces.extend(extension.implements_interfaces);
+            }
+            _ => return Err(unknown(&extension.name)),
+        },
+        TypeExtension::Interface(extension) => match types.get_mut(&extension.name) {
+            Some(TypeDefinition::Interface(interface)) => interface.fields.extend(extension.fields),
+            _ => return Err(unknown(&extension.name)),
+        },
+        TypeExtension::Union(extension) => match types.get_mut(&extension.name) {
+            Some(TypeDefinition::Union(union)) => union.types.extend(extension.types),
+            _ => return Err(unknown(&extension.name)),
+        },
+        TypeExtension::Enum(extension) => match types.get_mut(&extension.name) {
+            Some(TypeDefinition::Enum(enumeration)) => enumeration.values.extend(extension.values),
+            _ => return Err(unknown(&extension.name)),
+        },
+        TypeExtension::InputObject(extension) => match types.get_mut(&extension.name) {
+            Some(TypeDefinition::InputObject(input)) => input.fields.extend(extension.fields),
+            _ => return Err(unknown(&extension.name)),
+        },
+        TypeExtension::Scalar(_) => {}
+    }
+    Ok(())
+}
+
+fn field_references<'f>(field: &'f Field<'static, String>) -> impl Iterator<Item = &'f str> {
+    std::iter::once(named_type(&field.field_type))
+        .chain(field.arguments.iter().map(|argument| named_type(&argument.value_type)))
+}
+
+/// The type name under the list and non-null wrappers.
+fn named_type<'t, 'a, T: graphql_parser::query::Text<'a>>(field_type: &'t Type<'a, T>) -> &'t str {
+    match field_type {
+        Type::NamedType(name) => name.as_ref(),
+        Type::ListType(inner) | Type::NonNullType(inner) => named_type(inner),
+    }
+}
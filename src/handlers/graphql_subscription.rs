@@ -0,0 +1,120 @@
+use std::time::Duration;
+use graphql_parser::parse_query;
+use serde_json::{json, Value};
+use crate::handlers::graphql::{extract_arguments, find_operation, mock_key, respond, select_case, with_default_values};
+use crate::handlers::graphql_schema::{self, GraphQLSchema};
+use crate::models::GraphQLRequest;
+
+/// Pause between scripted events that don't set their own `delay`.
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+
+/// What a subscribed operation sends: the scripted events of its mock, then whatever is
+/// pushed to its key until the client completes it (or right away with `complete`).
+///
+/// ```json
+/// "subscription": {
+///   "onPrice": {
+///     "interval": 500,
+///     "events": [
+///       { "data": { "onPrice": { "value": 1 } } },
+///       { "data": { "onPrice": { "value": 2 } }, "delay": 2000 },
+///       { "errors": [{ "message": "Feed lost" }] }
+///     ],
+///     "complete": false
+///   }
+/// }
+/// ```
+///
+/// Queries and mutations sent over the socket answer once and complete.
+#[derive(Debug)]
+pub struct SubscriptionPlan {
+    /// The operation key that ad-hoc events are pushed to.
+    pub key: String,
+    pub events: Vec<SubscriptionEvent>,
+    pub complete: bool,
+}
+
+#[derive(Debug)]
+pub struct SubscriptionEvent {
+    /// Wait before sending the event: its own `delay`, else the interval (nothing for the first one).
+    pub delay: Duration,
+    /// The event, mocked like an operation (`data`, `errors`, `extensions`).
+    pub mock: Value,
+}
+
+/// Plans the events of an operation received over a subscription transport.
+///
+/// The error is the GraphQL errors array to send back instead.
+pub fn plan_subscription(request: &GraphQLRequest, json_data: &str, schema: Option<&GraphQLSchema>) -> Result<SubscriptionPlan, Value> {
+    let mocks: Value = serde_json::from_str(json_data).unwrap_or(Value::Null);
+    let document = parse_query::<&str>(&request.query)
+        .map_err(|e| json!([{ "message": format!("Syntax Error: {}", e.to_string().trim()) }]))?;
+    let operation = find_operation(&document, request.operation_name.as_deref())
+        .map_err(|message| json!([{ "message": message }]))?;
+    let variables = with_default_values(request.variables.clone().unwrap_or_default(), &operation);
+
+    if let Some(schema) = schema {
+        let errors = graphql_schema::validate(schema, &document, &operation, &variables);
+        if !errors.is_empty() {
+            return Err(Value::Array(errors));
+        }
+    }
+
+    let key = mock_key(&operation).unwrap_or_default().to_string();
+    let operation_mock = mocks.get(operation.kind).and_then(|section| section.get(&key));
+    let Some(operation_mock) = operation_mock else {
+        // A schema can generate everything, other mocks must exist
+        return match schema {
+            Some(_) if operation.kind == "subscription" => Ok(SubscriptionPlan { key, events: Vec::new(), complete: false }),
+            Some(_) => Ok(SubscriptionPlan { key, events: vec![SubscriptionEvent { delay: Duration::ZERO, mock: Value::Null }], complete: true }),
+            None => Err(json!([{ "message": format!("No mock for {} \"{}\".", operation.kind, key) }])),
+        };
+    };
+
+    if operation.kind != "subscription" {
+        let delay = operation_mock.get("delay").and_then(Value::as_u64).unwrap_or_default();
+        let event = SubscriptionEvent { delay: Duration::from_millis(delay), mock: operation_mock.clone() };
+        return Ok(SubscriptionPlan { key, events: vec![event], complete: true });
+    }
+
+    let mock = select_case(operation_mock, &extract_arguments(&operation, &variables));
+    let interval = mock.get("interval").and_then(Value::as_u64).unwrap_or(DEFAULT_INTERVAL_MS);
+    let events = match mock.get("events").and_then(Value::as_array) {
+        Some(events) => events.clone(),
+        // A plain operation mock is a single event
+        None if mock.get("data").is_some() || mock.get("errors").is_some() => vec![mock.clone()],
+        None => Vec::new(),
+    };
+    let events = events.into_iter()
+        .enumerate()
+        .map(|(index, event)| {
+            let default = if index == 0 { 0 } else { interval };
+            let delay = event.get("delay").and_then(Value::as_u64).unwrap_or(default);
+            SubscriptionEvent { delay: Duration::from_millis(delay), mock: event }
+        })
+        .collect();
+
+    Ok(SubscriptionPlan {
+        key,
+        events,
+        complete: mock.get("complete").and_then(Value::as_bool).unwrap_or(false),
+    })
+}
+
+/// Renders an event for a subscriber: shaped after its selection set, with its arguments
+/// filled into the event's templates. A `null` event is entirely generated from the schema.
+pub fn render_event(request: &GraphQLRequest, event: &Value, schema: Option<&GraphQLSchema>) -> Value {
+    let Ok(document) = parse_query::<&str>(&request.query) else {
+        return Value::Null;
+    };
+    let Ok(operation) = find_operation(&document, request.operation_name.as_deref()) else {
+        return Value::Null;
+    };
+    let variables = with_default_values(request.variables.clone().unwrap_or_default(), &operation);
+
+    let response = match schema {
+        Some(schema) => graphql_schema::respond(schema, &document, &operation, &variables, Some(event).filter(|event| !event.is_null())),
+        None => respond(&document, &operation, &variables, event),
+    };
+    response.body
+}
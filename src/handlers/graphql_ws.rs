@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use futures::{SinkExt, StreamExt};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
+use warp::ws::{Message, WebSocket};
+use crate::handlers::graphql_schema::GraphQLSchema;
+use crate::handlers::graphql_subscription::{plan_subscription, render_event};
+use crate::middlewares::subscriptions::{subscription_key, SubscriptionHub};
+use crate::models::{Endpoint, GraphQLRequest};
+
+/// The WebSocket sub-protocol spoken, as negotiated in `Sec-WebSocket-Protocol`.
+pub const GRAPHQL_TRANSPORT_WS: &str = "graphql-transport-ws";
+const CONNECTION_INIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Client messages of the `graphql-transport-ws` protocol.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    ConnectionInit {},
+    Ping {},
+    Pong {},
+    Subscribe { id: String, payload: GraphQLRequest },
+    Complete { id: String },
+}
+
+/// Runs a `graphql-transport-ws` connection on the endpoint at `path` until either side closes it.
+///
+/// Each subscription runs in its own task; they all write to the socket through one queue.
+pub async fn serve_graphql_ws(socket: WebSocket, path: String, endpoint: Endpoint, hub: SubscriptionHub) {
+    let (mut sink, mut stream) = socket.split();
+    let (outgoing, mut queue) = mpsc::unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = queue.recv().await {
+            let closing = message.is_close();
+            if sink.send(message).await.is_err() || closing {
+                break;
+            }
+        }
+    });
+
+    let mut acknowledged = false;
+    let mut operations: HashMap<String, JoinHandle<()>> = HashMap::new();
+    let init_deadline = sleep(CONNECTION_INIT_TIMEOUT);
+    tokio::pin!(init_deadline);
+
+    loop {
+        let received = tokio::select! {
+            received = stream.next() => received,
+            _ = &mut init_deadline, if !acknowledged => {
+                close(&outgoing, 4408, "Connection initialisation timeout");
+                break;
+            }
+        };
+        let Some(Ok(received)) = received else {
+            break;
+        };
+        if received.is_close() {
+            break;
+        }
+        // Protocol-level pings are answered by the WebSocket layer
+        let Ok(text) = received.to_str() else {
+            continue;
+        };
+        let Ok(message) = serde_json::from_str::<ClientMessage>(text) else {
+            close(&outgoing, 4400, "Invalid message received");
+            break;
+        };
+
+        match message {
+            ClientMessage::ConnectionInit {} => {
+                if acknowledged {
+                    close(&outgoing, 4429, "Too many initialisation requests");
+                    break;
+                }
+                acknowledged = true;
+                send(&outgoing, json!({ "type": "connection_ack" }));
+            }
+            ClientMessage::Ping {} => send(&outgoing, json!({ "type": "pong" })),
+            ClientMessage::Pong {} => {}
+            ClientMessage::Subscribe { id, payload } => {
+                if !acknowledged {
+                    close(&outgoing, 4401, "Unauthorized");
+                    break;
+                }
+                if operations.get(&id).is_some_and(|task| !task.is_finished()) {
+                    close(&outgoing, 4409, &format!("Subscriber for {} already exists", id));
+                    break;
+                }
+                let task = subscribe(id.clone(), payload, &path, &endpoint, hub.clone(), outgoing.clone()).await;
+                operations.insert(id, task);
+            }
+            ClientMessage::Complete { id } => {
+                if let Some(task) = operations.remove(&id) {
+                    task.abort();
+                }
+            }
+        }
+    }
+
+    for task in operations.values() {
+        task.abort();
+    }
+    drop(outgoing);
+    let _ = writer.await;
+}
+
+/// Starts sending the events of an operation: its scripted events, interleaved with the
+/// events pushed to its key on the endpoint.
+async fn subscribe(
+    id: String,
+    request: GraphQLRequest,
+    path: &str,
+    endpoint: &Endpoint,
+    hub: SubscriptionHub,
+    outgoing: UnboundedSender<Message>,
+) -> JoinHandle<()> {
+    // Mocks are read on every subscription so that re-registering applies right away
+    let json_data = tokio::fs::read_to_string(&endpoint.file).await.unwrap_or_default();
+    let schema = match &endpoint.graphql_schema {
+        Some(schema_file) => GraphQLSchema::load(schema_file).await,
+        None => None,
+    };
+    let path = path.to_string();
+
+    tokio::spawn(async move {
+        let plan = match plan_subscription(&request, &json_data, schema.as_deref()) {
+            Ok(plan) => plan,
+            Err(errors) => {
+                send(&outgoing, json!({ "id": id, "type": "error", "payload": errors }));
+                return;
+            }
+        };
+        let key = subscription_key(&path, &plan.key);
+        info!("📡 Subscription {} started for {}", id, key);
+
+        let next = |event: &Value| {
+            let payload = render_event(&request, event, schema.as_deref());
+            send(&outgoing, json!({ "id": id, "type": "next", "payload": payload }));
+        };
+        let mut pushed = hub.subscribe(&key).await;
+        let mut events = plan.events.into_iter().peekable();
+        let timer = sleep(events.peek().map_or(Duration::ZERO, |event| event.delay));
+        tokio::pin!(timer);
+
+        while !(plan.complete && events.peek().is_none()) {
+            tokio::select! {
+                _ = &mut timer, if events.peek().is_some() => {
+                    if let Some(event) = events.next() {
+                        next(&event.mock);
+                    }
+                    if let Some(following) = events.peek() {
+                        timer.as_mut().reset(Instant::now() + following.delay);
+                    }
+                }
+                received = pushed.recv() => match received {
+                    Ok(event) => next(&event),
+                    Err(RecvError::Lagged(missed)) => warn!("Subscription {} missed {} pushed events", id, missed),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+
+        send(&outgoing, json!({ "id": id, "type": "complete" }));
+    })
+}
+
+fn send(outgoing: &UnboundedSender<Message>, message: Value) {
+    let _ = outgoing.send(Message::text(message.to_string()));
+}
+
+fn close(outgoing: &UnboundedSender<Message>, code: u16, reason: &str) {
+    warn!("Closing GraphQL WebSocket: {} {}", code, reason);
+    let _ = outgoing.send(Message::close_with(code, reason.to_string()));
+}
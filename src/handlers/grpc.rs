@@ -1,57 +1,110 @@
 use std::sync::Arc;
-use warp::http::{HeaderValue, Response, StatusCode};
-use warp::{reply, Reply};
+use warp::http::{HeaderName, HeaderValue, Response, StatusCode};
+use warp::Reply;
 use warp::http::header::CONTENT_TYPE;
+use std::collections::HashMap;
+use serde_json::Value;
+use crate::middlewares::dynamic_vars::{flatten_json, replace_variables};
 use crate::middlewares::grpc_registry::GrpcRegistry;
-use crate::models::grpc::GrpcMockRequest;
+use crate::models::InvalidGrpcRequest;
+use crate::models::grpc::{GrpcCode, GrpcMockRequest, GrpcMockResponse};
 
 async fn process_grpc_mock(
     req: &GrpcMockRequest,
     registry: &GrpcRegistry,
-) -> Option<(u16, String)> {
-    let mock = registry.get_mock(&req.service, &req.method).await?;
+) -> Option<Response<String>> {
+    let mock = render_mock(registry.get_mock(&req.service, &req.method, &req.input).await?, &req.input);
 
     if let Some(delay) = mock.delay_ms {
         tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
     }
 
-    let status = mock.status.unwrap_or(200);
-    let body = serde_json::to_string(&mock.output).ok()?;
+    json_response(&mock)
+}
 
-    Some((status, body))
+/// Fills the `{{request.field}}` placeholders of a mock with the request message, for
+/// mocks `with_dynamic_vars`.
+pub(crate) fn render_mock(mut mock: GrpcMockResponse, input: &Value) -> GrpcMockResponse {
+    if !mock.with_dynamic_vars {
+        return mock;
+    }
+    let params = flatten_json("request", input);
+    mock.output = render_value(&mock.output, &params);
+    for message in &mut mock.stream {
+        message.output = render_value(&message.output, &params);
+    }
+    mock.message = mock.message.map(|message| replace_variables(&message, &params));
+    mock
 }
-pub async fn handle_grpc(
-    body_str: &str,
-    registry: Arc<GrpcRegistry>,
-) -> Option<Response<String>> {
-    let req = serde_json::from_str::<GrpcMockRequest>(body_str).ok()?;
-    let (status, body) = process_grpc_mock(&req, &registry).await?;
 
-    let response = Response::builder()
-        .status(StatusCode::from_u16(status).unwrap_or(StatusCode::OK))
+/// Replaces the placeholders in the strings of a JSON value.
+pub(crate) fn render_value(value: &Value, params: &HashMap<String, String>) -> Value {
+    match value {
+        Value::String(text) => Value::String(replace_variables(text, params)),
+        Value::Array(items) => Value::Array(items.iter().map(|item| render_value(item, params)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(key, nested)| (key.clone(), render_value(nested, params))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// The JSON form of a call: the output, or for a mock failing with a `code` its status as
+/// `{ "code", "message", "details" }`, answered with the HTTP status of the code unless
+/// the mock sets one. Metadata and trailers come as `grpc-metadata-*` and `grpc-trailer-*` headers.
+pub(crate) fn json_response(mock: &GrpcMockResponse) -> Option<Response<String>> {
+    let status = mock.grpc_status();
+    // Mocks with only an HTTP status keep answering their output as is
+    let (http_status, body) = if mock.code.is_none_or(|code| code == GrpcCode::Ok) {
+        // A streamed response comes as the array of its messages
+        let body = match mock.stream.is_empty() {
+            true => serde_json::to_string(&mock.output).ok()?,
+            false => serde_json::to_string(&mock.stream.iter().map(|message| &message.output).collect::<Vec<_>>()).ok()?,
+        };
+        (mock.status.unwrap_or(200), body)
+    } else {
+        let body = serde_json::json!({
+            "code": status.code as i32,
+            "message": status.message,
+            "details": status.details,
+        });
+        (mock.status.unwrap_or_else(|| status.code.to_http_status()), body.to_string())
+    };
+
+    let mut response = Response::builder()
+        .status(StatusCode::from_u16(http_status).unwrap_or(StatusCode::OK))
         .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
-        .body(body.into())
-        .ok()?;
+        .header("grpc-status", status.code as i32);
+    for (prefix, entries) in [("grpc-metadata-", &mock.metadata), ("grpc-trailer-", &mock.trailers)] {
+        for (key, value) in entries {
+            if let Ok(name) = HeaderName::from_bytes(format!("{}{}", prefix, key).as_bytes())
+                && let Ok(value) = HeaderValue::from_str(value) {
+                response = response.header(name, value);
+            }
+        }
+    }
+    response.body(body).ok()
+}
 
-    Some(response)
+/// Answers a JSON gRPC call, `{ "service", "rpc", "request" }`, from the registered mocks.
+pub async fn handle_grpc(
+    body_str: &str,
+    registry: Arc<GrpcRegistry>,
+) -> Result<Response<String>, warp::Rejection> {
+    let req = serde_json::from_str::<GrpcMockRequest>(body_str)
+        .map_err(|e| warp::reject::custom(InvalidGrpcRequest(e.to_string())))?;
+    Ok(process_grpc_mock(&req, &registry).await.unwrap_or_else(mock_not_found))
 }
 
 pub async fn grpc_handler(
     req: GrpcMockRequest,
     registry: Arc<GrpcRegistry>,
 ) -> Result<Box<dyn Reply>, warp::Rejection> {
-    match process_grpc_mock(&req, &registry).await {
-        Some((status, body)) => {
-            let json_value: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
-            let json = reply::json(&json_value);
-            Ok(Box::new(reply::with_status(json, StatusCode::from_u16(status).unwrap())))
-        }
-        None => {
-            let not_found = reply::with_status(
-                reply::json(&serde_json::json!({ "error": "Mock not found" })),
-                StatusCode::NOT_FOUND,
-            );
-            Ok(Box::new(not_found))
-        }
-    }
-}
\ No newline at end of file
+    let response = process_grpc_mock(&req, &registry).await.unwrap_or_else(mock_not_found);
+    Ok(Box::new(response))
+}
+
+fn mock_not_found() -> Response<String> {
+    let mut response = Response::new(serde_json::json!({ "error": "Mock not found" }).to_string());
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
@@ -0,0 +1,129 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use hyper::{Body, HeaderMap, Response};
+use prost::Message;
+use prost_reflect::{DescriptorPool, FileDescriptor};
+use crate::handlers::grpc_server::{encode_frame, grpc_response, status_headers, FrameReader};
+use crate::middlewares::grpc_registry::GrpcRegistry;
+use crate::models::grpc::{GrpcCode, GrpcStatus};
+use crate::models::grpc_reflection::{
+    ErrorResponse, ExtensionNumberResponse, FileDescriptorResponse, ListServiceResponse, MessageRequest,
+    MessageResponse, ServerReflectionRequest, ServerReflectionResponse, ServiceResponse,
+};
+
+/// Both versions of the reflection service, as tools still use either.
+const REFLECTION_SERVICES: [&str; 2] = ["grpc.reflection.v1.ServerReflection", "grpc.reflection.v1alpha.ServerReflection"];
+
+pub(crate) fn is_reflection_path(path: &str) -> bool {
+    path.trim_start_matches('/')
+        .split_once('/')
+        .is_some_and(|(service, method)| REFLECTION_SERVICES.contains(&service) && method == "ServerReflectionInfo")
+}
+
+/// Answers a `ServerReflectionInfo` stream from the descriptors loaded in the registry,
+/// one response per request, so that tools can discover the mocked services.
+pub(crate) fn serve_reflection(body: Body, registry: Arc<GrpcRegistry>) -> Response<Body> {
+    let (mut sender, response_body) = Body::channel();
+    tokio::spawn(async move {
+        let mut requests = FrameReader::new(body);
+        let mut status = GrpcStatus::new(GrpcCode::Ok, "");
+        while let Some(frame) = requests.next().await {
+            let request = match frame.and_then(|frame| ServerReflectionRequest::decode(frame)
+                .map_err(|e| GrpcStatus::new(GrpcCode::Internal, format!("Could not decode the reflection request: {}", e)))) {
+                Ok(request) => request,
+                Err(error) => {
+                    status = error;
+                    break;
+                }
+            };
+
+            // Descriptors uploaded meanwhile show up right away
+            let response = reflect(&registry.descriptors().await, request);
+            if sender.send_data(encode_frame(&response.encode_to_vec())).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender.send_trailers(status_headers(&status)).await;
+    });
+    grpc_response(response_body, HeaderMap::new())
+}
+
+fn reflect(pool: &DescriptorPool, request: ServerReflectionRequest) -> ServerReflectionResponse {
+    let message_response = match &request.message_request {
+        Some(MessageRequest::ListServices(_)) => Ok(MessageResponse::ListServicesResponse(ListServiceResponse {
+            service: pool.services().map(|service| ServiceResponse { name: service.full_name().to_string() }).collect(),
+        })),
+        Some(MessageRequest::FileByFilename(name)) => pool.get_file_by_name(name)
+            .ok_or_else(|| format!("File not found: {}", name))
+            .map(file_response),
+        Some(MessageRequest::FileContainingSymbol(symbol)) => file_containing_symbol(pool, symbol)
+            .ok_or_else(|| format!("Symbol not found: {}", symbol))
+            .map(file_response),
+        Some(MessageRequest::FileContainingExtension(extension)) => pool.get_message_by_name(&extension.containing_type)
+            .and_then(|message| message.extensions()
+                .find(|candidate| candidate.number() as i32 == extension.extension_number))
+            .ok_or_else(|| format!("Extension not found: {}({})", extension.containing_type, extension.extension_number))
+            .map(|extension| file_response(extension.parent_file())),
+        Some(MessageRequest::AllExtensionNumbersOfType(name)) => pool.get_message_by_name(name)
+            .ok_or_else(|| format!("Type not found: {}", name))
+            .map(|message| MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                base_type_name: message.full_name().to_string(),
+                extension_number: message.extensions().map(|extension| extension.number() as i32).collect(),
+            })),
+        None => Err("Missing message_request".to_string()),
+    };
+
+    let message_response = message_response.unwrap_or_else(|message| {
+        let code = match request.message_request {
+            Some(_) => GrpcCode::NotFound,
+            None => GrpcCode::InvalidArgument,
+        };
+        MessageResponse::ErrorResponse(ErrorResponse { error_code: code as i32, error_message: message })
+    });
+    ServerReflectionResponse {
+        valid_host: request.host.clone(),
+        original_request: Some(request),
+        message_response: Some(message_response),
+    }
+}
+
+/// The file defining a symbol: a service, method, message, field, enum or extension.
+fn file_containing_symbol(pool: &DescriptorPool, symbol: &str) -> Option<FileDescriptor> {
+    let symbol = symbol.trim_start_matches('.');
+    if let Some(service) = pool.get_service_by_name(symbol) {
+        return Some(service.parent_file());
+    }
+    if let Some(message) = pool.get_message_by_name(symbol) {
+        return Some(message.parent_file());
+    }
+    if let Some(enumeration) = pool.get_enum_by_name(symbol) {
+        return Some(enumeration.parent_file());
+    }
+    if let Some(extension) = pool.get_extension_by_name(symbol) {
+        return Some(extension.parent_file());
+    }
+
+    // Members are found through the type declaring them
+    let (parent, name) = symbol.rsplit_once('.')?;
+    if let Some(service) = pool.get_service_by_name(parent)
+        && service.methods().any(|method| method.name() == name) {
+        return Some(service.parent_file());
+    }
+    pool.get_message_by_name(parent)
+        .filter(|message| message.get_field_by_name(name).is_some())
+        .map(|message| message.parent_file())
+}
+
+/// A file along with everything it imports, which clients need to make sense of it.
+fn file_response(file: FileDescriptor) -> MessageResponse {
+    let mut seen = HashSet::new();
+    let mut pending = vec![file];
+    let mut files = Vec::new();
+    while let Some(file) = pending.pop() {
+        if seen.insert(file.name().to_string()) {
+            files.push(file.encode_to_vec());
+            pending.extend(file.dependencies());
+        }
+    }
+    MessageResponse::FileDescriptorResponse(FileDescriptorResponse { file_descriptor_proto: files })
+}
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use bytes::{BufMut, Bytes, BytesMut};
+use hyper::body::HttpBody;
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use log::info;
+use prost::Message;
+use prost_reflect::{DescriptorPool, DeserializeOptions, DynamicMessage, MethodDescriptor, SerializeOptions};
+use serde_json::Value;
+use tokio::net::TcpListener;
+use crate::handlers::grpc::{render_mock, render_value};
+use crate::handlers::grpc_reflection::{is_reflection_path, serve_reflection};
+use crate::handlers::grpc_transcoding::serve_transcoded;
+use crate::handlers::grpc_web::{is_grpc_web, preflight_response, serve_grpc_web};
+use crate::middlewares::dynamic_vars::flatten_json;
+use crate::middlewares::grpc_registry::GrpcRegistry;
+use crate::models::grpc::{GrpcCode, GrpcMockResponse, GrpcStatus};
+
+/// Largest request message accepted, gRPC's usual default. Larger ones fail the call with
+/// `RESOURCE_EXHAUSTED` before they are buffered.
+pub(crate) const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Serves the registered gRPC mocks to real gRPC clients over HTTP/2, to browsers over
+/// gRPC-Web, and as REST routes from the `google.api.http` options of the methods.
+///
+/// Calls are routed by their `/package.Service/Method` path to the methods of the loaded
+/// protobuf files; requests are decoded and the JSON mocks encoded with their descriptors.
+pub async fn serve_grpc(listener: TcpListener, registry: Arc<GrpcRegistry>) -> hyper::Result<()> {
+    let incoming = AddrIncoming::from_listener(listener)?;
+    let make_service = make_service_fn(move |_| {
+        let registry = registry.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle_grpc_call(request, registry.clone())))
+        }
+    });
+
+    Server::builder(incoming)
+        .serve(make_service)
+        .await
+}
+
+/// Answers a single gRPC call: the mocked messages, or the status the call fails with.
+pub async fn handle_grpc_call(request: Request<Body>, registry: Arc<GrpcRegistry>) -> Result<Response<Body>, Infallible> {
+    if request.method() == Method::OPTIONS {
+        return Ok(preflight_response(&request));
+    }
+
+    let content_type = request.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if is_grpc_web(content_type) {
+        return Ok(serve_grpc_web(request, registry).await);
+    }
+    if !content_type.starts_with("application/grpc") {
+        let response = serve_transcoded(request, registry).await.unwrap_or_else(|| {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            response
+        });
+        return Ok(response);
+    }
+
+    if is_reflection_path(request.uri().path()) {
+        return Ok(serve_reflection(request.into_body(), registry));
+    }
+
+    Ok(match call(request, &registry).await {
+        Ok(response) => response,
+        Err(status) => status_response(&status),
+    })
+}
+
+pub(crate) async fn call(request: Request<Body>, registry: &GrpcRegistry) -> Result<Response<Body>, GrpcStatus> {
+    let path = request.uri().path().to_string();
+    let method = find_method(&path, registry).await?;
+    let mut requests = FrameReader::new(request.into_body());
+
+    // The first message picks the mock; client-streaming calls may not send any
+    let first = match requests.next().await {
+        Some(frame) => Some(decode_message(&method, frame?)?),
+        None if method.is_client_streaming() => None,
+        None => return Err(GrpcStatus::new(GrpcCode::Internal, "Missing request message")),
+    };
+    let input = first.as_ref().map(message_json).transpose()?.unwrap_or(Value::Null);
+
+    let mock = registry.find_mock(&method, &input).await
+        .ok_or_else(|| GrpcStatus::new(GrpcCode::Unimplemented, format!("No mock for {}", path)))?;
+    let mock = render_mock(mock, &input);
+    info!("📡 gRPC call {}", path);
+
+    let status = mock.grpc_status();
+    let metadata = metadata_headers(&mock.metadata)?;
+    let mut trailers = metadata_headers(&mock.trailers)?;
+    trailers.extend(status_headers(&status));
+    if !status.details.is_empty() {
+        trailers.insert("grpc-status-details-bin", status_details(&status, &registry.descriptors().await)?);
+    }
+
+    if mock.echo {
+        return Ok(echo_response(first, requests, method, mock, metadata, trailers));
+    }
+
+    // Client-streaming calls answer once the client is done, or after `respond_after` messages
+    let expected = match method.is_client_streaming() {
+        true => mock.respond_after.unwrap_or(usize::MAX),
+        false => 1,
+    };
+    let mut received = usize::from(first.is_some());
+    while received < expected
+        && let Some(frame) = requests.next().await {
+        decode_message(&method, frame?)?;
+        received += 1;
+    }
+
+    if let Some(delay) = mock.delay_ms {
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
+
+    // Streams are sent whatever the status, which fails the call once they are done
+    let messages = if method.is_server_streaming() && !mock.stream.is_empty() {
+        mock.stream.iter()
+            .map(|message| Ok((encode_message(&method, &message.output)?, message.delay_ms)))
+            .collect::<Result<Vec<_>, GrpcStatus>>()?
+    } else if status.code == GrpcCode::Ok {
+        vec![(encode_message(&method, &mock.output)?, None)]
+    } else {
+        Vec::new()
+    };
+
+    // Failed calls without messages send everything in the headers ("trailers-only")
+    if messages.is_empty() {
+        let mut headers = metadata;
+        headers.extend(trailers);
+        return Ok(grpc_response(Body::empty(), headers));
+    }
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        // The call stays open for the client until it is answered
+        let _requests = requests;
+        for (frame, delay) in messages {
+            if let Some(delay) = delay {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            }
+            if sender.send_data(frame).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender.send_trailers(trailers).await;
+    });
+    Ok(grpc_response(body, metadata))
+}
+
+/// Answers every inbound message as it arrives, with the message itself and the fields
+/// of the mock's output set over it; the call ends when the client is done sending.
+fn echo_response(first: Option<DynamicMessage>, mut requests: FrameReader, method: MethodDescriptor, mock: GrpcMockResponse, metadata: HeaderMap, trailers: HeaderMap) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut pending = first.map(Ok);
+        loop {
+            let message = match pending.take() {
+                Some(message) => message,
+                None => match requests.next().await {
+                    Some(frame) => frame.and_then(|frame| decode_message(&method, frame)),
+                    None => break,
+                },
+            };
+            let reply = message
+                .and_then(|input| {
+                    let overrides = echo_overrides(&mock, &message_json(&input)?);
+                    Ok(echo_message(&protobuf_json(&input)?, &overrides))
+                })
+                .and_then(|output| encode_message(&method, &output));
+            let frame = match reply {
+                Ok(frame) => frame,
+                Err(status) => {
+                    let _ = sender.send_trailers(status_headers(&status)).await;
+                    return;
+                }
+            };
+            if let Some(delay) = mock.delay_ms {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            }
+            if sender.send_data(frame).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender.send_trailers(trailers).await;
+    });
+    grpc_response(body, metadata)
+}
+
+/// The fields an echo sets over each message, filled with that message when templated.
+fn echo_overrides(mock: &GrpcMockResponse, input: &Value) -> Value {
+    match mock.with_dynamic_vars {
+        true => render_value(&mock.output, &flatten_json("request", input)),
+        false => mock.output.clone(),
+    }
+}
+
+fn decode_message(method: &MethodDescriptor, frame: Bytes) -> Result<DynamicMessage, GrpcStatus> {
+    DynamicMessage::decode(method.input(), frame)
+        .map_err(|e| GrpcStatus::new(GrpcCode::Internal, format!("Could not decode {}: {}", method.input().full_name(), e)))
+}
+
+/// Encodes the JSON of a mocked message as the method's response message.
+fn encode_message(method: &MethodDescriptor, output: &Value) -> Result<Bytes, GrpcStatus> {
+    let options = DeserializeOptions::new().deny_unknown_fields(false);
+    let output = DynamicMessage::deserialize_with_options(method.output(), output, &options)
+        .map_err(|e| GrpcStatus::new(GrpcCode::Internal, format!("The mock is not a valid {}: {}", method.output().full_name(), e)))?;
+    Ok(encode_frame(&output.encode_to_vec()))
+}
+
+/// The JSON of an inbound message as matchers and templates read it: fields under their
+/// `.proto` names, as in the JSON calls of `POST /grpc`, and unset ones with their default.
+pub(crate) fn message_json(message: &DynamicMessage) -> Result<Value, GrpcStatus> {
+    let options = SerializeOptions::new().use_proto_field_name(true).skip_default_fields(false);
+    message.serialize_with_options(serde_json::value::Serializer, &options)
+        .map_err(|e| GrpcStatus::new(GrpcCode::Internal, format!("Could not read the message: {}", e)))
+}
+
+/// The canonical protobuf JSON of a message, as HTTP/JSON clients get it.
+pub(crate) fn protobuf_json(message: &DynamicMessage) -> Result<Value, GrpcStatus> {
+    serde_json::to_value(message)
+        .map_err(|e| GrpcStatus::new(GrpcCode::Internal, format!("Could not read the message: {}", e)))
+}
+
+/// An inbound message, with the fields of `overrides` set over it.
+fn echo_message(input: &Value, overrides: &Value) -> Value {
+    let mut echo = input.clone();
+    if let (Value::Object(echo), Value::Object(overrides)) = (&mut echo, overrides) {
+        echo.extend(overrides.clone());
+    }
+    echo
+}
+
+/// Finds the method called by a `/package.Service/Method` path.
+async fn find_method(path: &str, registry: &GrpcRegistry) -> Result<MethodDescriptor, GrpcStatus> {
+    let (service, method) = path.trim_start_matches('/')
+        .split_once('/')
+        .ok_or_else(|| GrpcStatus::new(GrpcCode::Unimplemented, format!("Malformed method path {}", path)))?;
+    registry.find_method(service, method).await
+        .ok_or_else(|| GrpcStatus::new(GrpcCode::Unimplemented, format!("Method not found: {}/{}", service, method)))
+}
+
+/// Frames a message: a compression flag, its length as four big-endian bytes, then the message.
+pub fn encode_frame(message: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(5 + message.len());
+    frame.put_u8(0);
+    frame.put_u32(message.len() as u32);
+    frame.put_slice(message);
+    frame.freeze()
+}
+
+/// Splits a body into the messages of its frames.
+pub fn decode_frames(body: &Bytes) -> Result<Vec<Bytes>, GrpcStatus> {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while offset < body.len() {
+        let Some(header) = body.get(offset..offset + 5) else {
+            return Err(GrpcStatus::new(GrpcCode::Internal, "Truncated message frame"));
+        };
+        let length = frame_length(header)?;
+        let start = offset + 5;
+        if body.len() < start + length {
+            return Err(GrpcStatus::new(GrpcCode::Internal, "Truncated message frame"));
+        }
+        messages.push(body.slice(start..start + length));
+        offset = start + length;
+    }
+    Ok(messages)
+}
+
+/// The length of the message a frame header announces, refusing compressed and oversized ones.
+fn frame_length(header: &[u8]) -> Result<usize, GrpcStatus> {
+    if header[0] != 0 {
+        return Err(GrpcStatus::new(GrpcCode::Unimplemented, "Compressed messages are not supported"));
+    }
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(GrpcStatus::new(GrpcCode::ResourceExhausted, format!(
+            "Received message larger than max ({} vs. {})", length, MAX_MESSAGE_SIZE
+        )));
+    }
+    Ok(length)
+}
+
+/// Reads the messages of a request body as they arrive, for calls that stream them.
+pub(crate) struct FrameReader {
+    body: Body,
+    buffer: BytesMut,
+}
+
+impl FrameReader {
+    pub(crate) fn new(body: Body) -> Self {
+        Self { body, buffer: BytesMut::new() }
+    }
+
+    /// The next message, or `None` once the client is done sending.
+    pub(crate) async fn next(&mut self) -> Option<Result<Bytes, GrpcStatus>> {
+        loop {
+            if self.buffer.len() >= 5 {
+                let length = match frame_length(&self.buffer[..5]) {
+                    Ok(length) => length,
+                    Err(status) => return Some(Err(status)),
+                };
+                if self.buffer.len() >= 5 + length {
+                    let mut frame = self.buffer.split_to(5 + length);
+                    return Some(Ok(frame.split_off(5).freeze()));
+                }
+            }
+
+            match self.body.data().await {
+                Some(Ok(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some(Err(GrpcStatus::new(GrpcCode::Internal, format!("Could not read the request: {}", e)))),
+                None if self.buffer.is_empty() => return None,
+                None => return Some(Err(GrpcStatus::new(GrpcCode::Internal, "Truncated message frame"))),
+            }
+        }
+    }
+}
+
+/// A failed call, answered with its status in the headers and no message ("trailers-only").
+pub(crate) fn status_response(status: &GrpcStatus) -> Response<Body> {
+    grpc_response(Body::empty(), status_headers(status))
+}
+
+pub(crate) fn grpc_response(body: Body, headers: HeaderMap) -> Response<Body> {
+    let mut response = Response::new(body);
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    response.headers_mut().extend(headers);
+    response
+}
+
+pub(crate) fn status_headers(status: &GrpcStatus) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("grpc-status", HeaderValue::from(status.code as i32));
+    if !status.message.is_empty()
+        && let Ok(message) = HeaderValue::from_str(&urlencoding::encode(&status.message)) {
+        headers.insert("grpc-message", message);
+    }
+    headers
+}
+
+/// Custom metadata of a mock. Keys ending in `-bin` carry binary values, sent in base64.
+fn metadata_headers(entries: &BTreeMap<String, String>) -> Result<HeaderMap, GrpcStatus> {
+    let mut headers = HeaderMap::new();
+    for (key, value) in entries {
+        let name = HeaderName::from_bytes(key.to_ascii_lowercase().as_bytes())
+            .map_err(|_| GrpcStatus::new(GrpcCode::Internal, format!("Invalid metadata key \"{}\"", key)))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| GrpcStatus::new(GrpcCode::Internal, format!("Invalid metadata value for \"{}\"", key)))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+/// The error details of a status, as the `google.rpc.Status` clients decode from the
+/// `grpc-status-details-bin` trailer. Each detail is the JSON of a `google.protobuf.Any`.
+fn status_details(status: &GrpcStatus, pool: &DescriptorPool) -> Result<HeaderValue, GrpcStatus> {
+    let descriptor = pool.get_message_by_name("google.rpc.Status")
+        .ok_or_else(|| GrpcStatus::new(GrpcCode::Internal, "google.rpc.Status is not loaded"))?;
+    let json = serde_json::json!({
+        "code": status.code as i32,
+        "message": status.message,
+        "details": status.details,
+    });
+    let message = DynamicMessage::deserialize(descriptor, &json)
+        .map_err(|e| GrpcStatus::new(GrpcCode::Internal, format!("Invalid error details: {}", e)))?;
+    HeaderValue::from_str(&STANDARD_NO_PAD.encode(message.encode_to_vec()))
+        .map_err(|e| GrpcStatus::new(GrpcCode::Internal, e.to_string()))
+}
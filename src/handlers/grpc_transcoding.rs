@@ -0,0 +1,348 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{info, warn};
+use prost_reflect::{DescriptorPool, DeserializeOptions, DynamicMessage, FieldDescriptor, Kind, MessageDescriptor, MethodDescriptor};
+use serde_json::{Map, Value};
+use crate::handlers::grpc::{json_response, render_mock};
+use crate::handlers::grpc_server::{message_json, protobuf_json};
+use crate::handlers::grpc_web::allow_cors;
+use crate::middlewares::grpc_registry::GrpcRegistry;
+use crate::models::grpc::{GrpcCode, GrpcMockResponse, GrpcStatus};
+
+/// A segment of a path template.
+#[derive(Debug, Clone, PartialEq)]
+enum PathSegment {
+    Literal(String),
+    /// `*`, a single segment
+    Single,
+    /// `**`, the rest of the path
+    Multi,
+}
+
+/// A path template of a `google.api.http` rule, such as `/v1/{name=shelves/*/books/*}:publish`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathTemplate {
+    segments: Vec<PathSegment>,
+    /// The fields the variables bind, with the segments each one covers.
+    variables: Vec<(String, Range<usize>)>,
+    verb: Option<String>,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("invalid path template \"{}\": {}", template, reason);
+        let rest = template.strip_prefix('/').ok_or_else(|| invalid("must start with /"))?;
+        // A verb follows the last segment, outside of any variable
+        let (mut rest, verb) = match rest.rfind(':') {
+            Some(index) if !rest[index..].contains(['/', '}']) => (&rest[..index], Some(rest[index + 1..].to_string())),
+            _ => (rest, None),
+        };
+
+        let mut segments = Vec::new();
+        let mut variables = Vec::new();
+        loop {
+            if let Some(variable) = rest.strip_prefix('{') {
+                let end = variable.find('}').ok_or_else(|| invalid("unclosed {"))?;
+                let (field, pattern) = variable[..end].split_once('=').unwrap_or((&variable[..end], "*"));
+                if field.trim().is_empty() {
+                    return Err(invalid("a variable needs a field"));
+                }
+                let start = segments.len();
+                for segment in pattern.split('/') {
+                    segments.push(parse_segment(segment).ok_or_else(|| invalid(&format!("invalid segment \"{}\"", segment)))?);
+                }
+                variables.push((field.trim().to_string(), start..segments.len()));
+                rest = &variable[end + 1..];
+            } else {
+                let end = rest.find('/').unwrap_or(rest.len());
+                segments.push(parse_segment(&rest[..end]).ok_or_else(|| invalid(&format!("invalid segment \"{}\"", &rest[..end])))?);
+                rest = &rest[end..];
+            }
+
+            match rest.strip_prefix('/') {
+                Some(next) => rest = next,
+                None if rest.is_empty() => break,
+                None => return Err(invalid("expected / after a variable")),
+            }
+        }
+
+        if segments.iter().rev().skip(1).any(|segment| *segment == PathSegment::Multi) {
+            return Err(invalid("** must be the last segment"));
+        }
+        Ok(Self { segments, variables, verb })
+    }
+
+    /// The fields the variables bind in `path`, if it matches the template.
+    pub fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let mut path = path.strip_prefix('/')?;
+        if let Some(verb) = &self.verb {
+            path = path.strip_suffix(verb.as_str())?.strip_suffix(':')?;
+        }
+        let parts: Vec<&str> = path.split('/').collect();
+
+        // Where each segment of the template starts in the path
+        let mut bounds = Vec::with_capacity(self.segments.len() + 1);
+        let mut index = 0;
+        for segment in &self.segments {
+            bounds.push(index);
+            match segment {
+                PathSegment::Literal(literal) if *parts.get(index)? == literal => index += 1,
+                PathSegment::Literal(_) => return None,
+                PathSegment::Single if !parts.get(index)?.is_empty() => index += 1,
+                PathSegment::Single => return None,
+                PathSegment::Multi => index = parts.len(),
+            }
+        }
+        bounds.push(index);
+        if index != parts.len() {
+            return None;
+        }
+
+        self.variables.iter()
+            .map(|(field, range)| {
+                let value = parts[bounds[range.start]..bounds[range.end]].join("/");
+                Some((field.clone(), urlencoding::decode(&value).ok()?.into_owned()))
+            })
+            .collect()
+    }
+}
+
+fn parse_segment(segment: &str) -> Option<PathSegment> {
+    match segment {
+        "*" => Some(PathSegment::Single),
+        "**" => Some(PathSegment::Multi),
+        "" => None,
+        literal if literal.contains(['{', '}', '*', '=']) => None,
+        literal => Some(PathSegment::Literal(literal.to_string())),
+    }
+}
+
+/// A REST route of a method, from its `google.api.http` option or one of its `additional_bindings`.
+struct HttpRoute {
+    method: MethodDescriptor,
+    verb: Method,
+    template: PathTemplate,
+    /// The request field the body fills, or `*` for the whole request.
+    body: Option<String>,
+    /// The response field answered in place of the whole response.
+    response_body: Option<String>,
+}
+
+impl HttpRoute {
+    fn from_rule(method: &MethodDescriptor, rule: &DynamicMessage) -> Option<Self> {
+        let (verb, path) = match ["get", "put", "post", "delete", "patch"].into_iter().find(|verb| rule.has_field_by_name(verb)) {
+            Some(verb) => (verb.to_ascii_uppercase(), string_field(rule, verb)?),
+            None => {
+                let custom = rule.get_field_by_name("custom")?;
+                let custom = custom.as_message()?;
+                (string_field(custom, "kind")?, string_field(custom, "path")?)
+            }
+        };
+        let template = PathTemplate::parse(&path)
+            .map_err(|e| warn!("📡 Skipping the HTTP route of {}: {}", method.full_name(), e))
+            .ok()?;
+
+        Some(Self {
+            method: method.clone(),
+            verb: Method::from_bytes(verb.as_bytes()).ok()?,
+            template,
+            body: string_field(rule, "body"),
+            response_body: string_field(rule, "response_body"),
+        })
+    }
+}
+
+fn string_field(message: &DynamicMessage, name: &str) -> Option<String> {
+    message.get_field_by_name(name)?.as_str()
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+/// The REST routes of the loaded methods, in the order of their services.
+fn http_routes(pool: &DescriptorPool) -> Vec<HttpRoute> {
+    let Some(extension) = pool.get_extension_by_name("google.api.http") else {
+        return Vec::new();
+    };
+    pool.services()
+        .flat_map(|service| service.methods().collect::<Vec<_>>())
+        .flat_map(|method| {
+            let options = method.options();
+            if !options.has_extension(&extension) {
+                return Vec::new();
+            }
+            let Some(rule) = options.get_extension(&extension).as_message().cloned() else {
+                return Vec::new();
+            };
+            let mut rules = vec![rule.clone()];
+            if let Some(bindings) = rule.get_field_by_name("additional_bindings")
+                && let Some(bindings) = bindings.as_list() {
+                rules.extend(bindings.iter().filter_map(|binding| binding.as_message().cloned()));
+            }
+            rules.iter().filter_map(|rule| HttpRoute::from_rule(&method, rule)).collect()
+        })
+        .collect()
+}
+
+/// Answers an HTTP/JSON request from the `google.api.http` routes of the loaded methods,
+/// as a gateway transcoding it into a call would; `None` if no route matches it.
+pub(crate) async fn serve_transcoded(request: Request<Body>, registry: Arc<GrpcRegistry>) -> Option<Response<Body>> {
+    let routes = http_routes(&registry.descriptors().await);
+    let (route, bindings) = routes.into_iter().find_map(|route| {
+        let bindings = (route.verb == request.method()).then(|| route.template.matches(request.uri().path()))??;
+        Some((route, bindings))
+    })?;
+    info!("📡 gRPC HTTP/JSON call {} {} to {}", request.method(), request.uri().path(), route.method.full_name());
+
+    let query = request.uri().query().map(String::from);
+    let result = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => transcode(&route, bindings, query.as_deref(), &body, &registry).await,
+        Err(e) => Err(GrpcStatus::new(GrpcCode::Internal, format!("Could not read the request: {}", e))),
+    };
+    let mut response = result.unwrap_or_else(|status| {
+        mock_response(GrpcMockResponse { code: Some(status.code), message: Some(status.message), ..Default::default() })
+    });
+    allow_cors(response.headers_mut());
+    Some(response)
+}
+
+async fn transcode(route: &HttpRoute, bindings: Vec<(String, String)>, query: Option<&str>, body: &[u8], registry: &GrpcRegistry) -> Result<Response<Body>, GrpcStatus> {
+    let input = request_input(route, bindings, query, body)?;
+    let mock = registry.find_mock(&route.method, &input).await
+        .ok_or_else(|| GrpcStatus::new(GrpcCode::Unimplemented, format!("No mock for {}", route.method.full_name())))?;
+    let mut mock = render_mock(mock, &input);
+
+    let status = mock.grpc_status();
+    if status.code == GrpcCode::Ok {
+        mock.output = output_json(route, &mock.output)?;
+        if route.method.is_server_streaming() {
+            for message in &mut mock.stream {
+                message.output = output_json(route, &message.output)?;
+            }
+        } else {
+            mock.stream.clear();
+        }
+    }
+    mock.code = Some(status.code);
+    mock.message = Some(status.message);
+
+    if let Some(delay) = mock.delay_ms {
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
+    Ok(mock_response(mock))
+}
+
+/// The JSON answer of a mock, as the JSON shim gives it.
+fn mock_response(mock: GrpcMockResponse) -> Response<Body> {
+    json_response(&mock)
+        .map(|response| response.map(Body::from))
+        .unwrap_or_else(|| {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        })
+}
+
+/// The request message of a call: the body, set at the `body` field of the route, with the
+/// query parameters and path variables filling the fields they name.
+fn request_input(route: &HttpRoute, bindings: Vec<(String, String)>, query: Option<&str>, body: &[u8]) -> Result<Value, GrpcStatus> {
+    let descriptor = route.method.input();
+    let body = match body.is_empty() {
+        true => None,
+        false => Some(serde_json::from_slice::<Value>(body)
+            .map_err(|e| GrpcStatus::new(GrpcCode::InvalidArgument, format!("Invalid JSON body: {}", e)))?),
+    };
+
+    let mut input = Value::Object(Map::new());
+    match route.body.as_deref() {
+        Some("*") => input = body.unwrap_or(input),
+        Some(field) => {
+            // Query parameters fill the fields left out of the body
+            set_query_fields(&mut input, &descriptor, query);
+            if let Some(body) = body {
+                set_field(&mut input, field, body, false);
+            }
+        }
+        None => set_query_fields(&mut input, &descriptor, query),
+    }
+    for (field, value) in bindings {
+        let value = typed_value(field_at(&descriptor, &field).as_ref(), &value);
+        set_field(&mut input, &field, value, false);
+    }
+
+    let message = DynamicMessage::deserialize(descriptor.clone(), &input)
+        .map_err(|e| GrpcStatus::new(GrpcCode::InvalidArgument, format!("Invalid {}: {}", descriptor.full_name(), e)))?;
+    message_json(&message)
+}
+
+fn set_query_fields(input: &mut Value, descriptor: &MessageDescriptor, query: Option<&str>) {
+    for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        // Parameters naming no field, such as cache busters, are left out
+        if let Some(field) = field_at(descriptor, &key) {
+            set_field(input, &key, typed_value(Some(&field), &value), field.is_list());
+        }
+    }
+}
+
+/// The field a dotted path names, by proto or JSON names.
+fn field_at(descriptor: &MessageDescriptor, path: &str) -> Option<FieldDescriptor> {
+    let mut message = descriptor.clone();
+    let mut field = None;
+    for name in path.split('.') {
+        let found = message.get_field_by_name(name).or_else(|| message.get_field_by_json_name(name))?;
+        if let Kind::Message(nested) = found.kind() {
+            message = nested;
+        }
+        field = Some(found);
+    }
+    field
+}
+
+/// The JSON of a text value for a field: numbers and booleans as such, anything else as a string.
+fn typed_value(field: Option<&FieldDescriptor>, text: &str) -> Value {
+    let typed = match field.map(FieldDescriptor::kind) {
+        Some(Kind::Bool) => text.parse().ok().map(Value::Bool),
+        Some(Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 | Kind::Uint32 | Kind::Fixed32 | Kind::Float | Kind::Double) => {
+            serde_json::from_str::<serde_json::Number>(text).ok().map(Value::Number)
+        }
+        _ => None,
+    };
+    typed.unwrap_or_else(|| Value::String(text.to_string()))
+}
+
+/// Sets a dotted field of a JSON object, or appends to it for repeated fields.
+fn set_field(input: &mut Value, path: &str, value: Value, repeated: bool) {
+    let mut current = input;
+    for name in path.split('.') {
+        current = object(current).entry(name).or_insert(Value::Null);
+    }
+    match (repeated, current) {
+        (true, Value::Array(items)) => items.push(value),
+        (true, current) => *current = Value::Array(vec![value]),
+        (false, current) => *current = value,
+    }
+}
+
+fn object(value: &mut Value) -> &mut Map<String, Value> {
+    if !value.is_object() {
+        *value = Value::Object(Map::new());
+    }
+    match value {
+        Value::Object(map) => map,
+        _ => unreachable!("the value was just made an object"),
+    }
+}
+
+/// The protobuf JSON of a mocked response, or of its `response_body` field.
+fn output_json(route: &HttpRoute, output: &Value) -> Result<Value, GrpcStatus> {
+    let descriptor = route.method.output();
+    let options = DeserializeOptions::new().deny_unknown_fields(false);
+    let message = DynamicMessage::deserialize_with_options(descriptor.clone(), output, &options)
+        .map_err(|e| GrpcStatus::new(GrpcCode::Internal, format!("The mock is not a valid {}: {}", descriptor.full_name(), e)))?;
+    let json = protobuf_json(&message)?;
+    Ok(match route.response_body.as_deref().and_then(|field| descriptor.get_field_by_name(field)) {
+        Some(field) => json.get(field.json_name()).cloned().unwrap_or(Value::Null),
+        None => json,
+    })
+}
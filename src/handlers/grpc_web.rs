@@ -0,0 +1,133 @@
+use std::sync::Arc;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::{BufMut, Bytes, BytesMut};
+use hyper::body::HttpBody;
+use hyper::header::{HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, CONTENT_TYPE};
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use crate::handlers::grpc_server::{call, status_response, MAX_MESSAGE_SIZE};
+use crate::middlewares::grpc_registry::GrpcRegistry;
+use crate::models::grpc::{GrpcCode, GrpcStatus};
+
+/// Whether a content type is gRPC-Web, binary (`application/grpc-web+proto`) or text
+/// (`application/grpc-web-text`).
+pub(crate) fn is_grpc_web(content_type: &str) -> bool {
+    content_type.starts_with("application/grpc-web")
+}
+
+/// Answers a gRPC-Web call, as browsers send them: the call is made as a native one, and
+/// its answer sent back with the trailers as a last frame of the body. The text variant
+/// has both bodies in base64.
+pub(crate) async fn serve_grpc_web(request: Request<Body>, registry: Arc<GrpcRegistry>) -> Response<Body> {
+    let content_type = request.headers().get(CONTENT_TYPE).cloned()
+        .unwrap_or_else(|| HeaderValue::from_static("application/grpc-web+proto"));
+    let text = content_type.to_str().is_ok_and(|value| value.starts_with("application/grpc-web-text"));
+
+    let (mut parts, body) = request.into_parts();
+    parts.headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    let body = match text {
+        true => match decode_text_body(body).await {
+            Ok(body) => Body::from(body),
+            Err(status) => return web_response(status_response(&status), text, content_type),
+        },
+        false => body,
+    };
+
+    let response = match call(Request::from_parts(parts, body), &registry).await {
+        Ok(response) => response,
+        Err(status) => status_response(&status),
+    };
+    web_response(response, text, content_type)
+}
+
+/// Answers the CORS preflight browsers send before a gRPC-Web or HTTP/JSON call.
+pub(crate) fn preflight_response(request: &Request<Body>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let headers = response.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    headers.insert(ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, POST, PUT, PATCH, DELETE, OPTIONS"));
+    headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, request.headers().get(ACCESS_CONTROL_REQUEST_HEADERS).cloned()
+        .unwrap_or_else(|| HeaderValue::from_static("content-type, x-grpc-web, x-user-agent, grpc-timeout")));
+    headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static("86400"));
+    response
+}
+
+/// Lets browsers from any origin read a response, headers included.
+pub(crate) fn allow_cors(headers: &mut HeaderMap) {
+    let names = headers.keys().map(|name| name.as_str()).collect::<Vec<_>>().join(", ");
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    if let Ok(names) = HeaderValue::from_str(&names) {
+        headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, names);
+    }
+}
+
+/// Turns the answer of a native call into a gRPC-Web one. Trailers-only answers keep
+/// their status in the headers.
+fn web_response(response: Response<Body>, text: bool, content_type: HeaderValue) -> Response<Body> {
+    let (mut parts, mut body) = response.into_parts();
+    parts.headers.insert(CONTENT_TYPE, content_type);
+    allow_cors(&mut parts.headers);
+
+    let (mut sender, web_body) = Body::channel();
+    tokio::spawn(async move {
+        while let Some(chunk) = body.data().await {
+            let Ok(chunk) = chunk else { return };
+            if sender.send_data(encode_chunk(chunk, text)).await.is_err() {
+                return;
+            }
+        }
+        if let Ok(Some(trailers)) = body.trailers().await {
+            let _ = sender.send_data(encode_chunk(trailer_frame(&trailers), text)).await;
+        }
+    });
+    Response::from_parts(parts, web_body)
+}
+
+/// The trailers as a frame flagged `0x80`, holding them as HTTP/1 header lines.
+pub fn trailer_frame(trailers: &HeaderMap) -> Bytes {
+    let mut block = BytesMut::new();
+    for (name, value) in trailers {
+        block.put_slice(name.as_str().as_bytes());
+        block.put_slice(b": ");
+        block.put_slice(value.as_bytes());
+        block.put_slice(b"\r\n");
+    }
+    let mut frame = BytesMut::with_capacity(5 + block.len());
+    frame.put_u8(0x80);
+    frame.put_u32(block.len() as u32);
+    frame.put_slice(&block);
+    frame.freeze()
+}
+
+fn encode_chunk(chunk: Bytes, text: bool) -> Bytes {
+    match text {
+        true => Bytes::from(STANDARD.encode(chunk)),
+        false => chunk,
+    }
+}
+
+/// The frames of a `grpc-web-text` body: base64, possibly several padded chunks in a row.
+///
+/// Bodies longer than the base64 of the largest message frame are refused while reading.
+async fn decode_text_body(mut body: Body) -> Result<Bytes, GrpcStatus> {
+    let max_len = (5 + MAX_MESSAGE_SIZE).div_ceil(3) * 4;
+    let mut text = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk
+            .map_err(|e| GrpcStatus::new(GrpcCode::Internal, format!("Could not read the request: {}", e)))?;
+        text.extend(chunk.into_iter().filter(|byte| !byte.is_ascii_whitespace()));
+        if text.len() > max_len {
+            return Err(GrpcStatus::new(GrpcCode::ResourceExhausted, format!(
+                "Received message larger than max ({} bytes)", MAX_MESSAGE_SIZE
+            )));
+        }
+    }
+    let mut decoded = BytesMut::new();
+    for group in text.chunks(4) {
+        let bytes = STANDARD.decode(group)
+            .map_err(|e| GrpcStatus::new(GrpcCode::Internal, format!("Invalid grpc-web-text body: {}", e)))?;
+        decoded.put_slice(&bytes);
+    }
+    Ok(decoded.freeze())
+}
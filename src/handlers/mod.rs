@@ -1,4 +1,12 @@
 pub mod multipart;
 pub mod params;
 pub mod graphql;
+pub mod graphql_schema;
+pub mod graphql_subscription;
+pub mod graphql_ws;
 pub mod grpc;
+pub mod grpc_reflection;
+pub mod grpc_server;
+pub mod grpc_transcoding;
+pub mod grpc_web;
+pub mod proto;
\ No newline at end of file
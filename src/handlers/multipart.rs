@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use bytes::BufMut;
 use futures::{StreamExt, TryStreamExt};
@@ -5,9 +6,12 @@ use tokio::fs;
 use uuid::Uuid;
 use warp::{Rejection};
 use warp::multipart::Part;
-use crate::middlewares::grpc_registry::GrpcRegistry;
-use crate::models::{Endpoint, Endpoints, FileError, InvalidMultipart, MultipartHandler, NotFound, RateLimit, Utf8Error};
-use crate::models::grpc::GrpcMockResponse;
+use crate::middlewares::credentials::hash_auth_data;
+use crate::middlewares::grpc_registry::{validate_matchers, GrpcRegistry};
+use crate::handlers::graphql_schema::GraphQLSchema;
+use crate::handlers::proto::{compile_protos, decode_descriptor_set, DescriptorFiles};
+use crate::models::{Endpoint, EndpointKind, Endpoints, FileError, InvalidEndpointKind, InvalidGraphQLSchema, InvalidGrpcMatchers, InvalidMultipart, InvalidProtoDescriptors, MultipartHandler, NotFound, RateLimit, RateLimitAlgorithm, RateLimitKey, Utf8Error};
+use crate::models::grpc::{GrpcCode, GrpcMatcher, GrpcMockKey, GrpcMockResponse};
 
 impl MultipartHandler {
     
@@ -21,9 +25,27 @@ impl MultipartHandler {
         let mut delay = None;
         let mut rate_limit = None;
         let mut with_dynamic_vars = None;
+        let mut rate_limit_key = Vec::new();
+        let mut rate_limit_algorithm = None;
+        let mut rate_limit_burst = None;
+        let mut rate_limit_body = None;
         let mut parts = form.into_stream();
         let mut grpc_service = None;
         let mut grpc_method = None;
+        let mut grpc_code = None;
+        let mut grpc_message = None;
+        let mut grpc_details = Vec::new();
+        let mut grpc_metadata = BTreeMap::new();
+        let mut grpc_trailers = BTreeMap::new();
+        let mut grpc_stream = Vec::new();
+        let mut grpc_respond_after = None;
+        let mut grpc_echo = false;
+        let mut grpc_matchers = Vec::new();
+        let mut graphql_schema = None;
+        let mut protos = Vec::new();
+        let mut descriptor_set = None;
+        let mut kind = None;
+        let mut is_graphql = false;
         
         while let Some(Ok(part)) = parts.next().await {
             match part.name() {
@@ -36,39 +58,123 @@ impl MultipartHandler {
                 }
                 "authentication" => {
                     let value = Self::part_to_string(part).await?;
-                    authentication = if value == "null" { None } else { Some(value) };
+                    authentication = if value == "null" { None } else { Some(hash_auth_data(&value)) };
                 }
                 "delay" => delay = Some(Self::part_to_string(part).await?.parse::<u64>().ok()),
-                "rate_limit" => {
+                "rate_limit" => rate_limit = Self::part_to_string(part).await?.parse::<RateLimit>().ok(),
+                "rate_limit_algorithm" => {
+                    rate_limit_algorithm = Self::part_to_string(part).await?.parse::<RateLimitAlgorithm>().ok();
+                },
+                "rate_limit_burst" => rate_limit_burst = Self::part_to_string(part).await?.parse::<usize>().ok(),
+                "rate_limit_body" => {
                     let value = Self::part_to_string(part).await?;
-                    if !value.is_empty() || value.contains('/') {
-                        let vals: Vec<&str> = value.split('/').collect();
-                        rate_limit = Some(RateLimit {
-                            requests: vals[0].parse::<usize>().unwrap_or(0),
-                            window_ms: vals[1].parse::<u64>().unwrap_or(0),
-                        });
-                    }
+                    rate_limit_body = (!value.is_empty())
+                        .then(|| serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value)));
+                },
+                "rate_limit_key" => {
+                    rate_limit_key = Self::part_to_string(part).await?
+                        .split(',')
+                        .filter_map(|key| RateLimitKey::try_from(key.to_string()).ok())
+                        .collect();
                 },
                 "with_dynamic_vars" => {
                     with_dynamic_vars = Some(Self::part_to_string(part).await?
                         .parse::<bool>().unwrap_or(false));
                 },
-                "grpcService" => grpc_service = Some(Self::part_to_string(part).await?),
-                "grpcRPC" => grpc_method = Some(Self::part_to_string(part).await?),
+                "schema" => {
+                    let sdl = Self::part_to_string(part).await?;
+                    let schema = GraphQLSchema::parse(&sdl).map_err(|e| warp::reject::custom(InvalidGraphQLSchema(e)))?;
+                    graphql_schema = Some((sdl, schema));
+                },
+                "kind" => {
+                    let value = Self::part_to_string(part).await?;
+                    kind = Some(value.parse::<EndpointKind>().map_err(|e| warp::reject::custom(InvalidEndpointKind(e)))?);
+                },
+                "isGraphQL" => is_graphql = Self::part_to_string(part).await?.parse::<bool>().unwrap_or(false),
+                "grpcService" => grpc_service = Some(Self::part_to_string(part).await?).filter(|name| !name.trim().is_empty()),
+                "grpcRPC" => grpc_method = Some(Self::part_to_string(part).await?).filter(|name| !name.trim().is_empty()),
+                "grpcCode" => grpc_code = Self::part_to_string(part).await?.parse::<GrpcCode>().ok(),
+                "grpcMessage" => grpc_message = Some(Self::part_to_string(part).await?),
+                "grpcDetails" => grpc_details = serde_json::from_str(&Self::part_to_string(part).await?).unwrap_or_default(),
+                "grpcMetadata" => grpc_metadata = serde_json::from_str(&Self::part_to_string(part).await?).unwrap_or_default(),
+                "grpcTrailers" => grpc_trailers = serde_json::from_str(&Self::part_to_string(part).await?).unwrap_or_default(),
+                "grpcStream" => grpc_stream = serde_json::from_str(&Self::part_to_string(part).await?).unwrap_or_default(),
+                "grpcRespondAfter" => grpc_respond_after = Self::part_to_string(part).await?.parse::<usize>().ok(),
+                "grpcEcho" => grpc_echo = Self::part_to_string(part).await?.parse::<bool>().unwrap_or(false),
+                "grpcMatch" => {
+                    let value = Self::part_to_string(part).await?;
+                    grpc_matchers = serde_json::from_str::<Vec<GrpcMatcher>>(&value)
+                        .map_err(|e| e.to_string())
+                        .and_then(|matchers| validate_matchers(&matchers).map(|_| matchers))
+                        .map_err(|e| warp::reject::custom(InvalidGrpcMatchers(e)))?;
+                },
+                "proto" => {
+                    let name = part.filename().unwrap_or("mock.proto").to_string();
+                    protos.push((name, Self::part_to_string(part).await?));
+                },
+                "descriptorSet" => descriptor_set = Some(Self::part_to_bytes(part).await?),
                 _ => {}
             }
         }
 
         let path = path.ok_or_else(|| warp::reject::custom(NotFound))?;
+        // Without a `kind`, the gRPC and GraphQL parts tell what the endpoint mocks
+        let kind = match kind {
+            Some(kind) => kind,
+            None if grpc_service.is_some() || grpc_method.is_some() => EndpointKind::Grpc,
+            None if is_graphql || graphql_schema.is_some() => EndpointKind::GraphQL,
+            None => EndpointKind::Rest,
+        };
+        let grpc = match (kind, grpc_service, grpc_method) {
+            (EndpointKind::Grpc, Some(service), Some(method)) => Some(GrpcMockKey { service, method, matchers: grpc_matchers }),
+            (EndpointKind::Grpc, _, _) => {
+                return Err(warp::reject::custom(InvalidEndpointKind("gRPC endpoints need a grpcService and a grpcRPC".to_string())));
+            }
+            _ => None,
+        };
         let methods = methods
             .map(|m| m.split(',').map(String::from).collect())
             .unwrap_or_else(Vec::new);
+        // A schema generates the data, so its mock overrides are optional
+        if file_name.is_none() && graphql_schema.is_some() {
+            file_name = Some(format!("uploads/{}.json", Uuid::new_v4()));
+            file_data = b"{}".to_vec();
+        }
         let file_name = file_name.ok_or_else(|| warp::reject::custom(NotFound))?;
+        let grpc_output = match &grpc {
+            Some(_) => Some(serde_json::from_slice(&file_data).map_err(|_| warp::reject::custom(Utf8Error))?),
+            None => None,
+        };
+
+        // Descriptors let real gRPC clients call the mocks, see `serve_grpc`. They are only
+        // loaded once the whole form is known to be valid.
+        let descriptors = Self::build_descriptors(protos, descriptor_set).await?;
+        for files in descriptors {
+            grpc_registry.add_descriptors(files).await.map_err(|e| warp::reject::custom(InvalidProtoDescriptors(e)))?;
+        }
         let delay = delay.unwrap_or(None);
+        if let Some(limit) = rate_limit.as_mut() {
+            limit.key_by = rate_limit_key;
+            limit.algorithm = rate_limit_algorithm.unwrap_or_default();
+            limit.burst = rate_limit_burst;
+            limit.exceeded_body = rate_limit_body;
+        }
 
         fs::write(&file_name, file_data.clone())
             .await
             .map_err(|_| warp::reject::custom(FileError))?;
+
+        let graphql_schema = match graphql_schema {
+            Some((sdl, schema)) => {
+                let schema_file = format!("uploads/{}.graphql", Uuid::new_v4());
+                fs::write(&schema_file, sdl)
+                    .await
+                    .map_err(|_| warp::reject::custom(FileError))?;
+                GraphQLSchema::cache(&schema_file, schema);
+                Some(schema_file)
+            }
+            None => None,
+        };
         
         let endpoint = Endpoint {
             method: methods,
@@ -78,23 +184,70 @@ impl MultipartHandler {
             delay,
             rate_limit,
             with_dynamic_vars,
+            graphql_schema,
+            grpc: grpc.clone(),
+            kind,
         };
         
-        if let Some(grpc) = grpc_service {
-            let data = serde_json::from_slice(&file_data)
-                .map_err(|_| warp::reject::custom(Utf8Error))?;
-            grpc_registry.register_mock(&grpc, grpc_method.unwrap().as_str(), GrpcMockResponse{
-                output: data,
+        if let (Some(key), Some(output)) = (&grpc, grpc_output) {
+            grpc_registry.register_mock(&key.service, &key.method, GrpcMockResponse{
+                output,
                 delay_ms: delay,
                 status: status_code,
+                code: grpc_code,
+                message: grpc_message,
+                details: grpc_details,
+                metadata: grpc_metadata,
+                trailers: grpc_trailers,
+                stream: grpc_stream,
+                respond_after: grpc_respond_after,
+                echo: grpc_echo,
+                matchers: key.matchers.clone(),
+                with_dynamic_vars: with_dynamic_vars.unwrap_or(false),
             }).await
         }
 
-        endpoints.lock().await.insert(path.clone(), endpoint);
+        let mut endpoints = endpoints.lock().await;
+        // A mock belongs to the endpoint that registered it last
+        if grpc.is_some() {
+            for other in endpoints.values_mut().filter(|other| other.grpc == grpc) {
+                other.grpc = None;
+            }
+        }
+        // Registering a path again drops the mock it had registered
+        if let Some(previous) = endpoints.insert(path.clone(), endpoint) {
+            if let Some(schema_file) = &previous.graphql_schema {
+                GraphQLSchema::evict(schema_file);
+            }
+            if let Some(key) = previous.grpc {
+                grpc_registry.remove_mock(&key.service, &key.method, &key.matchers).await;
+            }
+        }
         
         Ok(())
     }
 
+    /// Decodes the uploaded descriptor set and compiles the `.proto` files, off the async
+    /// runtime as compiling may take a while.
+    async fn build_descriptors(protos: Vec<(String, String)>, descriptor_set: Option<Vec<u8>>) -> Result<Vec<DescriptorFiles>, Rejection> {
+        if protos.is_empty() && descriptor_set.is_none() {
+            return Ok(Vec::new());
+        }
+        tokio::task::spawn_blocking(move || {
+            let mut descriptors = Vec::new();
+            if let Some(bytes) = descriptor_set {
+                descriptors.push(decode_descriptor_set(&bytes)?);
+            }
+            if !protos.is_empty() {
+                descriptors.push(compile_protos(&protos)?);
+            }
+            Ok(descriptors)
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+        .map_err(|e| warp::reject::custom(InvalidProtoDescriptors(e)))
+    }
+
     async fn part_to_string(part: Part) -> Result<String, Rejection> {
         let bytes = Self::part_to_bytes(part).await?;
         String::from_utf8(bytes).map_err(|_| warp::reject::custom(Utf8Error))
@@ -37,7 +37,7 @@ pub fn get_params_from_request(path: &str) -> HashMap<String, String> {
     params
 }
 
-///
+/// A helper function to extract parameters from a JSON request body
 pub fn get_body_from_request(body: Bytes) -> HashMap<String, String> {
     let mut params = HashMap::new();
 
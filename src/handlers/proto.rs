@@ -0,0 +1,133 @@
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use bytes::Bytes;
+use prost::Message;
+use prost_reflect::DescriptorPool;
+use prost_reflect::prost_types::FileDescriptorProto;
+use uuid::Uuid;
+
+/// Files loaded along with every service: the `google.rpc` error model, so that mocks can
+/// send error details (`google.rpc.Status`, `ErrorInfo`, `BadRequest`...) that clients decode,
+/// and the `google.api.http` annotations the HTTP/JSON routes are transcoded from.
+const BUILT_IN_PROTOS: [(&str, &str); 4] = [
+    ("google/rpc/status.proto", include_str!("protos/google/rpc/status.proto")),
+    ("google/rpc/error_details.proto", include_str!("protos/google/rpc/error_details.proto")),
+    ("google/api/http.proto", include_str!("protos/google/api/http.proto")),
+    ("google/api/annotations.proto", include_str!("protos/google/api/annotations.proto")),
+];
+
+static BUILT_IN_DESCRIPTORS: LazyLock<DescriptorFiles> = LazyLock::new(|| {
+    let files: Vec<(String, String)> = BUILT_IN_PROTOS.iter()
+        .map(|(name, content)| (name.to_string(), content.to_string()))
+        .collect();
+    parse_protos(&files).expect("the built-in protos compile")
+});
+
+/// Protobuf files, each an encoded `FileDescriptorProto` by file name.
+///
+/// They stay encoded: decoding them with `prost_types` would drop the custom options of
+/// the methods, which the HTTP/JSON routes are read from.
+#[derive(Debug, Clone, Default)]
+pub struct DescriptorFiles {
+    pub files: Vec<(String, Bytes)>,
+}
+
+/// The files of a `FileDescriptorSet`, left encoded.
+#[derive(Clone, PartialEq, prost::Message)]
+struct EncodedFileDescriptorSet {
+    #[prost(bytes = "bytes", repeated, tag = "1")]
+    file: Vec<Bytes>,
+}
+
+impl DescriptorFiles {
+    /// Reads an encoded `FileDescriptorSet`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let set = EncodedFileDescriptorSet::decode(bytes).map_err(|e| format!("invalid descriptor set: {}", e))?;
+        let files = set.file.into_iter()
+            .map(|file| {
+                let name = FileDescriptorProto::decode(file.clone()).map_err(|e| format!("invalid descriptor set: {}", e))?.name().to_string();
+                Ok((name, file))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { files })
+    }
+
+    /// The files as an encoded `FileDescriptorSet`.
+    pub fn encode(&self) -> Vec<u8> {
+        EncodedFileDescriptorSet { file: self.files.iter().map(|(_, file)| file.clone()).collect() }.encode_to_vec()
+    }
+
+    /// The descriptors of the files, which must import nothing but each other.
+    pub fn pool(&self) -> Result<DescriptorPool, String> {
+        DescriptorPool::decode(self.encode().as_slice()).map_err(|e| e.to_string())
+    }
+
+    fn add_missing(&mut self, others: &DescriptorFiles) {
+        for (name, file) in &others.files {
+            if !self.files.iter().any(|(loaded, _)| loaded == name) {
+                self.files.push((name.clone(), file.clone()));
+            }
+        }
+    }
+}
+
+/// Compiles uploaded `.proto` files, given by file name and content, into descriptors.
+///
+/// The files may import each other by name, as well as the `google/protobuf` well-known
+/// types, the `google/rpc` error model and the `google/api` HTTP annotations. The result
+/// holds their imports too, so that it can be loaded on its own.
+pub fn compile_protos(files: &[(String, String)]) -> Result<DescriptorFiles, String> {
+    let mut files = files.to_vec();
+    for (name, content) in BUILT_IN_PROTOS {
+        if !files.iter().any(|(uploaded, _)| uploaded == name) {
+            files.push((name.to_string(), content.to_string()));
+        }
+    }
+    parse_protos(&files)
+}
+
+/// Reads a binary `FileDescriptorSet`, as written by `protoc --include_imports --descriptor_set_out`.
+pub fn decode_descriptor_set(bytes: &[u8]) -> Result<DescriptorFiles, String> {
+    let mut files = DescriptorFiles::decode(bytes)?;
+    files.add_missing(&BUILT_IN_DESCRIPTORS);
+    Ok(files)
+}
+
+fn parse_protos(files: &[(String, String)]) -> Result<DescriptorFiles, String> {
+    // The parser reads files from disk
+    let dir = std::env::temp_dir().join(format!("mockiapi-protos-{}", Uuid::new_v4()));
+    let compiled = write_protos(&dir, files).and_then(|inputs| {
+        protobuf_parse::Parser::new()
+            .pure()
+            .include(&dir)
+            .inputs(&inputs)
+            .parse_and_typecheck()
+            .map_err(|e| format!("{:#}", e))
+    });
+    let _ = std::fs::remove_dir_all(&dir);
+
+    compiled?.file_descriptors.iter()
+        .map(|file| {
+            let bytes = protobuf::Message::write_to_bytes(file).map_err(|e| e.to_string())?;
+            Ok((file.name().to_string(), Bytes::from(bytes)))
+        })
+        .collect::<Result<_, String>>()
+        .map(|files| DescriptorFiles { files })
+}
+
+fn write_protos(dir: &Path, files: &[(String, String)]) -> Result<Vec<PathBuf>, String> {
+    files.iter()
+        .map(|(name, content)| {
+            // Names are relative to the include directory, never outside of it
+            if name.split(['/', '\\']).any(|segment| segment == ".." || segment.is_empty()) {
+                return Err(format!("invalid file name \"{}\"", name));
+            }
+            let path = dir.join(name);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            std::fs::write(&path, content).map_err(|e| e.to_string())?;
+            Ok(path)
+        })
+        .collect()
+}
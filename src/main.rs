@@ -1,22 +1,41 @@
 use std::collections::HashMap;
 use std::sync::{Arc};
-use log::info;
+use log::{info, warn};
 use tokio::sync::Mutex;
 use warp::{Filter};
 use warp::http::header::AUTHORIZATION;
+use mockiapi::handlers::grpc_server::serve_grpc;
+use mockiapi::middlewares::admin::{with_admin_auth, AdminCredentials};
 use mockiapi::middlewares::grpc_registry::GrpcRegistry;
-use mockiapi::models::{Endpoints};
-use mockiapi::middlewares::rate_limit::new_rate_limit;
+use mockiapi::middlewares::oidc_provider::init_oidc_provider;
+use mockiapi::models::{AuthProfiles, Endpoints, RateLimit};
+use mockiapi::middlewares::rate_limit::RateLimiter;
+use mockiapi::middlewares::persisted_queries::new_persisted_queries;
+use mockiapi::middlewares::subscriptions::new_subscription_hub;
 use mockiapi::routes::endpoints::{delete_endpoint, list_endpoint, register_endpoint, with_endpoints};
 use mockiapi::routes::dynamic_response::serve_dynamic_response;
-use mockiapi::utils::{handle_rejection, with_rate_limiter};
+use mockiapi::routes::oidc;
+use mockiapi::routes::subscriptions::{list_subscriptions, push_subscription_event, upgrade_graphql_ws};
+use mockiapi::routes::grpc_mocks::{delete_grpc_mocks, list_grpc_mocks, put_grpc_mock};
+use mockiapi::routes::rate_limits::{exhaust_rate_limit, list_rate_limits, reset_rate_limit, reset_rate_limits};
+use mockiapi::routes::auth_profiles::{delete_auth_profile, list_auth_profiles, put_auth_profile, with_auth_profiles};
+use mockiapi::utils::{handle_rejection, with_persisted_queries, with_rate_limiter, with_subscriptions};
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
+    init_oidc_provider();
     let endpoints: Endpoints = Arc::new(Mutex::new(HashMap::new()));
-    let rate_limiter = new_rate_limit();
+    let auth_profiles: AuthProfiles = Arc::new(Mutex::new(HashMap::new()));
+    let global_limit = std::env::var("MOCKIAPI_GLOBAL_RATE_LIMIT").ok()
+        .and_then(|value| value.parse::<RateLimit>()
+            .map_err(|e| warn!("Ignoring MOCKIAPI_GLOBAL_RATE_LIMIT: {}", e))
+            .ok());
+    let rate_limiter = Arc::new(RateLimiter::new(global_limit));
     let registry = Arc::new(GrpcRegistry::new());
+    let subscriptions = new_subscription_hub();
+    let persisted_queries = new_persisted_queries();
+    let admin = AdminCredentials::from_env();
     let registry_filter = warp::any().map({
         let registry = Arc::clone(&registry);
         move || Arc::clone(&registry)
@@ -37,6 +56,7 @@ async fn main() {
     
     let register = warp::post()
         .and(warp::path!("register"))
+        .and(with_admin_auth(admin.clone()))
         .and(warp::multipart::form().max_length(5_000_000)) // 5MB
         .and(with_endpoints(endpoints.clone()))
         .and(registry_filter.clone())
@@ -44,42 +64,190 @@ async fn main() {
 
     let list = warp::get()
         .and(warp::path!("list"))
+        .and(with_admin_auth(admin.clone()))
         .and(with_endpoints(endpoints.clone()))
         .and_then(list_endpoint);
 
     let delete = warp::delete()
         .and(warp::path!("delete" / String))
+        .and(with_admin_auth(admin.clone()))
         .and(with_endpoints(endpoints.clone()))
+        .and(registry_filter.clone())
         .and_then(delete_endpoint);
 
+    let grpc_mocks = warp::get()
+        .and(warp::path!("grpc-mocks"))
+        .and(with_admin_auth(admin.clone()))
+        .and(registry_filter.clone())
+        .and_then(list_grpc_mocks)
+        .or(warp::put()
+            .and(warp::path!("grpc-mocks" / String / String))
+            .and(with_admin_auth(admin.clone()))
+            .and(warp::body::json())
+            .and(registry_filter.clone())
+            .and_then(put_grpc_mock))
+        .or(warp::delete()
+            .and(warp::path!("grpc-mocks" / String / String))
+            .and(with_admin_auth(admin.clone()))
+            .and(with_endpoints(endpoints.clone()))
+            .and(registry_filter.clone())
+            .and_then(delete_grpc_mocks));
+
+    let profiles = warp::get()
+        .and(warp::path!("auth-profiles"))
+        .and(with_admin_auth(admin.clone()))
+        .and(with_auth_profiles(auth_profiles.clone()))
+        .and_then(list_auth_profiles)
+        .or(warp::put()
+            .and(warp::path!("auth-profiles" / String))
+            .and(with_admin_auth(admin.clone()))
+            .and(warp::body::json())
+            .and(with_auth_profiles(auth_profiles.clone()))
+            .and_then(put_auth_profile))
+        .or(warp::delete()
+            .and(warp::path!("auth-profiles" / String))
+            .and(with_admin_auth(admin.clone()))
+            .and(with_auth_profiles(auth_profiles.clone()))
+            .and_then(delete_auth_profile));
+
+    let rate_limits = warp::get()
+        .and(warp::path!("rate-limits"))
+        .and(with_admin_auth(admin.clone()))
+        .and(with_rate_limiter(rate_limiter.clone()))
+        .and_then(list_rate_limits)
+        .or(warp::delete()
+            .and(warp::path!("rate-limits"))
+            .and(with_admin_auth(admin.clone()))
+            .and(with_rate_limiter(rate_limiter.clone()))
+            .and_then(reset_rate_limits))
+        .or(warp::delete()
+            .and(warp::path!("rate-limits" / String))
+            .and(with_admin_auth(admin.clone()))
+            .and(with_rate_limiter(rate_limiter.clone()))
+            .and_then(reset_rate_limit))
+        .or(warp::post()
+            .and(warp::path!("rate-limits" / "exhaust"))
+            .and(with_admin_auth(admin.clone()))
+            .and(warp::body::json())
+            .and(with_rate_limiter(rate_limiter.clone()))
+            .and_then(exhaust_rate_limit));
+
+    let graphql_subscriptions = warp::get()
+        .and(warp::path!("graphql" / "subscriptions"))
+        .and(with_admin_auth(admin.clone()))
+        .and(with_subscriptions(subscriptions.clone()))
+        .and_then(list_subscriptions)
+        .or(warp::post()
+            .and(warp::path!("graphql" / "subscriptions" / ..))
+            .and(warp::path::tail())
+            .and(with_admin_auth(admin.clone()))
+            .and(warp::body::json())
+            .and(with_subscriptions(subscriptions.clone()))
+            .and_then(push_subscription_event));
+
+    let oidc_routes = warp::get()
+        .and(warp::path!(".well-known" / "openid-configuration"))
+        .and_then(oidc::openid_configuration)
+        .or(warp::get()
+            .and(warp::path!(".well-known" / "jwks.json"))
+            .and_then(oidc::jwks))
+        .or(warp::get()
+            .and(warp::path!("oauth" / "authorize"))
+            .and(warp::query())
+            .and_then(oidc::authorize_page))
+        .or(warp::post()
+            .and(warp::path!("oauth" / "authorize"))
+            .and(warp::body::form())
+            .and_then(oidc::approve))
+        .or(warp::post()
+            .and(warp::path!("oauth" / "token"))
+            .and(warp::body::form())
+            .and(warp::header::optional::<String>(AUTHORIZATION.as_str()))
+            .and_then(oidc::token))
+        .or(warp::get()
+            .and(warp::path!("oauth" / "userinfo"))
+            .and(warp::header::optional::<String>(AUTHORIZATION.as_str()))
+            .and_then(oidc::userinfo));
+
+    let graphql_ws = warp::ws()
+        .and(warp::path::full())
+        .and(warp::query::<HashMap<String, String>>()
+            .map(Some)
+            .or(warp::any().map(|| None))
+            .unify())
+        .and(warp::header::headers_cloned())
+        .and(with_endpoints(endpoints.clone()))
+        .and(with_auth_profiles(auth_profiles.clone()))
+        .and(with_subscriptions(subscriptions.clone()))
+        .and_then(upgrade_graphql_ws);
+
     let dynamic_routes = warp::method()
         .and(warp::path::full())
         .and(warp::query::<HashMap<String, String>>()
             .map(Some)
             .or(warp::any().map(|| None))
             .unify())
-        .and(warp::header::optional::<String>(AUTHORIZATION.as_str()))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(with_endpoints(endpoints.clone()))
+        .and(with_auth_profiles(auth_profiles.clone()))
         .and(with_rate_limiter(rate_limiter.clone()))
         .and(warp::body::bytes()
             .map(Some)
             .or(warp::any().map(|| None))
             .unify())
         .and(registry_filter.clone())
+        .and(with_persisted_queries(persisted_queries))
         .and_then(serve_dynamic_response)
         .recover(handle_rejection);
     
     let static_files = warp::fs::dir("frontend/dist")
         .with(warp::log("static_files"));
     
-    let routes = register
+    // Management rejections (e.g. missing admin credentials) must not fall through to the mocks
+    let management = register
         .or(list)
         .or(delete)
+        .or(profiles)
+        .or(rate_limits)
+        .or(grpc_mocks)
+        .or(graphql_subscriptions)
+        .recover(handle_rejection);
+
+    let routes = management
+        .or(oidc_routes)
         .or(static_files)
+        .or(graphql_ws)
         .or(dynamic_routes)
-        .with(warp::cors().allow_any_origin())
+        .with(warp::cors()
+            .allow_any_origin()
+            .expose_headers(vec![
+                "ratelimit-limit", "ratelimit-remaining", "ratelimit-reset",
+                "x-ratelimit-limit", "x-ratelimit-remaining", "x-ratelimit-reset",
+                "retry-after",
+            ]))
         .with(log);
     
+    if admin.is_enabled() {
+        println!(".: Management routes require admin credentials");
+    }
+    let grpc_port = std::env::var("MOCKIAPI_GRPC_PORT").ok()
+        .and_then(|value| value.parse::<u16>()
+            .map_err(|e| warn!("Ignoring MOCKIAPI_GRPC_PORT: {}", e))
+            .ok())
+        .unwrap_or(50051);
+    match tokio::net::TcpListener::bind(("0.0.0.0", grpc_port)).await {
+        Ok(listener) => {
+            println!(".: gRPC server running at http://localhost:{}", grpc_port);
+            let registry = Arc::clone(&registry);
+            tokio::spawn(async move {
+                if let Err(e) = serve_grpc(listener, registry).await {
+                    warn!("gRPC server stopped: {}", e);
+                }
+            });
+        }
+        Err(e) => warn!("gRPC server not started on port {}: {}", grpc_port, e),
+    }
     println!(".: Server running at http://localhost:3001");
     warp::serve(routes).run(([0, 0, 0, 0], 3001)).await;
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+use base64::{Engine as _, engine::general_purpose};
+use log::warn;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use warp::{Filter, Rejection};
+use warp::http::header::AUTHORIZATION;
+use crate::models::AdminUnauthorized;
+
+/// Credentials protecting the management routes (`/register`, `/list`, `/delete`, ...).
+///
+/// Read from `MOCKIAPI_ADMIN_TOKEN` (Bearer) and/or `MOCKIAPI_ADMIN_USER` +
+/// `MOCKIAPI_ADMIN_PASSWORD` (Basic). When none is set the routes stay open.
+#[derive(Debug, Clone, Default)]
+pub struct AdminCredentials {
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl AdminCredentials {
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+        AdminCredentials {
+            token: var("MOCKIAPI_ADMIN_TOKEN"),
+            username: var("MOCKIAPI_ADMIN_USER"),
+            password: var("MOCKIAPI_ADMIN_PASSWORD"),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some() || self.basic().is_some()
+    }
+
+    fn basic(&self) -> Option<(&str, &str)> {
+        Some((self.username.as_deref()?, self.password.as_deref()?))
+    }
+
+    /// Checks the `Authorization` header of a management request.
+    pub fn allows(&self, header: Option<&str>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let Some(header) = header else {
+            return false;
+        };
+
+        if let (Some(token), Some(presented)) = (&self.token, header.strip_prefix("Bearer ")) {
+            return same_secret(token, presented);
+        }
+
+        if let (Some((username, password)), Some(encoded)) = (self.basic(), header.strip_prefix("Basic ")) {
+            let decoded = general_purpose::STANDARD.decode(encoded).ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok());
+            return decoded.as_deref()
+                .and_then(|creds| creds.split_once(':'))
+                .is_some_and(|(u, p)| same_secret(u, username) & same_secret(p, password));
+        }
+
+        false
+    }
+
+    /// The `WWW-Authenticate` challenge sent back on a rejected management request.
+    pub fn challenge(&self) -> &'static str {
+        if self.basic().is_some() {
+            "Basic realm=\"mockiapi admin\""
+        } else {
+            "Bearer realm=\"mockiapi admin\""
+        }
+    }
+}
+
+/// Compares a presented credential in constant time, so timing does not leak the secret.
+///
+/// Both values are compared as HMAC tags under a throwaway key: `hmac::verify` checks tags in
+/// constant time, and comparing tags hides the length of the secret as well.
+fn same_secret(presented: &str, expected: &str) -> bool {
+    let Ok(key) = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()) else {
+        return false;
+    };
+    hmac::verify(&key, presented.as_bytes(), hmac::sign(&key, expected.as_bytes()).as_ref()).is_ok()
+}
+
+/// Rejects management requests that don't carry the admin credentials.
+///
+/// Must come after the path filter so that unrelated routes are not challenged.
+pub fn with_admin_auth(admin: AdminCredentials) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>(AUTHORIZATION.as_str())
+        .and_then(move |header: Option<String>| {
+            let admin = admin.clone();
+            async move {
+                if admin.allows(header.as_deref()) {
+                    Ok(())
+                } else {
+                    warn!("🔒 Rejected management request without valid admin credentials");
+                    Err(warp::reject::custom(AdminUnauthorized { challenge: admin.challenge() }))
+                }
+            }
+        })
+        .untuple_one()
+}
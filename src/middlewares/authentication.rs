@@ -1,55 +1,261 @@
+use std::collections::HashMap;
 use base64::{Engine as _, engine::{general_purpose}};
-use crate::models::AuthData;
+use log::warn;
+use warp::http::HeaderMap;
+use warp::http::header::AUTHORIZATION;
+use crate::middlewares::credentials::verify_password;
+use crate::middlewares::jwt::{token_roles, verify_jwt, JwtError};
+use crate::models::{ApiKeyConfig, ApiKeyLocation, AuthData, AuthError, AuthFailure, AuthProfile, Principal};
+
+const DEFAULT_REALM: &str = "mockiapi";
+
+/// Why the presented credentials did not authenticate the caller, from the least to the
+/// most telling.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Denial {
+    /// Nothing usable was presented for the accepted schemes.
+    Missing,
+    /// A username/password or API key was presented but is wrong.
+    InvalidCredentials,
+    /// A bearer token was presented but is unknown, invalid or expired.
+    InvalidToken,
+    /// A valid bearer token without the required scopes or roles.
+    InsufficientScope,
+}
 
 /// Function to validate the authorization based on the `auth_type` and `auth_data`
-pub fn validate_auth(auth_data: Option<String>, auth_header: Option<String>) -> bool {
-    if let Some(header) = auth_header {
-        if header.starts_with("Basic ") {
-            return validate_basic_auth(auth_data, header);
+///
+/// Credentials are checked against the endpoint's inline settings or the named profile it
+/// references. Returns the authenticated `Principal` on success, so callers can expose it
+/// (e.g. JWT claims) to response templates. On failure the error carries the
+/// `WWW-Authenticate` challenges and the body to answer with.
+pub fn validate_auth(
+    auth_data: Option<String>,
+    headers: &HeaderMap,
+    query_params: &Option<HashMap<String, String>>,
+    profiles: &HashMap<String, AuthProfile>,
+) -> Result<Principal, AuthError> {
+    let auth_struct = auth_data
+        .and_then(|data| serde_json::from_str::<AuthData>(&data).ok())
+        .ok_or_else(|| AuthError::Unauthenticated(AuthFailure::default()))?;
+
+    let inline;
+    let profile = match &auth_struct.profile {
+        Some(name) => {
+            let Some(profile) = profiles.get(name) else {
+                warn!("🔒 Authentication profile '{}' not found", name);
+                return Err(AuthError::Unauthenticated(AuthFailure {
+                    challenges: Vec::new(),
+                    body: auth_struct.unauthorized_body.clone(),
+                }));
+            };
+            profile
+        }
+        None => {
+            inline = AuthProfile::from(&auth_struct);
+            &inline
         }
+    };
+
+    let principal = authenticate(profile, headers, query_params)
+        .map_err(|denial| refuse(&auth_struct, profile, denial))?;
 
-        if header.starts_with("Bearer ") {
-            return validate_bearer_token(auth_data, header);
+    authorize(&auth_struct, principal)
+}
+
+/// Checks the presented credentials against every credential type of the profile.
+///
+/// The caller is authenticated by the first type that accepts them. Otherwise the most
+/// telling denial is kept, e.g. an invalid token over a missing API key.
+fn authenticate(
+    profile: &AuthProfile,
+    headers: &HeaderMap,
+    query_params: &Option<HashMap<String, String>>,
+) -> Result<Principal, Denial> {
+    let mut denial = Denial::Missing;
+
+    if let Some(api_key) = &profile.api_key {
+        match validate_api_key(api_key, headers, query_params) {
+            Ok(principal) => return Ok(principal),
+            Err(other) => denial = denial.max(other),
         }
     }
 
-    false
+    if let Some(header) = headers.get(AUTHORIZATION).and_then(|h| h.to_str().ok()) {
+        let attempt = if header.starts_with("Basic ") && !profile.users.is_empty() {
+            Some(validate_basic_auth(profile, header))
+        } else if header.starts_with("Bearer ") && accepts_bearer(profile) {
+            Some(validate_bearer_token(profile, header))
+        } else {
+            None
+        };
+        match attempt {
+            Some(Ok(principal)) => return Ok(principal),
+            Some(Err(other)) => denial = denial.max(other),
+            None => {}
+        }
+    }
+
+    Err(denial)
 }
 
-/// Function to validate Basic Authentication
-fn validate_basic_auth(auth_data: Option<String>, header: String) -> bool {
-    if let Some(auth_data) = auth_data {
-        let encoded_credentials = header.trim_start_matches("Basic ").to_string();
-        if let Ok(decoded) = general_purpose::STANDARD.decode(&encoded_credentials) {
-            if let Ok(creds) = String::from_utf8(decoded) {
-                let mut parts = creds.split(':');
-                if let (Some(username), Some(password)) = (parts.next(), parts.next()) {
-                    if let Ok(auth_struct) = serde_json::from_str::<AuthData>(&auth_data) {
-                        // Now check if the fields are Some and match
-                        let username_matches = auth_struct.username
-                            .map_or(false, |u| u == username);
-                        let password_matches = auth_struct.password
-                            .map_or(false, |p| p == password);
-
-                        return username_matches && password_matches;
-                    }
-                }
-            }
+/// Applies the endpoint's `allowedUsers` / `allowedRoles` restrictions.
+fn authorize(auth_struct: &AuthData, principal: Principal) -> Result<Principal, AuthError> {
+    let user_allowed = auth_struct.allowed_users.is_empty()
+        || principal.subject.as_ref().is_some_and(|s| auth_struct.allowed_users.contains(s));
+    let role_allowed = auth_struct.allowed_roles.is_empty()
+        || principal.roles.iter().any(|r| auth_struct.allowed_roles.contains(r));
+
+    if user_allowed && role_allowed {
+        Ok(principal)
+    } else {
+        warn!("🔒 {:?} is authenticated but not allowed on this endpoint", principal.subject);
+        Err(AuthError::Forbidden(AuthFailure {
+            challenges: Vec::new(),
+            body: auth_struct.forbidden_body.clone(),
+        }))
+    }
+}
+
+/// Builds the 401/403 answer for a denied request.
+///
+/// A 401 challenges the client with every scheme the profile accepts; bearer challenges
+/// carry the RFC 6750 `error` code when a token was presented. A token lacking scopes
+/// gets a 403 with an `insufficient_scope` challenge listing the required scopes.
+fn refuse(auth_struct: &AuthData, profile: &AuthProfile, denial: Denial) -> AuthError {
+    let realm = quoted(auth_struct.realm.as_deref().unwrap_or(DEFAULT_REALM));
+
+    if denial == Denial::InsufficientScope {
+        let mut challenge = format!("Bearer realm={}, error=\"insufficient_scope\"", realm);
+        let scopes = profile.jwt.as_ref().map(|jwt| jwt.required_scopes.join(" ")).unwrap_or_default();
+        if !scopes.is_empty() {
+            challenge.push_str(&format!(", scope={}", quoted(&scopes)));
+        }
+        return AuthError::Forbidden(AuthFailure {
+            challenges: vec![challenge],
+            body: auth_struct.forbidden_body.clone(),
+        });
+    }
+
+    let mut challenges = Vec::new();
+    if let Some(api_key) = &profile.api_key {
+        let location = match api_key.location {
+            ApiKeyLocation::Header => "header",
+            ApiKeyLocation::Query => "query",
+        };
+        challenges.push(format!("ApiKey realm={}, in=\"{}\", name={}", realm, location, quoted(api_key.name())));
+    }
+    if !profile.users.is_empty() {
+        challenges.push(format!("Basic realm={}, charset=\"UTF-8\"", realm));
+    }
+    if accepts_bearer(profile) {
+        challenges.push(match denial {
+            Denial::InvalidToken => format!(
+                "Bearer realm={}, error=\"invalid_token\", error_description=\"The access token is invalid or expired\"",
+                realm
+            ),
+            _ => format!("Bearer realm={}", realm),
+        });
+    }
+
+    AuthError::Unauthenticated(AuthFailure {
+        challenges,
+        body: auth_struct.unauthorized_body.clone(),
+    })
+}
+
+/// A challenge parameter as an HTTP quoted-string: quotes and backslashes are escaped and
+/// control characters, which no header value may hold, are dropped.
+fn quoted(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars().filter(|c| !c.is_control()) {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
         }
+        quoted.push(c);
     }
-    false
+    quoted.push('"');
+    quoted
+}
+
+fn accepts_bearer(profile: &AuthProfile) -> bool {
+    profile.jwt.is_some() || !profile.tokens.is_empty()
+}
+
+/// Function to validate Basic Authentication
+fn validate_basic_auth(profile: &AuthProfile, header: &str) -> Result<Principal, Denial> {
+    let encoded_credentials = header.trim_start_matches("Basic ");
+    let creds = general_purpose::STANDARD.decode(encoded_credentials).ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or(Denial::InvalidCredentials)?;
+
+    let (username, password) = creds.split_once(':').ok_or(Denial::InvalidCredentials)?;
+
+    let user = profile.users.iter()
+        .find(|u| u.username == username && verify_password(&u.password, password))
+        .ok_or(Denial::InvalidCredentials)?;
+
+    Ok(Principal {
+        subject: Some(user.username.clone()),
+        roles: user.roles.clone(),
+        claims: None,
+        api_key: None,
+    })
 }
 
 /// Function to validate Bearer Token Authentication
-fn validate_bearer_token(auth_data: Option<String>, header: String) -> bool {
-    if let Some(auth_data) = auth_data {
-        if let Ok(auth_struct) = serde_json::from_str::<AuthData>(&auth_data) {
-            if let Some(expected_token) = auth_struct.token_data {
-                let token = header.trim_start_matches("Bearer ").to_string();
-                return token == expected_token;
-            }
-        }
+///
+/// A `jwt` configuration takes precedence over static tokens.
+fn validate_bearer_token(profile: &AuthProfile, header: &str) -> Result<Principal, Denial> {
+    let token = header.trim_start_matches("Bearer ");
+
+    if let Some(jwt) = &profile.jwt {
+        let claims = verify_jwt(jwt, token).map_err(|e| match e {
+            JwtError::InvalidToken => Denial::InvalidToken,
+            JwtError::InsufficientScope => Denial::InsufficientScope,
+        })?;
+        let subject = claims.get("sub").and_then(|s| s.as_str()).map(String::from);
+        return Ok(Principal { subject, roles: token_roles(&claims), claims: Some(claims), api_key: None });
     }
 
-    false
+    let credential = profile.tokens.iter()
+        .find(|t| t.token == token)
+        .ok_or(Denial::InvalidToken)?;
+    Ok(Principal {
+        subject: credential.subject.clone(),
+        roles: credential.roles.clone(),
+        claims: None,
+        api_key: None,
+    })
+}
+
+/// Returns the query parameter an endpoint reads its API key from, if it reads one there.
+///
+/// The key is a credential, not part of the route, so endpoint matching leaves it out.
+pub fn api_key_query_param(auth_data: &str, profiles: &HashMap<String, AuthProfile>) -> Option<String> {
+    let auth_struct = serde_json::from_str::<AuthData>(auth_data).ok()?;
+    let api_key = match &auth_struct.profile {
+        Some(name) => profiles.get(name)?.api_key.as_ref(),
+        None => auth_struct.api_key.as_ref(),
+    }?;
+    (api_key.location == ApiKeyLocation::Query).then(|| api_key.name().to_string())
+}
+
+/// Function to validate API key Authentication, read from a header or a query parameter
+fn validate_api_key(
+    config: &ApiKeyConfig,
+    headers: &HeaderMap,
+    query_params: &Option<HashMap<String, String>>,
+) -> Result<Principal, Denial> {
+    let presented = match config.location {
+        ApiKeyLocation::Header => headers.get(config.name()).and_then(|h| h.to_str().ok()),
+        ApiKeyLocation::Query => query_params.as_ref()
+            .and_then(|params| params.get(config.name()))
+            .map(String::as_str),
+    }.ok_or(Denial::Missing)?;
+
+    config.keys.iter()
+        .any(|key| key == presented)
+        .then(|| Principal { api_key: Some(presented.to_string()), ..Principal::default() })
+        .ok_or(Denial::InvalidCredentials)
 }
@@ -0,0 +1,110 @@
+use std::num::NonZeroU32;
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::Value;
+use crate::models::AuthProfile;
+
+const HASH_PREFIX: &str = "pbkdf2-sha256";
+/// Kept low on purpose: this is a mock server and every Basic request pays the cost.
+const HASH_ITERATIONS: u32 = 10_000;
+const REDACTED: &str = "********";
+/// Fields of the authentication settings that must never leave the server.
+const SECRET_FIELDS: [&str; 5] = ["password", "tokenData", "token", "secret", "keys"];
+
+/// Hashes a password as `pbkdf2-sha256$<iterations>$<salt>$<hash>`.
+///
+/// Values that are already hashed are returned unchanged, so settings can be re-submitted.
+pub fn hash_password(password: &str) -> String {
+    if password.starts_with(HASH_PREFIX) {
+        return password.to_string();
+    }
+
+    let mut salt = [0u8; 16];
+    SystemRandom::new().fill(&mut salt).expect("system randomness is available");
+    let mut hash = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(HASH_ITERATIONS).unwrap(),
+        &salt,
+        password.as_bytes(),
+        &mut hash,
+    );
+
+    format!("{}${}${}${}", HASH_PREFIX, HASH_ITERATIONS, STANDARD_NO_PAD.encode(salt), STANDARD_NO_PAD.encode(hash))
+}
+
+/// Checks a presented password against a stored one.
+///
+/// Stored values that are not hashed (e.g. endpoints built in code) are compared as is.
+pub fn verify_password(stored: &str, presented: &str) -> bool {
+    let Some(encoded) = stored.strip_prefix(HASH_PREFIX).and_then(|s| s.strip_prefix('$')) else {
+        return stored == presented;
+    };
+
+    let mut parts = encoded.split('$');
+    let (Some(iterations), Some(salt), Some(hash)) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    let (Some(iterations), Ok(salt), Ok(hash)) = (
+        iterations.parse().ok().and_then(NonZeroU32::new),
+        STANDARD_NO_PAD.decode(salt),
+        STANDARD_NO_PAD.decode(hash),
+    ) else {
+        return false;
+    };
+
+    pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, presented.as_bytes(), &hash).is_ok()
+}
+
+/// Hashes the inline `password` of an endpoint's authentication settings before storing them.
+pub fn hash_auth_data(auth_data: &str) -> String {
+    let Ok(mut value) = serde_json::from_str::<Value>(auth_data) else {
+        return auth_data.to_string();
+    };
+
+    if let Some(password) = value.get_mut("password")
+        && let Some(plain) = password.as_str() {
+        *password = Value::String(hash_password(plain));
+    }
+    value.to_string()
+}
+
+/// Hashes the passwords of every user of a profile before storing it.
+pub fn hash_profile(mut profile: AuthProfile) -> AuthProfile {
+    for user in &mut profile.users {
+        user.password = hash_password(&user.password);
+    }
+    profile
+}
+
+/// Returns the authentication settings with every secret replaced by a placeholder.
+pub fn redact_auth_data(auth_data: &str) -> String {
+    match serde_json::from_str::<Value>(auth_data) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(_) => REDACTED.to_string(),
+    }
+}
+
+/// Replaces secret fields anywhere in a JSON document by a placeholder.
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if SECRET_FIELDS.contains(&key.as_str()) && !field.is_null() {
+                    *field = match field {
+                        Value::Array(items) => Value::Array(vec![Value::String(REDACTED.to_string()); items.len()]),
+                        _ => Value::String(REDACTED.to_string()),
+                    };
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use chrono::Utc;
 use regex::Regex;
+use serde_json::Value;
 
 /// Replaces placeholders in the response body with actual values.
 ///
@@ -10,15 +11,42 @@ use regex::Regex;
 /// Supports:
 /// - `{{timestamp}}` → Current UTC timestamp
 /// - Other `{{var}}` → Replaced with values from `params`
+/// - Dotted `{{jwt.sub}}` → Replaced with values added through `flatten_json`
 pub fn replace_variables(body: &str, params: &HashMap<String, String>) -> String {
-    let rx = Regex::new(r"\{\{(\w+)}}").unwrap();
-    
+    let rx = Regex::new(r"\{\{([\w.]+)}}").unwrap();
+
     rx.replace_all(body, |caps: &regex::Captures| {
         let key = &caps[1];
-        
+
         match key {
             "timestamp" => Utc::now().to_rfc3339(),
             _ => params.get(key).cloned().unwrap_or_else(|| format!("{{{{{}}}}}", key)),
         }
     }).into_owned()
-}
\ No newline at end of file
+}
+
+/// Flattens a JSON value into dotted template keys under `prefix`.
+///
+/// `{"sub": "42", "org": {"id": 7}}` with prefix `jwt` becomes
+/// `jwt.sub = 42` and `jwt.org.id = 7`. Arrays are kept as their JSON text.
+pub fn flatten_json(prefix: &str, value: &Value) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    collect_flattened(prefix, value, &mut vars);
+    vars
+}
+
+fn collect_flattened(prefix: &str, value: &Value, vars: &mut HashMap<String, String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, nested) in map {
+                collect_flattened(&format!("{}.{}", prefix, key), nested, vars);
+            }
+        }
+        Value::String(s) => {
+            vars.insert(prefix.to_string(), s.clone());
+        }
+        other => {
+            vars.insert(prefix.to_string(), other.to_string());
+        }
+    }
+}
@@ -1,12 +1,26 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use prost_reflect::{DescriptorPool, MethodDescriptor};
+use bytes::Bytes;
+use regex::Regex;
+use serde_json::Value;
 use tokio::sync::RwLock;
-use crate::models::grpc::GrpcMockResponse;
+use crate::handlers::proto::DescriptorFiles;
+use crate::middlewares::json_path::JsonPath;
+use crate::models::grpc::{GrpcMatcher, GrpcMockResponse};
 
 type MethodKey = String; // format: "MyService.MyMethod"
 
 #[derive(Default)]
 pub struct GrpcRegistry {
-    mocks: RwLock<HashMap<MethodKey, GrpcMockResponse>>,
+    mocks: RwLock<HashMap<MethodKey, Vec<GrpcMockResponse>>>,
+    descriptors: RwLock<Descriptors>,
+}
+
+/// The protobuf files loaded so far, by file name, and the pool built from them.
+#[derive(Default)]
+struct Descriptors {
+    files: BTreeMap<String, Bytes>,
+    pool: DescriptorPool,
 }
 
 impl GrpcRegistry {
@@ -14,12 +28,51 @@ impl GrpcRegistry {
         Self::default()
     }
 
+    /// Registers a mock of a method, replacing the one registered with the same matchers.
     pub async fn register_mock(&self, service: &str, method: &str, response: GrpcMockResponse) {
         let key = format!("{}.{}", service, method);
-        self.mocks.write().await.insert(key, response);
+        let mut registry = self.mocks.write().await;
+        let mocks = registry.entry(key).or_default();
+        match mocks.iter_mut().find(|mock| mock.matchers == response.matchers) {
+            Some(mock) => *mock = response,
+            None => mocks.push(response),
+        }
+    }
+
+    /// Removes the mock of a method registered with `matchers`.
+    pub async fn remove_mock(&self, service: &str, method: &str, matchers: &[GrpcMatcher]) -> Option<GrpcMockResponse> {
+        let key = format!("{}.{}", service, method);
+        let mut registry = self.mocks.write().await;
+        let mocks = registry.get_mut(&key)?;
+        let index = mocks.iter().position(|mock| mock.matchers == matchers)?;
+        let removed = mocks.remove(index);
+        if mocks.is_empty() {
+            registry.remove(&key);
+        }
+        Some(removed)
+    }
+
+    /// Removes every mock of a method.
+    pub async fn remove_method(&self, service: &str, method: &str) -> Vec<GrpcMockResponse> {
+        self.mocks.write().await
+            .remove(&format!("{}.{}", service, method))
+            .unwrap_or_default()
+    }
+
+    /// The registered mocks, by service then method.
+    pub async fn list_mocks(&self) -> BTreeMap<String, BTreeMap<String, Vec<GrpcMockResponse>>> {
+        let mut services: BTreeMap<String, BTreeMap<String, Vec<GrpcMockResponse>>> = BTreeMap::new();
+        for (key, mocks) in self.mocks.read().await.iter() {
+            // Service names have dots, method names do not
+            if let Some((service, method)) = key.rsplit_once('.') {
+                services.entry(service.to_string()).or_default().insert(method.to_string(), mocks.clone());
+            }
+        }
+        services
     }
 
-    pub async fn get_mock(&self, service: &str, method: &str) -> Option<GrpcMockResponse> {
+    /// Finds the mock of a method answering the request message `input`.
+    pub async fn get_mock(&self, service: &str, method: &str, input: &Value) -> Option<GrpcMockResponse> {
         let registry = self.mocks.read().await;
 
         let service_prefix = format!("{}.", service);
@@ -29,7 +82,11 @@ impl GrpcRegistry {
             if key.starts_with(&service_prefix) {
                 service_exists = true;
                 if key == &format!("{}.{}", service, method) {
-                    return registry.get(key).cloned();
+                    let mock = select_mock(&registry[key], input).cloned();
+                    if mock.is_none() {
+                        log::warn!("No mock of gRPC method '{}' matches the request", key);
+                    }
+                    return mock;
                 }
             }
         }
@@ -42,4 +99,78 @@ impl GrpcRegistry {
 
         None
     }
-}
\ No newline at end of file
+
+    /// Finds the mock answering `input` of a method described by the loaded protobuf files,
+    /// registered under either the full (`package.Service`) or the short name of its service.
+    pub async fn find_mock(&self, method: &MethodDescriptor, input: &Value) -> Option<GrpcMockResponse> {
+        let registry = self.mocks.read().await;
+        let service = method.parent_service();
+        [service.full_name(), service.name()].iter()
+            .find_map(|service| registry.get(&format!("{}.{}", service, method.name())))
+            .and_then(|mocks| select_mock(mocks, input))
+            .cloned()
+    }
+
+    /// Loads protobuf descriptors. A file loaded again under the same name replaces the
+    /// previous version; nothing changes if the files do not link with the ones loaded.
+    pub async fn add_descriptors(&self, loaded: DescriptorFiles) -> Result<(), String> {
+        let mut descriptors = self.descriptors.write().await;
+        let mut files = descriptors.files.clone();
+        files.extend(loaded.files);
+
+        let pool = DescriptorFiles { files: files.iter().map(|(name, file)| (name.clone(), file.clone())).collect() }.pool()?;
+        *descriptors = Descriptors { files, pool };
+        Ok(())
+    }
+
+    /// The descriptors of the loaded protobuf files, as they are now.
+    pub async fn descriptors(&self) -> DescriptorPool {
+        self.descriptors.read().await.pool.clone()
+    }
+
+    /// Finds a method of the loaded protobuf services, by the full name of its service.
+    pub async fn find_method(&self, service: &str, method: &str) -> Option<MethodDescriptor> {
+        self.descriptors.read().await.pool
+            .get_service_by_name(service)?
+            .methods()
+            .find(|candidate| candidate.name() == method)
+    }
+}
+
+/// The first mock, in the order they were registered, whose matchers all match `input`;
+/// else the one without matchers.
+fn select_mock<'a>(mocks: &'a [GrpcMockResponse], input: &Value) -> Option<&'a GrpcMockResponse> {
+    mocks.iter()
+        .find(|mock| !mock.matchers.is_empty() && mock.matchers.iter().all(|matcher| matches(matcher, input)))
+        .or_else(|| mocks.iter().find(|mock| mock.matchers.is_empty()))
+}
+
+fn matches(matcher: &GrpcMatcher, input: &Value) -> bool {
+    let Ok(path) = JsonPath::from_field(&matcher.field) else {
+        return false;
+    };
+    let regex = match matcher.regex.as_deref().map(Regex::new) {
+        Some(Ok(regex)) => Some(regex),
+        Some(Err(_)) => return false,
+        None => None,
+    };
+    path.select(input).into_iter().any(|value| {
+        matcher.equals.as_ref().is_none_or(|expected| value == expected || text(value) == text(expected))
+            && regex.as_ref().is_none_or(|regex| regex.is_match(&text(value)))
+    })
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().map(String::from).unwrap_or_else(|| value.to_string())
+}
+
+/// Checks the fields and regular expressions of matchers, so that they fail on upload.
+pub fn validate_matchers(matchers: &[GrpcMatcher]) -> Result<(), String> {
+    for matcher in matchers {
+        JsonPath::from_field(&matcher.field)?;
+        if let Some(regex) = &matcher.regex {
+            Regex::new(regex).map_err(|e| format!("invalid regex \"{}\": {}", regex, e))?;
+        }
+    }
+    Ok(())
+}
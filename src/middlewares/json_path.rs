@@ -0,0 +1,138 @@
+use serde_json::Value;
+
+/// A step of a JSONPath.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// `.name`, `['name']`, or an index given by name: `.0`
+    Child(String),
+    /// `[0]`, `[-1]` counting from the end
+    Index(i64),
+    /// `.*` or `[*]`
+    Wildcard,
+    /// `..name`, or `..*` for every descendant
+    Descendant(Option<String>),
+}
+
+/// A parsed JSONPath, supporting the child (`$.a.b`, `$['a']`), index (`$.list[0]`, `$.list[-1]`),
+/// wildcard (`$.*`, `$.list[*]`) and recursive descent (`$..name`) selectors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("invalid JSONPath \"{}\": {}", path, reason);
+        let rest = path.trim().strip_prefix('$').ok_or_else(|| invalid("must start with $"))?;
+        let chars: Vec<char> = rest.chars().collect();
+        let mut segments = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '.' if chars.get(i + 1) == Some(&'.') => {
+                    let (name, next) = read_name(&chars, i + 2);
+                    let name = name.ok_or_else(|| invalid("expected a name after .."))?;
+                    segments.push(Segment::Descendant((name != "*").then_some(name)));
+                    i = next;
+                }
+                '.' => {
+                    let (name, next) = read_name(&chars, i + 1);
+                    let name = name.ok_or_else(|| invalid("expected a name after ."))?;
+                    segments.push(if name == "*" { Segment::Wildcard } else { Segment::Child(name) });
+                    i = next;
+                }
+                '[' => {
+                    let end = chars[i..].iter().position(|c| *c == ']').ok_or_else(|| invalid("unclosed ["))? + i;
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    let inner = inner.trim();
+                    let segment = if inner == "*" {
+                        Segment::Wildcard
+                    } else if let Some(name) = inner.strip_prefix('\'').and_then(|s| s.strip_suffix('\''))
+                        .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"'))) {
+                        Segment::Child(name.to_string())
+                    } else {
+                        Segment::Index(inner.parse().map_err(|_| invalid("expected an index, a quoted name or *"))?)
+                    };
+                    segments.push(segment);
+                    i = end + 1;
+                }
+                other => return Err(invalid(&format!("unexpected '{}'", other))),
+            }
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// A dotted field name, `author.name` or `tags.0`, as a path from the root.
+    pub fn from_field(field: &str) -> Result<Self, String> {
+        if field.starts_with('$') {
+            return Self::parse(field);
+        }
+        Self::parse(&format!("$.{}", field))
+    }
+
+    /// The values the path selects in `value`, in document order.
+    pub fn select<'a>(&self, value: &'a Value) -> Vec<&'a Value> {
+        let mut current = vec![value];
+        for segment in &self.segments {
+            current = current.into_iter()
+                .flat_map(|value| match segment {
+                    Segment::Child(name) => child(value, name).into_iter().collect(),
+                    Segment::Index(index) => index_of(value, *index).into_iter().collect(),
+                    Segment::Wildcard => children(value),
+                    Segment::Descendant(name) => {
+                        let mut descendants = Vec::new();
+                        collect_descendants(value, &mut descendants);
+                        match name {
+                            Some(name) => descendants.into_iter().filter_map(|value| child(value, name)).collect(),
+                            None => descendants.into_iter().skip(1).collect(),
+                        }
+                    }
+                })
+                .collect();
+        }
+        current
+    }
+}
+
+fn read_name(chars: &[char], start: usize) -> (Option<String>, usize) {
+    if chars.get(start) == Some(&'*') {
+        return (Some("*".to_string()), start + 1);
+    }
+    let end = chars[start.min(chars.len())..].iter()
+        .position(|c| !(c.is_alphanumeric() || *c == '_' || *c == '-'))
+        .map_or(chars.len(), |offset| start + offset);
+    let name: String = chars[start.min(end)..end].iter().collect();
+    ((!name.is_empty()).then_some(name), end)
+}
+
+fn child<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => map.get(name),
+        Value::Array(_) => index_of(value, name.parse().ok()?),
+        _ => None,
+    }
+}
+
+fn index_of(value: &Value, index: i64) -> Option<&Value> {
+    let items = value.as_array()?;
+    let index = if index < 0 { items.len().checked_sub(index.unsigned_abs() as usize)? } else { index as usize };
+    items.get(index)
+}
+
+fn children(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Object(map) => map.values().collect(),
+        Value::Array(items) => items.iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// A value followed by everything nested in it, depth first.
+fn collect_descendants<'a>(value: &'a Value, descendants: &mut Vec<&'a Value>) {
+    descendants.push(value);
+    for nested in children(value) {
+        collect_descendants(nested, descendants);
+    }
+}
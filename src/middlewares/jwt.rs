@@ -0,0 +1,177 @@
+use std::str::FromStr;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::jwk::JwkSet;
+use log::warn;
+use serde_json::Value;
+use crate::middlewares::oidc_provider::oidc_provider;
+use crate::models::JwtConfig;
+
+const HMAC_ALGORITHMS: [Algorithm; 3] = [Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+const ASYMMETRIC_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+    Algorithm::ES256, Algorithm::ES384,
+];
+
+/// Why a bearer JWT was refused, mapped to the RFC 6750 error codes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JwtError {
+    /// Malformed, badly signed, expired or issued for someone else (`invalid_token`).
+    InvalidToken,
+    /// Valid, but without the required scopes or roles (`insufficient_scope`).
+    InsufficientScope,
+}
+
+/// Verifies a JWT against the endpoint configuration and returns its claims.
+///
+/// The signature is checked with the configured key source, then the registered
+/// claims (`exp`, `nbf`, `aud`, `iss`) and finally the required scopes and roles.
+/// Any failure is logged and reported as a `JwtError`.
+pub fn verify_jwt(config: &JwtConfig, token: &str) -> Result<Value, JwtError> {
+    let header = decode_header(token).map_err(|e| {
+        warn!("🔑 Malformed JWT header: {}", e);
+        JwtError::InvalidToken
+    })?;
+
+    if !allowed_algorithms(config).contains(&header.alg) {
+        warn!("🔑 JWT algorithm {:?} is not allowed for this endpoint", header.alg);
+        return Err(JwtError::InvalidToken);
+    }
+
+    let key = decoding_key(config, header.alg, header.kid.as_deref()).ok_or(JwtError::InvalidToken)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.required_spec_claims.clear();
+    validation.validate_nbf = true;
+    validation.leeway = config.leeway.unwrap_or(0);
+    match &config.audience {
+        Some(audience) => validation.set_audience(&[audience]),
+        None => validation.validate_aud = false,
+    }
+    if let Some(issuer) = &config.issuer {
+        validation.set_issuer(&[issuer]);
+    }
+
+    let claims = decode::<Value>(token, &key, &validation)
+        .map_err(|e| {
+            warn!("🔑 JWT rejected: {}", e);
+            JwtError::InvalidToken
+        })?
+        .claims;
+
+    if !has_all(&token_scopes(&claims), &config.required_scopes) {
+        warn!("🔑 JWT is missing required scopes {:?}", config.required_scopes);
+        return Err(JwtError::InsufficientScope);
+    }
+    if !has_all(&token_roles(&claims), &config.required_roles) {
+        warn!("🔑 JWT is missing required roles {:?}", config.required_roles);
+        return Err(JwtError::InsufficientScope);
+    }
+
+    Ok(claims)
+}
+
+/// Algorithms accepted for the endpoint, restricted to the family of the configured key
+/// so an HMAC secret can never be used to verify an asymmetric token (or vice versa).
+fn allowed_algorithms(config: &JwtConfig) -> Vec<Algorithm> {
+    let family: &[Algorithm] = if config.secret.is_some() {
+        &HMAC_ALGORITHMS
+    } else {
+        &ASYMMETRIC_ALGORITHMS
+    };
+
+    if config.algorithms.is_empty() {
+        return family.to_vec();
+    }
+
+    config.algorithms.iter()
+        .filter_map(|alg| Algorithm::from_str(alg).ok())
+        .filter(|alg| family.contains(alg))
+        .collect()
+}
+
+fn decoding_key(config: &JwtConfig, alg: Algorithm, kid: Option<&str>) -> Option<DecodingKey> {
+    if let Some(secret) = &config.secret {
+        return Some(DecodingKey::from_secret(secret.as_bytes()));
+    }
+
+    if let Some(path) = &config.public_key_file {
+        let pem = std::fs::read(path)
+            .map_err(|e| warn!("🔑 Unable to read public key file {}: {}", path, e))
+            .ok()?;
+        let key = match alg {
+            Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&pem),
+            _ => DecodingKey::from_rsa_pem(&pem),
+        };
+        return key.map_err(|e| warn!("🔑 Invalid public key in {}: {}", path, e)).ok();
+    }
+
+    if let Some(path) = &config.jwks_file {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| warn!("🔑 Unable to read JWKS file {}: {}", path, e))
+            .ok()?;
+        let jwks: JwkSet = serde_json::from_str(&content)
+            .map_err(|e| warn!("🔑 Invalid JWKS in {}: {}", path, e))
+            .ok()?;
+        return jwk_decoding_key(&jwks, kid);
+    }
+
+    // No key source configured: trust tokens issued by the built-in identity provider
+    let provider = oidc_provider();
+    if !matches!(alg, Algorithm::ES256) {
+        warn!("🔑 Built-in identity provider only issues ES256 tokens");
+        return None;
+    }
+    provider.decoding_key(kid)
+}
+
+/// Picks the key referenced by `kid`, or the only key of the set when the token has no `kid`.
+fn jwk_decoding_key(jwks: &JwkSet, kid: Option<&str>) -> Option<DecodingKey> {
+    let jwk = match kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    };
+
+    let Some(jwk) = jwk else {
+        warn!("🔑 No matching key found in JWKS for kid {:?}", kid);
+        return None;
+    };
+
+    DecodingKey::from_jwk(jwk)
+        .map_err(|e| warn!("🔑 Unsupported JWK: {}", e))
+        .ok()
+}
+
+/// Collects scopes from the `scope` (space separated) or `scp` (string or array) claims.
+fn token_scopes(claims: &Value) -> Vec<String> {
+    ["scope", "scp"].iter()
+        .filter_map(|name| claims.get(*name))
+        .flat_map(string_list)
+        .collect()
+}
+
+/// Collects roles from the `roles` claim or a Keycloak style `realm_access.roles`.
+pub(crate) fn token_roles(claims: &Value) -> Vec<String> {
+    let direct = claims.get("roles");
+    let realm = claims.get("realm_access").and_then(|r| r.get("roles"));
+
+    direct.into_iter()
+        .chain(realm)
+        .flat_map(string_list)
+        .collect()
+}
+
+fn string_list(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => s.split_whitespace().map(String::from).collect(),
+        Value::Array(items) => items.iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn has_all(granted: &[String], required: &[String]) -> bool {
+    required.iter().all(|r| granted.contains(r))
+}
@@ -1,4 +1,11 @@
 pub mod rate_limit;
 pub mod authentication;
 pub mod dynamic_vars;
-pub mod grpc_registry;
\ No newline at end of file
+pub mod json_path;
+pub mod grpc_registry;
+pub mod jwt;
+pub mod oidc_provider;
+pub mod credentials;
+pub mod admin;
+pub mod subscriptions;
+pub mod persisted_queries;
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{encode, Algorithm, DecodingKey, EncodingKey, Header};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+pub const ACCESS_TOKEN_TTL_SECS: u64 = 3600;
+const CODE_TTL_SECS: u64 = 300;
+const REFRESH_TOKEN_TTL_SECS: u64 = 86400;
+
+static PROVIDER: OnceLock<OidcProvider> = OnceLock::new();
+
+/// Generates the signing key of the built-in identity provider, so that it is not
+/// paid for by the first request. Called once at startup.
+pub fn init_oidc_provider() {
+    oidc_provider();
+}
+
+/// Returns the built-in identity provider, generating its signing key if
+/// `init_oidc_provider` has not run yet.
+pub fn oidc_provider() -> &'static OidcProvider {
+    PROVIDER.get_or_init(OidcProvider::new)
+}
+
+/// A pending `authorization_code` grant, waiting to be exchanged at the token endpoint.
+#[derive(Debug, Clone)]
+pub struct AuthorizationCode {
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    pub subject: String,
+    pub scope: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub expires_at: u64,
+}
+
+/// The grant a refresh token stands for, so it can be re-issued with the same identity.
+#[derive(Debug, Clone)]
+pub struct RefreshGrant {
+    pub client_id: String,
+    pub subject: String,
+    pub scope: Option<String>,
+    pub expires_at: u64,
+}
+
+/// Mock OAuth2 / OpenID Connect provider.
+///
+/// Tokens are ES256 JWTs signed with a key pair generated at startup, so every token it
+/// issues can be verified through `jwks()` by the server's own bearer validation.
+pub struct OidcProvider {
+    pub issuer: String,
+    kid: String,
+    encoding_key: EncodingKey,
+    public_jwk: Value,
+    codes: Mutex<HashMap<String, AuthorizationCode>>,
+    refresh_tokens: Mutex<HashMap<String, RefreshGrant>>,
+}
+
+impl OidcProvider {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .expect("Failed to generate OIDC signing key");
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .expect("Failed to load OIDC signing key");
+
+        // Uncompressed SEC1 point: 0x04 || x || y
+        let point = key_pair.public_key().as_ref();
+        let kid = Uuid::new_v4().to_string();
+        let public_jwk = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "use": "sig",
+            "alg": "ES256",
+            "kid": kid,
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..65]),
+        });
+
+        Self {
+            issuer: std::env::var("MOCKIAPI_ISSUER")
+                .unwrap_or_else(|_| "http://localhost:3001".to_string()),
+            kid,
+            encoding_key: EncodingKey::from_ec_der(pkcs8.as_ref()),
+            public_jwk,
+            codes: Mutex::new(HashMap::new()),
+            refresh_tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The public signing keys, as served from the JWKS endpoint.
+    pub fn jwks(&self) -> Value {
+        json!({ "keys": [self.public_jwk] })
+    }
+
+    /// Decoding key for a token issued by this provider.
+    pub fn decoding_key(&self, kid: Option<&str>) -> Option<DecodingKey> {
+        if kid.is_some_and(|kid| kid != self.kid) {
+            return None;
+        }
+        let x = self.public_jwk["x"].as_str()?;
+        let y = self.public_jwk["y"].as_str()?;
+        DecodingKey::from_ec_components(x, y).ok()
+    }
+
+    /// The OpenID Connect discovery document.
+    pub fn discovery(&self) -> Value {
+        json!({
+            "issuer": self.issuer,
+            "authorization_endpoint": format!("{}/oauth/authorize", self.issuer),
+            "token_endpoint": format!("{}/oauth/token", self.issuer),
+            "userinfo_endpoint": format!("{}/oauth/userinfo", self.issuer),
+            "jwks_uri": format!("{}/.well-known/jwks.json", self.issuer),
+            "response_types_supported": ["code"],
+            "grant_types_supported": ["authorization_code", "client_credentials", "password", "refresh_token"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["ES256"],
+            "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+            "code_challenge_methods_supported": ["S256", "plain"],
+            "scopes_supported": ["openid", "profile", "email", "offline_access"],
+            "claims_supported": ["sub", "iss", "aud", "exp", "iat", "scope", "client_id", "nonce"],
+        })
+    }
+
+    /// Signs `claims` with the provider key, filling in the registered claims.
+    pub fn sign(&self, mut claims: Value) -> String {
+        let now = jsonwebtoken::get_current_timestamp();
+        if let Some(obj) = claims.as_object_mut() {
+            obj.entry("iss").or_insert_with(|| json!(self.issuer));
+            obj.entry("iat").or_insert_with(|| json!(now));
+            obj.entry("exp").or_insert_with(|| json!(now + ACCESS_TOKEN_TTL_SECS));
+            obj.entry("jti").or_insert_with(|| json!(Uuid::new_v4().to_string()));
+        }
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.kid.clone());
+        encode(&header, &claims, &self.encoding_key).expect("Failed to sign token")
+    }
+
+    /// Stores a new authorization code, dropping the expired ones nobody exchanged.
+    pub async fn issue_code(&self, mut code: AuthorizationCode) -> String {
+        let value = Uuid::new_v4().simple().to_string();
+        let now = jsonwebtoken::get_current_timestamp();
+        code.expires_at = now + CODE_TTL_SECS;
+        let mut codes = self.codes.lock().await;
+        codes.retain(|_, code| code.expires_at >= now);
+        codes.insert(value.clone(), code);
+        value
+    }
+
+    /// Consumes an authorization code; codes are single use.
+    pub async fn take_code(&self, code: &str) -> Option<AuthorizationCode> {
+        let now = jsonwebtoken::get_current_timestamp();
+        let mut codes = self.codes.lock().await;
+        codes.retain(|_, code| code.expires_at >= now);
+        codes.remove(code)
+    }
+
+    /// Stores a new refresh token, dropping the expired ones.
+    pub async fn issue_refresh_token(&self, mut grant: RefreshGrant) -> String {
+        let value = Uuid::new_v4().simple().to_string();
+        let now = jsonwebtoken::get_current_timestamp();
+        grant.expires_at = now + REFRESH_TOKEN_TTL_SECS;
+        let mut refresh_tokens = self.refresh_tokens.lock().await;
+        refresh_tokens.retain(|_, grant| grant.expires_at >= now);
+        refresh_tokens.insert(value.clone(), grant);
+        value
+    }
+
+    /// Consumes a refresh token; a new one is issued on every refresh (rotation).
+    pub async fn take_refresh_token(&self, token: &str) -> Option<RefreshGrant> {
+        let now = jsonwebtoken::get_current_timestamp();
+        let mut refresh_tokens = self.refresh_tokens.lock().await;
+        refresh_tokens.retain(|_, grant| grant.expires_at >= now);
+        refresh_tokens.remove(token)
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use ring::digest::{digest, SHA256};
+use tokio::sync::Mutex;
+
+pub type PersistedQueries = Arc<PersistedQueryStore>;
+
+pub fn new_persisted_queries() -> PersistedQueries {
+    Arc::new(PersistedQueryStore::default())
+}
+
+/// How many queries are kept; registering another one evicts the oldest.
+pub const MAX_PERSISTED_QUERIES: usize = 1000;
+/// Longest query that can be persisted, in bytes.
+pub const MAX_PERSISTED_QUERY_LEN: usize = 64 * 1024;
+
+/// The queries registered by Automatic Persisted Queries, by their SHA-256 hash.
+///
+/// Kept in memory: clients register their queries again after a restart. Any client may
+/// register queries, so the store is bounded, forgetting the oldest queries first.
+#[derive(Default)]
+pub struct PersistedQueryStore {
+    queries: Mutex<StoredQueries>,
+}
+
+#[derive(Default)]
+struct StoredQueries {
+    by_hash: HashMap<String, String>,
+    /// Hashes from the oldest registration to the newest.
+    order: VecDeque<String>,
+}
+
+impl PersistedQueryStore {
+    pub async fn get(&self, hash: &str) -> Option<String> {
+        self.queries.lock().await.by_hash.get(&hash.to_ascii_lowercase()).cloned()
+    }
+
+    /// Persists a query under its hash. Fails when the query is too long to be kept.
+    pub async fn insert(&self, hash: &str, query: String) -> Result<(), String> {
+        if query.len() > MAX_PERSISTED_QUERY_LEN {
+            return Err(format!("Persisted queries are limited to {} bytes", MAX_PERSISTED_QUERY_LEN));
+        }
+        let hash = hash.to_ascii_lowercase();
+        let mut queries = self.queries.lock().await;
+        if queries.by_hash.insert(hash.clone(), query).is_none() {
+            queries.order.push_back(hash);
+        }
+        while queries.order.len() > MAX_PERSISTED_QUERIES {
+            if let Some(oldest) = queries.order.pop_front() {
+                queries.by_hash.remove(&oldest);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The lowercase hex SHA-256 of a query, as clients compute it for `sha256Hash`.
+pub fn query_hash(query: &str) -> String {
+    digest(&SHA256, query.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -1,51 +1,442 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use log::{info, warn};
 use tokio::sync::Mutex;
-use crate::models::{RateLimit, RateLimited};
+use warp::http::{HeaderMap, HeaderValue, Method};
+use warp::http::header::RETRY_AFTER;
+use crate::models::{Principal, RateLimit, RateLimitAlgorithm, RateLimitDecision, RateLimitKey, RateLimitWindow, RateLimited};
 
-pub type RateLimitTracker = Arc<Mutex<HashMap<String, (Instant, usize)>>>;
+/// How often idle tracker entries are looked for.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(30);
+
+pub type RateLimitTracker = Arc<RateLimiter>;
 
 /// Initialize the rate limit tracker
 pub fn new_rate_limit() -> RateLimitTracker {
-    Arc::new(Mutex::new(HashMap::new()))
+    Arc::new(RateLimiter::new(None))
 }
 
-pub async fn check_rate_limit(
-    path: String,
-    method: &str,
-    rate_limit: Option<&RateLimit>,
-    rate_limiter: RateLimitTracker,
-) -> Result<(), warp::Rejection> {
-    if let Some(limit) = rate_limit {
-        let mut rate_tracker = rate_limiter.lock().await;
+/// Counters of every rate-limited client, plus an optional limit applied to all requests.
+#[derive(Debug)]
+pub struct RateLimiter {
+    entries: Mutex<Entries>,
+    global: Option<RateLimit>,
+}
+
+#[derive(Debug)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    /// Keys forced to refuse requests until the given instant, see `RateLimiter::exhaust`.
+    exhausted: HashMap<String, Instant>,
+    last_eviction: Instant,
+}
+
+/// The counter of one key, with the limit it was last checked against.
+#[derive(Debug, Clone)]
+struct Entry {
+    limit: RateLimit,
+    state: WindowState,
+    last_seen: Instant,
+}
 
-        // Use both path and method as the key for rate limiting
-        let key = format!("{}|{}", path, method); // Combine path and method
+#[derive(Debug, Clone)]
+enum WindowState {
+    Fixed { start: Instant, count: usize },
+    SlidingLog { hits: VecDeque<Instant> },
+    SlidingWindow { start: Instant, current: usize, previous: usize },
+    TokenBucket { tokens: f64, refilled_at: Instant },
+}
+
+impl RateLimiter {
+    pub fn new(global: Option<RateLimit>) -> Self {
+        RateLimiter {
+            entries: Mutex::new(Entries {
+                by_key: HashMap::new(),
+                exhausted: HashMap::new(),
+                last_eviction: Instant::now(),
+            }),
+            global,
+        }
+    }
+
+    /// The limit shared by all requests, if one is configured.
+    pub fn global(&self) -> Option<&RateLimit> {
+        self.global.as_ref()
+    }
+
+    /// Counts a request against the limit of `key` and tells whether it may proceed.
+    pub async fn check(&self, key: &str, limit: &RateLimit) -> RateLimitDecision {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().await;
+        entries.evict_idle(now);
+        let exhausted_until = entries.exhausted_until(key, now);
+
+        let entry = entries.by_key.entry(key.to_string())
+            .or_insert_with(|| Entry::new(limit, now));
+        if entry.limit.algorithm != limit.algorithm {
+            *entry = Entry::new(limit, now);
+        }
+        entry.limit = limit.clone();
+        entry.last_seen = now;
+
+        // A pre-exhausted key refuses the request without counting it
+        match exhausted_until {
+            Some(until) => {
+                let wait = until.duration_since(now);
+                RateLimitDecision { allowed: false, remaining: 0, reset: wait, retry_after: Some(wait), ..entry.hit(now, false) }
+            }
+            None => entry.hit(now, true),
+        }
+    }
 
+    /// Current state of every tracked key, without counting anything against them.
+    ///
+    /// Exhausted keys that have not counted a request yet are listed too, with no
+    /// algorithm and a zero limit.
+    pub async fn windows(&self) -> Vec<RateLimitWindow> {
         let now = Instant::now();
-        let (start_time, count) = rate_tracker.entry(key.clone()).or_insert((now, 0));
+        let entries = self.entries.lock().await;
+
+        let mut windows: Vec<RateLimitWindow> = entries.by_key.iter()
+            .map(|(key, entry)| {
+                let decision = entry.clone().hit(now, false);
+                RateLimitWindow {
+                    key: key.clone(),
+                    algorithm: Some(entry.limit.algorithm),
+                    limit: decision.limit,
+                    count: decision.limit.saturating_sub(decision.remaining),
+                    remaining: decision.remaining,
+                    reset_ms: u64::try_from(decision.reset.as_millis()).unwrap_or(u64::MAX),
+                    exhausted_ms: entries.exhausted_until(key, now)
+                        .map(|until| until.duration_since(now).as_millis() as u64),
+                }
+            })
+            .collect();
+        let exhausted_only = entries.exhausted.iter()
+            .filter(|(key, until)| **until > now && !entries.by_key.contains_key(*key))
+            .map(|(key, until)| {
+                let left = until.duration_since(now).as_millis() as u64;
+                RateLimitWindow {
+                    key: key.clone(),
+                    algorithm: None,
+                    limit: 0,
+                    count: 0,
+                    remaining: 0,
+                    reset_ms: left,
+                    exhausted_ms: Some(left),
+                }
+            });
+        windows.extend(exhausted_only);
+        windows.sort_by(|a, b| a.key.cmp(&b.key));
+        windows
+    }
+
+    /// Forgets the counters of `key` and of every client under it (e.g. `/orders|GET`
+    /// resets `/orders|GET|ip=...` too), or of all keys. Returns how many were removed.
+    pub async fn reset(&self, key: Option<&str>) -> usize {
+        let mut entries = self.entries.lock().await;
+        let before = entries.by_key.len();
+        match key {
+            Some(key) => {
+                entries.by_key.retain(|k, _| !covers(key, k));
+                entries.exhausted.retain(|k, _| !covers(key, k));
+            }
+            None => {
+                entries.by_key.clear();
+                entries.exhausted.clear();
+            }
+        }
+        before - entries.by_key.len()
+    }
+
+    /// Refuses every request counted under `key` for the given duration, whatever its counter.
+    ///
+    /// Fails when the duration reaches beyond what the clock can represent.
+    pub async fn exhaust(&self, key: &str, duration: Duration) -> Result<(), String> {
+        let until = Instant::now().checked_add(duration)
+            .ok_or_else(|| format!("duration of {} ms is too long", duration.as_millis()))?;
+        let mut entries = self.entries.lock().await;
+        entries.exhausted.insert(key.to_string(), until);
+        Ok(())
+    }
+}
+
+/// Whether `key` is `prefix` itself or one of its per-client keys.
+fn covers(prefix: &str, key: &str) -> bool {
+    key.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('|'))
+}
+
+impl Entries {
+    fn exhausted_until(&self, key: &str, now: Instant) -> Option<Instant> {
+        self.exhausted.iter()
+            .filter(|(prefix, until)| **until > now && covers(prefix, key))
+            .map(|(_, until)| *until)
+            .max()
+    }
+
+    /// Drops the entries that have been idle long enough to be back to a full allowance.
+    fn evict_idle(&mut self, now: Instant) {
+        if now.duration_since(self.last_eviction) < EVICTION_INTERVAL {
+            return;
+        }
+        let before = self.by_key.len();
+        self.by_key.retain(|_, entry| now.duration_since(entry.last_seen) < entry.idle_ttl());
+        self.exhausted.retain(|_, until| *until > now);
+        self.last_eviction = now;
+
+        if self.by_key.len() < before {
+            info!("🧹 Evicted {} idle rate limit entries", before - self.by_key.len());
+        }
+    }
+}
+
+impl Entry {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        let state = match limit.algorithm {
+            RateLimitAlgorithm::FixedWindow => WindowState::Fixed { start: now, count: 0 },
+            RateLimitAlgorithm::SlidingLog => WindowState::SlidingLog { hits: VecDeque::new() },
+            RateLimitAlgorithm::SlidingWindow => WindowState::SlidingWindow { start: now, current: 0, previous: 0 },
+            RateLimitAlgorithm::TokenBucket => WindowState::TokenBucket {
+                tokens: capacity(limit) as f64,
+                refilled_at: now,
+            },
+        };
+        Entry { limit: limit.clone(), state, last_seen: now }
+    }
 
-        if now.duration_since(*start_time).as_millis() as u64 > limit.window_ms {
-            *start_time = now;
-            *count = 1;
-            info!("🕛 Rate window expired for path: {}. Resetting counter.", key);
-        } else {
-            *count += 1;
+    /// How long the entry may stay unused before it carries no information anymore.
+    fn idle_ttl(&self) -> Duration {
+        let window = window(&self.limit);
+        match self.state {
+            WindowState::SlidingWindow { .. } => window.checked_mul(2).unwrap_or(Duration::MAX),
+            WindowState::TokenBucket { .. } => {
+                scaled(refill_interval(&self.limit), capacity(&self.limit) as f64).max(window)
+            }
+            _ => window,
+        }
+    }
+
+    /// Brings the counter up to date and tells whether a request is allowed now,
+    /// counting it only when `consume` is set.
+    fn hit(&mut self, now: Instant, consume: bool) -> RateLimitDecision {
+        let limit = &self.limit;
+        let window = window(limit);
+        let requests = limit.requests;
+
+        match &mut self.state {
+            WindowState::Fixed { start, count } => {
+                if now.duration_since(*start).as_millis() as u64 > limit.window_ms {
+                    *start = now;
+                    *count = 0;
+                    info!("🕛 Rate window expired. Resetting counter.");
+                }
+                let reset = window.saturating_sub(now.duration_since(*start));
+                let allowed = *count < requests;
+                if allowed && consume {
+                    *count += 1;
+                }
+                decision(allowed, requests, requests.saturating_sub(*count), reset, reset)
+            }
+            WindowState::SlidingLog { hits } => {
+                while hits.front().is_some_and(|hit| now.duration_since(*hit) >= window) {
+                    hits.pop_front();
+                }
+                let allowed = hits.len() < requests;
+                if allowed && consume {
+                    hits.push_back(now);
+                }
+                let until_oldest_expires = hits.front()
+                    .map(|oldest| window.saturating_sub(now.duration_since(*oldest)))
+                    .unwrap_or_default();
+                let reset = hits.back()
+                    .map(|newest| window.saturating_sub(now.duration_since(*newest)))
+                    .unwrap_or_default();
+                decision(allowed, requests, requests.saturating_sub(hits.len()), reset, until_oldest_expires)
+            }
+            WindowState::SlidingWindow { start, current, previous } => {
+                let elapsed = now.duration_since(*start);
+                if elapsed >= window {
+                    let windows_passed = u32::try_from(elapsed.as_nanos() / window.as_nanos().max(1)).unwrap_or(u32::MAX);
+                    *previous = if windows_passed == 1 { *current } else { 0 };
+                    *current = 0;
+                    *start = window.checked_mul(windows_passed)
+                        .and_then(|passed| start.checked_add(passed))
+                        .filter(|start| *start <= now)
+                        .unwrap_or(now);
+                }
+                let progress = now.duration_since(*start).as_secs_f64() / window.as_secs_f64().max(f64::EPSILON);
+                let weighted = *previous as f64 * (1.0 - progress) + *current as f64;
+                let allowed = weighted + 1.0 <= requests as f64;
+                if allowed && consume {
+                    *current += 1;
+                }
+                let used = (*previous as f64 * (1.0 - progress)).floor() as usize + *current;
+                let reset = window.saturating_sub(now.duration_since(*start))
+                    .saturating_add(if *current > 0 { window } else { Duration::ZERO });
+                let retry_after = window.saturating_sub(now.duration_since(*start));
+                decision(allowed, requests, requests.saturating_sub(used), reset, retry_after)
+            }
+            WindowState::TokenBucket { tokens, refilled_at } => {
+                let capacity = capacity(limit) as f64;
+                let interval = refill_interval(limit);
+                let refilled = now.duration_since(*refilled_at).as_secs_f64() / interval.as_secs_f64().max(f64::EPSILON);
+                *tokens = (*tokens + refilled).min(capacity);
+                *refilled_at = now;
 
-            if *count > limit.requests {
-                warn!(
-                    "⚠️ Rate limit exceeded for path: {} | Method: {} | Current count: {} | Limit: {}",
-                    path,
-                    method,
-                    *count,
-                    limit.requests
-                );
-                return Err(warp::reject::custom(RateLimited));
+                let allowed = *tokens >= 1.0;
+                if allowed && consume {
+                    *tokens -= 1.0;
+                }
+                let reset = scaled(interval, capacity - *tokens);
+                let retry_after = scaled(interval, (1.0 - *tokens).max(0.0));
+                decision(allowed, capacity as usize, tokens.floor() as usize, reset, retry_after)
             }
         }
     }
+}
+
+fn decision(allowed: bool, limit: usize, remaining: usize, reset: Duration, retry_after: Duration) -> RateLimitDecision {
+    RateLimitDecision {
+        allowed,
+        limit,
+        remaining,
+        reset,
+        retry_after: (!allowed).then_some(retry_after),
+    }
+}
 
-    Ok(())
-}
\ No newline at end of file
+fn window(limit: &RateLimit) -> Duration {
+    Duration::from_millis(limit.window_ms)
+}
+
+fn capacity(limit: &RateLimit) -> usize {
+    limit.burst.unwrap_or(limit.requests)
+}
+
+/// Time needed by the token bucket to gain one token.
+fn refill_interval(limit: &RateLimit) -> Duration {
+    window(limit).div_f64(limit.requests.max(1) as f64)
+}
+
+/// `duration * factor`, saturating instead of panicking when the product does not fit.
+fn scaled(duration: Duration, factor: f64) -> Duration {
+    Duration::try_from_secs_f64(duration.as_secs_f64() * factor).unwrap_or(Duration::MAX)
+}
+
+/// Builds the tracker key of a request: its path and method, followed by the configured
+/// client attributes (e.g. `/orders|GET|ip=10.0.0.1|header:x-tenant=acme`).
+///
+/// Missing attributes (no principal, absent header, ...) are keyed as `-`, so such
+/// requests share one counter.
+pub fn rate_limit_key(
+    path: &str,
+    method: &Method,
+    limit: &RateLimit,
+    remote: Option<SocketAddr>,
+    headers: &HeaderMap,
+    principal: Option<&Principal>,
+) -> String {
+    format!("{}|{}{}", path, method, client_key(limit, remote, headers, principal))
+}
+
+/// Builds the tracker key of a request for the global limit, which spans every endpoint.
+pub fn global_rate_limit_key(
+    limit: &RateLimit,
+    remote: Option<SocketAddr>,
+    headers: &HeaderMap,
+    principal: Option<&Principal>,
+) -> String {
+    format!("*{}", client_key(limit, remote, headers, principal))
+}
+
+fn client_key(
+    limit: &RateLimit,
+    remote: Option<SocketAddr>,
+    headers: &HeaderMap,
+    principal: Option<&Principal>,
+) -> String {
+    let mut key = String::new();
+    for part in &limit.key_by {
+        let value = match part {
+            RateLimitKey::Ip => remote.map(|addr| addr.ip().to_string()),
+            RateLimitKey::Principal => principal.and_then(|p| p.subject.clone()),
+            RateLimitKey::ApiKey => principal.and_then(|p| p.api_key.clone()),
+            RateLimitKey::Header(name) => headers.get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+        };
+        key.push_str(&format!("|{}={}", String::from(part.clone()).to_lowercase(), value.as_deref().unwrap_or("-")));
+    }
+    key
+}
+
+/// Counts a request against `rate_limit`, returning the counter state for the response headers.
+///
+/// A refused request is rejected with `RateLimited`, carrying the same state and the
+/// configured 429 body.
+pub async fn check_rate_limit(
+    key: String,
+    rate_limit: Option<&RateLimit>,
+    rate_limiter: RateLimitTracker,
+) -> Result<Option<RateLimitDecision>, warp::Rejection> {
+    let Some(limit) = rate_limit else {
+        return Ok(None);
+    };
+    let decision = rate_limiter.check(&key, limit).await;
+
+    if !decision.allowed {
+        warn!(
+            "⚠️ Rate limit exceeded for key: {} | Algorithm: {:?} | Limit: {}",
+            key,
+            limit.algorithm,
+            decision.limit
+        );
+        return Err(warp::reject::custom(RateLimited {
+            decision,
+            body: limit.exceeded_body.clone(),
+        }));
+    }
+
+    Ok(Some(decision))
+}
+
+/// Keeps the state of the limit closest to refusing requests, which is what clients must honor.
+pub fn most_restrictive(a: Option<RateLimitDecision>, b: Option<RateLimitDecision>) -> Option<RateLimitDecision> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let b_is_tighter = b.remaining < a.remaining || (b.remaining == a.remaining && b.reset > a.reset);
+            Some(if b_is_tighter { b } else { a })
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+/// Adds the `RateLimit-*` and `X-RateLimit-*` headers, plus `Retry-After` on a refusal.
+///
+/// `RateLimit-Reset` is the number of seconds until the allowance is restored, while
+/// `X-RateLimit-Reset` is the matching Unix timestamp.
+pub fn apply_rate_limit_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    let reset = ceil_secs(decision.reset);
+    let reset_at = (chrono::Utc::now().timestamp().max(0) as u64).saturating_add(reset);
+
+    let values = [
+        ("ratelimit-limit", decision.limit as u64),
+        ("ratelimit-remaining", decision.remaining as u64),
+        ("ratelimit-reset", reset),
+        ("x-ratelimit-limit", decision.limit as u64),
+        ("x-ratelimit-remaining", decision.remaining as u64),
+        ("x-ratelimit-reset", reset_at),
+    ];
+    for (name, value) in values {
+        headers.insert(name, HeaderValue::from(value));
+    }
+    if let Some(retry_after) = decision.retry_after {
+        headers.insert(RETRY_AFTER, HeaderValue::from(ceil_secs(retry_after).max(1)));
+    }
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis().div_ceil(1000)).unwrap_or(u64::MAX)
+}
@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde_json::Value;
+use tokio::sync::{broadcast, Mutex};
+
+/// Events a slow subscriber may fall behind by before it starts missing some.
+const CHANNEL_CAPACITY: usize = 64;
+
+pub type SubscriptionHub = Arc<Subscriptions>;
+
+pub fn new_subscription_hub() -> SubscriptionHub {
+    Arc::new(Subscriptions::default())
+}
+
+/// The key of the subscribers of an operation on the endpoint at `path`, e.g. `/graphql|onPrice`.
+pub fn subscription_key(path: &str, operation: &str) -> String {
+    format!("{}|{}", path, operation)
+}
+
+/// Fans ad-hoc events out to the open GraphQL subscriptions, by endpoint and operation key.
+#[derive(Default)]
+pub struct Subscriptions {
+    channels: Mutex<HashMap<String, broadcast::Sender<Value>>>,
+}
+
+impl Subscriptions {
+    pub async fn subscribe(&self, key: &str) -> broadcast::Receiver<Value> {
+        let mut channels = self.channels.lock().await;
+        channels.entry(key.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Sends an event to every subscriber of the key, returning how many there are.
+    pub async fn publish(&self, key: &str, event: Value) -> usize {
+        let mut channels = self.channels.lock().await;
+        let Some(sender) = channels.get(key) else {
+            return 0;
+        };
+
+        let delivered = sender.send(event).unwrap_or(0);
+        if delivered == 0 {
+            channels.remove(key);
+        }
+        delivered
+    }
+
+    /// The number of open subscriptions per endpoint and operation key.
+    pub async fn counts(&self) -> HashMap<String, usize> {
+        let mut channels = self.channels.lock().await;
+        channels.retain(|_, sender| sender.receiver_count() > 0);
+        channels.iter()
+            .map(|(key, sender)| (key.clone(), sender.receiver_count()))
+            .collect()
+    }
+}
@@ -1,9 +1,162 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
 
+pub type AuthProfiles = Arc<Mutex<HashMap<String, AuthProfile>>>;
+
+/// Authentication settings of an endpoint.
+///
+/// Credentials are either inline (`username`/`password`, `tokenData`, `jwt`, `apiKey`) or
+/// taken from a named `profile`. `allowedUsers` and `allowedRoles` then restrict which of
+/// the authenticated callers may use the endpoint; everybody else gets a 403.
+/// `realm` is announced in the `WWW-Authenticate` challenges and `unauthorizedBody` /
+/// `forbiddenBody` replace the default error bodies (a string is sent as text, anything
+/// else as JSON).
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AuthData {
     pub(crate) username: Option<String>,
     pub(crate) password: Option<String>,
     pub(crate) token_data: Option<String>,
-}
\ No newline at end of file
+    pub(crate) jwt: Option<JwtConfig>,
+    pub(crate) api_key: Option<ApiKeyConfig>,
+    pub(crate) profile: Option<String>,
+    #[serde(default)]
+    pub(crate) allowed_users: Vec<String>,
+    #[serde(default)]
+    pub(crate) allowed_roles: Vec<String>,
+    pub(crate) realm: Option<String>,
+    pub(crate) unauthorized_body: Option<Value>,
+    pub(crate) forbidden_body: Option<Value>,
+}
+
+/// A reusable set of credentials, registered once and referenced by name from endpoints.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthProfile {
+    #[serde(default)]
+    pub users: Vec<UserCredential>,
+    #[serde(default)]
+    pub tokens: Vec<TokenCredential>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<JwtConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<ApiKeyConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserCredential {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenCredential {
+    pub token: String,
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl From<&AuthData> for AuthProfile {
+    /// Views the inline credentials of an endpoint as an anonymous profile.
+    fn from(data: &AuthData) -> Self {
+        let users = match (&data.username, &data.password) {
+            (Some(username), Some(password)) => vec![UserCredential {
+                username: username.clone(),
+                password: password.clone(),
+                roles: Vec::new(),
+            }],
+            _ => Vec::new(),
+        };
+        let tokens = data.token_data.iter()
+            .map(|token| TokenCredential { token: token.clone(), subject: None, roles: Vec::new() })
+            .collect();
+
+        AuthProfile { users, tokens, jwt: data.jwt.clone(), api_key: data.api_key.clone() }
+    }
+}
+
+/// API key accepted in a header (default `X-API-Key`) or a query parameter (default `api_key`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyConfig {
+    #[serde(default, rename = "in")]
+    pub location: ApiKeyLocation,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+
+impl ApiKeyConfig {
+    pub fn name(&self) -> &str {
+        match (&self.name, &self.location) {
+            (Some(name), _) => name,
+            (None, ApiKeyLocation::Header) => "X-API-Key",
+            (None, ApiKeyLocation::Query) => "api_key",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyLocation {
+    #[default]
+    Header,
+    Query,
+}
+
+/// Settings used to verify JWT bearer tokens for an endpoint.
+///
+/// At most one key source is expected: `secret` for the HMAC family,
+/// `publicKeyFile` for a PEM encoded RSA/EC public key, or `jwksFile`
+/// for a local JSON Web Key Set. Without any of them, tokens issued by the
+/// built-in identity provider are accepted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JwtConfig {
+    pub secret: Option<String>,
+    pub public_key_file: Option<String>,
+    pub jwks_file: Option<String>,
+    #[serde(default)]
+    pub algorithms: Vec<String>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    #[serde(default)]
+    pub required_scopes: Vec<String>,
+    #[serde(default)]
+    pub required_roles: Vec<String>,
+    pub leeway: Option<u64>,
+}
+
+/// Identity established by a successful authentication check.
+#[derive(Debug, Clone, Default)]
+pub struct Principal {
+    pub subject: Option<String>,
+    pub roles: Vec<String>,
+    pub claims: Option<Value>,
+    /// The API key the caller presented, when authenticated by one.
+    pub api_key: Option<String>,
+}
+
+/// Why a request could not be authenticated or authorized.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    /// No valid credentials were presented (401).
+    Unauthenticated(AuthFailure),
+    /// The caller is authenticated but not allowed to use the endpoint (403).
+    Forbidden(AuthFailure),
+}
+
+/// What the client is told about a refused request.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuthFailure {
+    /// `WWW-Authenticate` values, one per accepted scheme (RFC 7235, RFC 6750).
+    pub challenges: Vec<String>,
+    /// Custom body configured on the endpoint.
+    pub body: Option<Value>,
+}
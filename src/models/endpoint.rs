@@ -1,7 +1,11 @@
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
 use std::sync::{Arc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::sync::Mutex;
+use crate::models::grpc::GrpcMockKey;
 
 pub type Endpoints = Arc<Mutex<HashMap<String, Endpoint>>>;
 
@@ -15,10 +19,175 @@ pub struct Endpoint {
     pub delay: Option<u64>,
     pub rate_limit: Option<RateLimit>,
     pub with_dynamic_vars: Option<bool>,
+    /// SDL file the GraphQL operations are validated and generated against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub graphql_schema: Option<String>,
+    /// The gRPC mock the endpoint registered, removed along with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<GrpcMockKey>,
+    /// What the endpoint mocks, which decides how its requests are answered.
+    #[serde(default)]
+    pub kind: EndpointKind,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// The kind of API an endpoint mocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointKind {
+    /// Answers every request with its file.
+    #[default]
+    Rest,
+    /// Answers GraphQL operations from the mocks of its file, and its schema.
+    GraphQL,
+    /// Answers JSON gRPC calls (`{ "service", "rpc", "request" }`) from the registered gRPC mocks.
+    Grpc,
+}
+
+impl FromStr for EndpointKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(value.trim().to_ascii_lowercase()))
+            .map_err(|_| format!("unknown endpoint kind \"{}\", expected rest, graphql or grpc", value.trim()))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct RateLimit {
     pub requests: usize,
     pub window_ms: u64,
+    /// What identifies a client; without keys every caller shares the same counter.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_by: Vec<RateLimitKey>,
+    #[serde(default)]
+    pub algorithm: RateLimitAlgorithm,
+    /// Bucket size of the token bucket; defaults to `requests`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<usize>,
+    /// Body of the 429 response: a string is sent as text, anything else as JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exceeded_body: Option<Value>,
+}
+
+/// Outcome of a rate limit check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: usize,
+    pub remaining: usize,
+    /// Time until the counter is back to its full allowance.
+    pub reset: Duration,
+    /// Time until the next request would be accepted, when this one was refused.
+    pub retry_after: Option<Duration>,
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parses `requests/window_ms` (e.g. `100/60000`) or a JSON object.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.starts_with('{') {
+            return serde_json::from_str(value).map_err(|e| e.to_string());
+        }
+
+        let (requests, window_ms) = value.split_once('/')
+            .ok_or_else(|| format!("expected requests/window_ms, got {}", value))?;
+        Ok(RateLimit {
+            requests: requests.trim().parse().map_err(|_| format!("invalid request count: {}", requests))?,
+            window_ms: window_ms.trim().parse().map_err(|_| format!("invalid window: {}", window_ms))?,
+            ..Default::default()
+        })
+    }
+}
+
+/// State of one rate limit counter, as listed by `GET /rate-limits`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitWindow {
+    pub key: String,
+    /// Missing for a key that is exhausted but has not counted any request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<RateLimitAlgorithm>,
+    pub limit: usize,
+    pub count: usize,
+    pub remaining: usize,
+    pub reset_ms: u64,
+    /// Time left while the key is forced to refuse requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exhausted_ms: Option<u64>,
+}
+
+/// Body of `POST /rate-limits/exhaust`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExhaustRateLimit {
+    /// Tracker key, e.g. `/orders|GET` for every client of an endpoint or `*` for the global limit.
+    pub key: String,
+    pub duration_ms: u64,
+}
+
+/// How requests are counted against a `RateLimit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RateLimitAlgorithm {
+    /// `requests` per window, the counter restarting with the first request after it ends.
+    #[default]
+    FixedWindow,
+    /// Exact count of the requests made during the last `window_ms`.
+    SlidingLog,
+    /// Current window count plus the weighted count of the previous window.
+    SlidingWindow,
+    /// `burst` tokens, refilled at `requests` per `window_ms`.
+    TokenBucket,
+}
+
+impl FromStr for RateLimitAlgorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(value.trim().to_string()))
+            .map_err(|_| format!("unknown rate limit algorithm: {}", value))
+    }
+}
+
+/// A request attribute the rate limit counters are keyed on.
+///
+/// Written as `ip`, `principal`, `apiKey` or `header:<name>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum RateLimitKey {
+    /// The client address of the connection.
+    Ip,
+    /// The authenticated subject of the request.
+    Principal,
+    /// The API key the request was authenticated with.
+    ApiKey,
+    /// The value of a request header (e.g. `X-Tenant`).
+    Header(String),
+}
+
+impl TryFrom<String> for RateLimitKey {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.trim() {
+            "ip" => Ok(RateLimitKey::Ip),
+            "principal" => Ok(RateLimitKey::Principal),
+            "apiKey" | "api_key" => Ok(RateLimitKey::ApiKey),
+            other => other.strip_prefix("header:")
+                .filter(|name| !name.is_empty())
+                .map(|name| RateLimitKey::Header(name.to_string()))
+                .ok_or_else(|| format!("unknown rate limit key: {}", other)),
+        }
+    }
+}
+
+impl From<RateLimitKey> for String {
+    fn from(key: RateLimitKey) -> Self {
+        match key {
+            RateLimitKey::Ip => "ip".to_string(),
+            RateLimitKey::Principal => "principal".to_string(),
+            RateLimitKey::ApiKey => "apiKey".to_string(),
+            RateLimitKey::Header(name) => format!("header:{}", name),
+        }
+    }
 }
\ No newline at end of file
@@ -1,7 +1,13 @@
 use warp::reject::Reject;
+use serde_json::Value;
+use crate::models::{AuthFailure, RateLimitDecision};
 
+/// A request refused by a rate limit, with the counter state and the configured body.
 #[derive(Debug)]
-pub struct RateLimited;
+pub struct RateLimited {
+    pub decision: RateLimitDecision,
+    pub body: Option<Value>,
+}
 impl Reject for RateLimited {}
 
 #[derive(Debug)]
@@ -9,9 +15,20 @@ pub struct NotFound;
 impl Reject for NotFound {}
 
 #[derive(Debug)]
-pub struct Unauthorized;
+pub struct Unauthorized(pub AuthFailure);
 impl Reject for Unauthorized {}
 
+/// A management request without valid admin credentials, with the challenge to send back.
+#[derive(Debug)]
+pub struct AdminUnauthorized {
+    pub challenge: &'static str,
+}
+impl Reject for AdminUnauthorized {}
+
+#[derive(Debug)]
+pub struct Forbidden(pub AuthFailure);
+impl Reject for Forbidden {}
+
 #[derive(Debug)]
 pub struct InvalidMultipart;
 impl Reject for InvalidMultipart {}
@@ -28,6 +45,41 @@ impl Reject for Utf8Error {}
 pub struct InvalidGraphQLRequest;
 impl Reject for InvalidGraphQLRequest {}
 
+/// An uploaded GraphQL schema that does not parse, with the reason.
+#[derive(Debug)]
+pub struct InvalidGraphQLSchema(pub String);
+impl Reject for InvalidGraphQLSchema {}
+
+/// Uploaded `.proto` files or descriptor set that do not load, with the reason.
+#[derive(Debug)]
+pub struct InvalidProtoDescriptors(pub String);
+impl Reject for InvalidProtoDescriptors {}
+
+/// A JSON gRPC call that is not `{ "service", "rpc", "request" }`, with the reason.
+#[derive(Debug)]
+pub struct InvalidGrpcRequest(pub String);
+impl Reject for InvalidGrpcRequest {}
+
+/// A registration whose `kind` is unknown or misses what the kind needs, with the reason.
+#[derive(Debug)]
+pub struct InvalidEndpointKind(pub String);
+impl Reject for InvalidEndpointKind {}
+
+/// Uploaded gRPC matchers that do not parse, with the reason.
+#[derive(Debug)]
+pub struct InvalidGrpcMatchers(pub String);
+impl Reject for InvalidGrpcMatchers {}
+
+/// A WebSocket upgrade offering only subprotocols the server does not speak, with those offered.
+#[derive(Debug)]
+pub struct UnsupportedSubprotocol(pub String);
+impl Reject for UnsupportedSubprotocol {}
+
+/// A rate limit admin request that cannot be applied, with the reason.
+#[derive(Debug)]
+pub struct InvalidRateLimitRequest(pub String);
+impl Reject for InvalidRateLimitRequest {}
+
 #[derive(Debug)]
 pub struct MethodNotAllowed;
 impl Reject for MethodNotAllowed {}
\ No newline at end of file
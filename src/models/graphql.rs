@@ -1,7 +1,47 @@
 use serde::Deserialize;
+use serde_json::{Map, Value};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct GraphQLRequest {
+    /// Left out by Automatic Persisted Queries that only send the hash of a known query.
+    #[serde(default)]
     pub query: String,
+    #[serde(alias = "operationName")]
     pub operation_name: Option<String>,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub variables: Option<Map<String, Value>>,
+    #[serde(default)]
+    pub extensions: Option<Map<String, Value>>,
+}
+
+impl GraphQLRequest {
+    /// The Automatic Persisted Query the request refers to, if any.
+    pub fn persisted_query(&self) -> Option<PersistedQuery> {
+        let persisted = self.extensions.as_ref()?.get("persistedQuery")?;
+        serde_json::from_value(persisted.clone()).ok()
+    }
+}
+
+/// The `persistedQuery` extension of Apollo's Automatic Persisted Queries.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PersistedQuery {
+    pub version: u64,
+    #[serde(rename = "sha256Hash")]
+    pub sha256_hash: String,
+}
+
+/// A GraphQL request as sent over HTTP: a single operation, or a batch of operations
+/// answered with an array of results.
+#[derive(Debug, Clone)]
+pub enum GraphQLPayload {
+    Single(GraphQLRequest),
+    Batch(Vec<GraphQLRequest>),
+}
+
+/// A GraphQL response built from a mock, with the HTTP status and delay the operation simulates.
+#[derive(Debug)]
+pub struct GraphQLResponse {
+    pub body: Value,
+    pub status: Option<u16>,
+    pub delay: Option<u64>,
+}
@@ -1,4 +1,8 @@
-use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GrpcMockRequest {
@@ -9,9 +13,214 @@ pub struct GrpcMockRequest {
     pub input: serde_json::Value,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct GrpcMockResponse {
     pub output: serde_json::Value,
     pub delay_ms: Option<u64>,
+    /// The HTTP status of the JSON shim; without a `code`, also the gRPC code it maps to.
     pub status: Option<u16>,
-}
\ No newline at end of file
+    /// The gRPC status code the call ends with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<GrpcCode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Error details, as `google.protobuf.Any` JSON: `{ "@type": "type.googleapis.com/google.rpc.ErrorInfo", ... }`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<Value>,
+    /// Response headers (initial metadata).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// Trailing metadata, sent along with the status.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub trailers: BTreeMap<String, String>,
+    /// The messages a server-streaming method answers with, in place of `output`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stream: Vec<GrpcStreamMessage>,
+    /// How many messages a client-streaming method takes before answering; all of them if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub respond_after: Option<usize>,
+    /// Answers every inbound message with itself, with the fields of `output` set over it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub echo: bool,
+    /// Conditions on the request message; a mock without any is the default of its method.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matchers: Vec<GrpcMatcher>,
+    /// Fills the `{{request.field}}` placeholders of the output with the request message.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub with_dynamic_vars: bool,
+}
+
+/// Identifies a registered mock: its method, and the matchers telling it apart from the
+/// other mocks of the method.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GrpcMockKey {
+    pub service: String,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matchers: Vec<GrpcMatcher>,
+}
+
+/// A condition on the request message, met when any value the `field` selects passes
+/// the checks given; with neither `equals` nor `regex`, when the field is set.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GrpcMatcher {
+    /// A dotted field name (`author.name`) or a JSONPath (`$.tags[*]`).
+    pub field: String,
+    /// The value of the field; numbers and booleans also match their text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<Value>,
+    /// A regular expression the text of the field matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+/// A message of a streamed response, sent `delay_ms` after the previous one.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GrpcStreamMessage {
+    pub output: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_ms: Option<u64>,
+}
+
+impl GrpcMockResponse {
+    /// The status the call ends with: `code`, else the code of the HTTP `status`. Failures
+    /// without a `message` use the one of the output, if it has one.
+    pub fn grpc_status(&self) -> GrpcStatus {
+        let code = self.code.unwrap_or_else(|| GrpcCode::from_http_status(self.status.unwrap_or(200)));
+        let message = self.message.clone()
+            .or_else(|| (code != GrpcCode::Ok)
+                .then(|| self.output.get("message").and_then(Value::as_str).map(String::from))
+                .flatten())
+            .unwrap_or_default();
+        GrpcStatus { code, message, details: self.details.clone() }
+    }
+}
+
+/// The status codes of gRPC, sent in the `grpc-status` trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrpcCode {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+    Unauthenticated = 16,
+}
+
+const GRPC_CODES: [(GrpcCode, &str); 17] = [
+    (GrpcCode::Ok, "OK"),
+    (GrpcCode::Cancelled, "CANCELLED"),
+    (GrpcCode::Unknown, "UNKNOWN"),
+    (GrpcCode::InvalidArgument, "INVALID_ARGUMENT"),
+    (GrpcCode::DeadlineExceeded, "DEADLINE_EXCEEDED"),
+    (GrpcCode::NotFound, "NOT_FOUND"),
+    (GrpcCode::AlreadyExists, "ALREADY_EXISTS"),
+    (GrpcCode::PermissionDenied, "PERMISSION_DENIED"),
+    (GrpcCode::ResourceExhausted, "RESOURCE_EXHAUSTED"),
+    (GrpcCode::FailedPrecondition, "FAILED_PRECONDITION"),
+    (GrpcCode::Aborted, "ABORTED"),
+    (GrpcCode::OutOfRange, "OUT_OF_RANGE"),
+    (GrpcCode::Unimplemented, "UNIMPLEMENTED"),
+    (GrpcCode::Internal, "INTERNAL"),
+    (GrpcCode::Unavailable, "UNAVAILABLE"),
+    (GrpcCode::DataLoss, "DATA_LOSS"),
+    (GrpcCode::Unauthenticated, "UNAUTHENTICATED"),
+];
+
+impl GrpcCode {
+    /// The code gRPC clients read from an HTTP status, for mocks that only set `status`.
+    pub fn from_http_status(status: u16) -> Self {
+        match status {
+            200..=299 => GrpcCode::Ok,
+            400 => GrpcCode::Internal,
+            401 => GrpcCode::Unauthenticated,
+            403 => GrpcCode::PermissionDenied,
+            404 => GrpcCode::Unimplemented,
+            429 | 502..=504 => GrpcCode::Unavailable,
+            _ => GrpcCode::Unknown,
+        }
+    }
+
+    /// The HTTP status a JSON gateway answers a failed call with.
+    pub fn to_http_status(self) -> u16 {
+        match self {
+            GrpcCode::Ok => 200,
+            GrpcCode::Cancelled => 499,
+            GrpcCode::InvalidArgument | GrpcCode::FailedPrecondition | GrpcCode::OutOfRange => 400,
+            GrpcCode::DeadlineExceeded => 504,
+            GrpcCode::NotFound => 404,
+            GrpcCode::AlreadyExists | GrpcCode::Aborted => 409,
+            GrpcCode::PermissionDenied => 403,
+            GrpcCode::ResourceExhausted => 429,
+            GrpcCode::Unimplemented => 501,
+            GrpcCode::Unavailable => 503,
+            GrpcCode::Unauthenticated => 401,
+            GrpcCode::Unknown | GrpcCode::Internal | GrpcCode::DataLoss => 500,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        GRPC_CODES[self as usize].1
+    }
+}
+
+impl fmt::Display for GrpcCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parses a code by name (`UNAVAILABLE`, in any case) or by number (`14`).
+impl FromStr for GrpcCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        GRPC_CODES.iter()
+            .find(|(code, name)| name.eq_ignore_ascii_case(s) || s.parse::<usize>().is_ok_and(|number| number == *code as usize))
+            .map(|(code, _)| *code)
+            .ok_or_else(|| format!("unknown gRPC status code \"{}\"", s))
+    }
+}
+
+impl Serialize for GrpcCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for GrpcCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::String(name) => name.parse().map_err(serde::de::Error::custom),
+            Value::Number(number) => number.to_string().parse().map_err(serde::de::Error::custom),
+            other => Err(serde::de::Error::custom(format!("invalid gRPC status code {}", other))),
+        }
+    }
+}
+
+/// A gRPC status: the code and message a call ends with, and the error details sent
+/// with them as a `google.rpc.Status`.
+#[derive(Debug, Clone)]
+pub struct GrpcStatus {
+    pub code: GrpcCode,
+    pub message: String,
+    pub details: Vec<Value>,
+}
+
+impl GrpcStatus {
+    pub fn new(code: GrpcCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), details: Vec::new() }
+    }
+}
@@ -4,6 +4,8 @@ pub mod auth;
 pub mod graphql;
 pub mod multipart;
 pub mod grpc;
+pub mod grpc_reflection;
+pub mod oidc;
 
 pub use endpoint::*;
 pub use errors::*;
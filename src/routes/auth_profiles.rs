@@ -0,0 +1,37 @@
+use std::convert::Infallible;
+use warp::{reply, Filter, Rejection, Reply};
+use crate::middlewares::credentials::{hash_profile, redact};
+use crate::models::{AuthProfile, AuthProfiles, NotFound};
+
+/// Lists the profiles with their passwords, tokens and keys redacted.
+pub async fn list_auth_profiles(profiles: AuthProfiles) -> Result<impl Reply, Rejection> {
+    let mut profiles_json = serde_json::to_value(&*profiles.lock().await).unwrap_or_default();
+    redact(&mut profiles_json);
+    Ok(reply::json(&profiles_json))
+}
+
+/// Creates or replaces a profile; every endpoint referencing it picks up the change immediately.
+pub async fn put_auth_profile(name: String, profile: AuthProfile, profiles: AuthProfiles) -> Result<impl Reply, Rejection> {
+    let decoded_name = urlencoding::decode(&name)
+        .map_err(|_| warp::reject::custom(NotFound))?
+        .into_owned();
+
+    profiles.lock().await.insert(decoded_name, hash_profile(profile));
+    Ok(reply::json(&"Profile saved successfully"))
+}
+
+pub async fn delete_auth_profile(name: String, profiles: AuthProfiles) -> Result<impl Reply, Rejection> {
+    let decoded_name = urlencoding::decode(&name)
+        .map_err(|_| warp::reject::custom(NotFound))?
+        .into_owned();
+
+    if profiles.lock().await.remove(&decoded_name).is_some() {
+        return Ok(reply::with_status("Deleted successfully", warp::http::StatusCode::OK));
+    }
+
+    Err(warp::reject::custom(NotFound))
+}
+
+pub fn with_auth_profiles(profiles: AuthProfiles) -> impl Filter<Extract = (AuthProfiles,), Error = Infallible> + Clone {
+    warp::any().map(move || profiles.clone())
+}
@@ -1,50 +1,73 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use warp::{Rejection, Reply};
 use warp::http::header::CONTENT_TYPE;
-use warp::http::{HeaderValue, Method, Response, StatusCode};
+use warp::http::{HeaderMap, HeaderValue, Method, Response, StatusCode};
 use warp::path::FullPath;
 use warp::hyper::body::Bytes;
-use crate::handlers::graphql::{handle_graphql};
+use crate::handlers::graphql::{handle_graphql, read_graphql_body, read_graphql_query_params};
+use crate::handlers::graphql_schema::GraphQLSchema;
 use crate::handlers::grpc::{handle_grpc};
 use crate::handlers::params::{get_body_from_request, get_params_from_request};
 use crate::middlewares::authentication::{validate_auth};
 use crate::middlewares::dynamic_vars;
 use crate::middlewares::grpc_registry::GrpcRegistry;
-use crate::models::{Endpoint, Endpoints, InvalidGraphQLRequest, MethodNotAllowed, NotFound, Unauthorized};
-use crate::middlewares::rate_limit::{check_rate_limit, RateLimitTracker};
-use crate::utils::{add_possible_delay, reconstruct_full_url};
+use crate::middlewares::persisted_queries::PersistedQueries;
+use crate::models::{AuthError, AuthProfiles, Endpoint, EndpointKind, Endpoints, Forbidden, InvalidGraphQLRequest, MethodNotAllowed, NotFound, Principal, Unauthorized};
+use crate::middlewares::rate_limit::{apply_rate_limit_headers, check_rate_limit, global_rate_limit_key, most_restrictive, rate_limit_key, RateLimitTracker};
+use crate::utils::{add_possible_delay, find_endpoint, reconstruct_full_url};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn serve_dynamic_response(
     method: Method,
     path: FullPath,
     query_params: Option<HashMap<String, String>>,
-    auth_header: Option<String>,
+    headers: HeaderMap,
+    remote: Option<SocketAddr>,
     endpoints: Endpoints,
+    auth_profiles: AuthProfiles,
     rate_limiter: RateLimitTracker,
     body: Option<Bytes>,
     grpc_registry: Arc<GrpcRegistry>,
+    persisted_queries: PersistedQueries,
 ) -> Result<impl Reply, Rejection> {
     let full_url = reconstruct_full_url(path.as_str(), &query_params);
 
-    let endpoint = {
+    let (_, endpoint) = {
         let endpoints_map = endpoints.lock().await;
-        endpoints_map.get(&full_url).cloned()
+        let profiles = auth_profiles.lock().await;
+        find_endpoint(&endpoints_map, path.as_str(), &query_params, &profiles)
     }.ok_or_else(|| warp::reject::custom(NotFound))?;
 
     if !endpoint.method.iter().any(|m| m.eq_ignore_ascii_case(method.as_str())) {
         return Err(warp::reject::custom(MethodNotAllowed));
     }
 
-    if let Some(auth) = &endpoint.authentication {
-        if !validate_auth(Some(auth.clone()), auth_header) {
-            return Err(warp::reject::custom(Unauthorized));
+    let principal = match &endpoint.authentication {
+        Some(auth) => {
+            let profiles = auth_profiles.lock().await;
+            match validate_auth(Some(auth.clone()), &headers, &query_params, &profiles) {
+                Ok(principal) => Some(principal),
+                Err(AuthError::Unauthenticated(failure)) => return Err(warp::reject::custom(Unauthorized(failure))),
+                Err(AuthError::Forbidden(failure)) => return Err(warp::reject::custom(Forbidden(failure))),
+            }
         }
-    }
+        None => None,
+    };
 
-    check_rate_limit(path.as_str().to_string(), "GET", endpoint.rate_limit.as_ref(), rate_limiter.clone()).await?;
+    let mut rate_limit = None;
+    if let Some(global) = rate_limiter.global() {
+        let key = global_rate_limit_key(global, remote, &headers, principal.as_ref());
+        rate_limit = check_rate_limit(key, Some(global), rate_limiter.clone()).await?;
+    }
+    if let Some(limit) = &endpoint.rate_limit {
+        let key = rate_limit_key(path.as_str(), &method, limit, remote, &headers, principal.as_ref());
+        let decision = check_rate_limit(key, Some(limit), rate_limiter.clone()).await?;
+        rate_limit = most_restrictive(rate_limit, decision);
+    }
 
-    if let Some(_) = endpoint.delay {
+    if endpoint.delay.is_some() {
         add_possible_delay(&endpoint).await;
     }
 
@@ -54,34 +77,39 @@ pub async fn serve_dynamic_response(
         Err(_) => return Err(warp::reject::custom(NotFound)),
     };
 
-    // Try to Handle GraphQL or Grpc
-    if let Some(ref body_bytes) = body {
-        if let Ok(body_str) = std::str::from_utf8(body_bytes) {
-            // Try GraphQL
-            if let Some(response) = handle_graphql(body_str, &endpoint, &json_file_content) {
-                return Ok(response);
-            }
+    let body_str = body.as_deref().and_then(|bytes| std::str::from_utf8(bytes).ok());
+    let mut response = match endpoint.kind {
+        EndpointKind::GraphQL => {
+            // GraphQL comes as a body, or in the query string of a GET request
+            let payload = match body_str {
+                Some(body_str) if !body_str.trim().is_empty() => read_graphql_body(body_str),
+                _ if method == Method::GET => query_params.as_ref().and_then(read_graphql_query_params),
+                _ => None,
+            }.ok_or_else(|| warp::reject::custom(InvalidGraphQLRequest))?;
+            let graphql_schema = match &endpoint.graphql_schema {
+                Some(schema_file) => GraphQLSchema::load(schema_file).await,
+                None => None,
+            };
+            handle_graphql(payload, &endpoint, &json_file_content, graphql_schema.as_deref(), &persisted_queries).await
+        }
+        EndpointKind::Grpc => handle_grpc(body_str.unwrap_or_default(), grpc_registry).await?,
+        EndpointKind::Rest => {
+            let response_body = maybe_replace_variables(json_file_content, &endpoint, &full_url, body, principal.as_ref());
+            let status_code = StatusCode::from_u16(endpoint.status_code.unwrap_or(200))
+                .unwrap_or(StatusCode::NOT_FOUND);
 
-            // Try gRPC
-            if let Some(response) = handle_grpc(body_str, grpc_registry).await {
-                return Ok(response);
-            } else {
-                return Err(warp::reject::custom(InvalidGraphQLRequest));
-            }
+            Response::builder()
+                .status(status_code)
+                .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+                .body(response_body)
+                .unwrap()
         }
-    }
+    };
 
-    // Default path if not a GraphQL request
-    let response_body = maybe_replace_variables(json_file_content, &endpoint, &full_url, body);
-    let status_code = StatusCode::from_u16(endpoint.status_code.unwrap_or(200))
-        .unwrap_or(StatusCode::NOT_FOUND);
+    if let Some(decision) = &rate_limit {
+        apply_rate_limit_headers(response.headers_mut(), decision);
+    }
 
-    let response = Response::builder()
-        .status(status_code)
-        .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
-        .body(response_body.into())
-        .unwrap();
-    
     Ok(response)
 }
 
@@ -90,12 +118,17 @@ fn maybe_replace_variables(
     endpoint: &Endpoint,
     full_url: &str,
     body: Option<Bytes>,
+    principal: Option<&Principal>,
 ) -> String {
     if endpoint.with_dynamic_vars.unwrap_or(false) {
-        let params = match body {
-            Some(body) => get_body_from_request(body),
-            None => get_params_from_request(full_url),
-        };
+        // The fields of a JSON body join the path and query parameters
+        let mut params = get_params_from_request(full_url);
+        if let Some(body) = body.filter(|body| !body.is_empty()) {
+            params.extend(get_body_from_request(body));
+        }
+        if let Some(claims) = principal.and_then(|p| p.claims.as_ref()) {
+            params.extend(dynamic_vars::flatten_json("jwt", claims));
+        }
         data = dynamic_vars::replace_variables(&data, &params);
     }
     data
@@ -1,36 +1,56 @@
 use std::convert::Infallible;
 use std::sync::Arc;
 use warp::{reply, Filter, Rejection, Reply};
+use crate::handlers::graphql_schema::GraphQLSchema;
+use crate::middlewares::credentials::redact_auth_data;
 use crate::middlewares::grpc_registry::GrpcRegistry;
-use crate::models::{Endpoints, MultipartHandler, NotFound};
+use crate::models::{Endpoint, Endpoints, MultipartHandler, NotFound};
 
 pub async fn register_endpoint(form: warp::multipart::FormData, endpoints: Endpoints, grpc_registry: Arc<GrpcRegistry>) -> Result<impl Reply, Rejection> {
     MultipartHandler::parse(form, endpoints, grpc_registry).await?;
     Ok(reply::json(&"Registered successfully"))
 }
 
+/// Lists the registered endpoints, with the secrets of their authentication settings redacted.
 pub async fn list_endpoint(endpoints: Endpoints) -> Result<impl Reply, Rejection> {
-    let endpoints_map = endpoints.lock().await.clone();
+    let mut endpoints_map = endpoints.lock().await.clone();
+    for endpoint in endpoints_map.values_mut() {
+        endpoint.authentication = endpoint.authentication.as_deref().map(redact_auth_data);
+    }
     Ok(reply::json(&endpoints_map))
 }
 
-pub async fn delete_endpoint(path_to_delete: String, endpoints: Endpoints) -> Result<impl Reply, Rejection> {
+/// Deletes an endpoint, along with its files and the gRPC mock it registered.
+pub async fn delete_endpoint(path_to_delete: String, endpoints: Endpoints, grpc_registry: Arc<GrpcRegistry>) -> Result<impl Reply, Rejection> {
     let decoded_path = urlencoding::decode(&path_to_delete)
         .map_err(|_| warp::reject::custom(NotFound))?
         .into_owned();
 
     let mut endpoints_map = endpoints.lock().await;
     if let Some(endpoint) = endpoints_map.remove(&decoded_path) {
-        let file_path = format!("uploads/{}", endpoint.file);
-        if tokio::fs::remove_file(&file_path).await.is_err() {
-            log::info!("Failed to delete file: {}", file_path);
+        if let Some(key) = &endpoint.grpc {
+            grpc_registry.remove_mock(&key.service, &key.method, &key.matchers).await;
         }
+        remove_endpoint_files(endpoint).await;
         return Ok(reply::with_status("Deleted successfully", warp::http::StatusCode::OK));
     }
 
     Err(warp::reject::custom(NotFound))
 }
 
+pub(crate) async fn remove_endpoint_files(endpoint: Endpoint) {
+    let file_path = format!("uploads/{}", endpoint.file);
+    if tokio::fs::remove_file(&file_path).await.is_err() {
+        log::info!("Failed to delete file: {}", file_path);
+    }
+    if let Some(schema_file) = endpoint.graphql_schema {
+        GraphQLSchema::evict(&schema_file);
+        if tokio::fs::remove_file(&schema_file).await.is_err() {
+            log::info!("Failed to delete file: {}", schema_file);
+        }
+    }
+}
+
 pub fn with_endpoints(endpoints: Endpoints) -> impl Filter<Extract = (Endpoints,), Error = Infallible> + Clone {
     warp::any().map(move || endpoints.clone())
 }
\ No newline at end of file
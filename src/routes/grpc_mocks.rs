@@ -0,0 +1,47 @@
+use std::sync::Arc;
+use warp::{reply, Rejection, Reply};
+use crate::middlewares::grpc_registry::{validate_matchers, GrpcRegistry};
+use crate::models::{Endpoints, InvalidGrpcMatchers, NotFound};
+use crate::models::grpc::GrpcMockResponse;
+use crate::routes::endpoints::remove_endpoint_files;
+
+/// Lists the registered gRPC mocks, grouped by service then method.
+pub async fn list_grpc_mocks(grpc_registry: Arc<GrpcRegistry>) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&grpc_registry.list_mocks().await))
+}
+
+/// Adds a mock to a method, or replaces the one registered with the same matchers.
+pub async fn put_grpc_mock(service: String, method: String, mock: GrpcMockResponse, grpc_registry: Arc<GrpcRegistry>) -> Result<impl Reply, Rejection> {
+    let (service, method) = decode_method(&service, &method)?;
+    validate_matchers(&mock.matchers).map_err(|e| warp::reject::custom(InvalidGrpcMatchers(e)))?;
+
+    grpc_registry.register_mock(&service, &method, mock).await;
+    Ok(reply::json(&"Mock saved successfully"))
+}
+
+/// Removes every mock of a method, with the endpoints that registered them.
+pub async fn delete_grpc_mocks(service: String, method: String, endpoints: Endpoints, grpc_registry: Arc<GrpcRegistry>) -> Result<impl Reply, Rejection> {
+    let (service, method) = decode_method(&service, &method)?;
+    if grpc_registry.remove_method(&service, &method).await.is_empty() {
+        return Err(warp::reject::custom(NotFound));
+    }
+
+    let mut endpoints_map = endpoints.lock().await;
+    let owners: Vec<String> = endpoints_map.iter()
+        .filter(|(_, endpoint)| endpoint.grpc.as_ref().is_some_and(|key| key.service == service && key.method == method))
+        .map(|(path, _)| path.clone())
+        .collect();
+    for path in owners {
+        if let Some(endpoint) = endpoints_map.remove(&path) {
+            remove_endpoint_files(endpoint).await;
+        }
+    }
+    Ok(reply::with_status("Deleted successfully", warp::http::StatusCode::OK))
+}
+
+fn decode_method(service: &str, method: &str) -> Result<(String, String), Rejection> {
+    let decode = |value: &str| urlencoding::decode(value)
+        .map(|decoded| decoded.into_owned())
+        .map_err(|_| warp::reject::custom(NotFound));
+    Ok((decode(service)?, decode(method)?))
+}
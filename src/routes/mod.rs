@@ -1,2 +1,7 @@
 pub mod endpoints;
-pub mod dynamic_response;
\ No newline at end of file
+pub mod dynamic_response;
+pub mod oidc;
+pub mod auth_profiles;
+pub mod rate_limits;
+pub mod subscriptions;
+pub mod grpc_mocks;
\ No newline at end of file
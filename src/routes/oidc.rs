@@ -0,0 +1,265 @@
+use base64::{Engine as _, engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}};
+use ring::digest::{digest, SHA256};
+use serde_json::json;
+use warp::http::{header::LOCATION, StatusCode};
+use warp::{reply, Rejection, Reply};
+use crate::middlewares::oidc_provider::{oidc_provider, AuthorizationCode, RefreshGrant, ACCESS_TOKEN_TTL_SECS};
+use crate::models::oidc::{AuthorizeParams, OAuthError, TokenRequest, TokenResponse};
+
+/// Subject used when the approval page is submitted without a username.
+const DEFAULT_SUBJECT: &str = "mock-user";
+
+pub async fn openid_configuration() -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&oidc_provider().discovery()))
+}
+
+pub async fn jwks() -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&oidc_provider().jwks()))
+}
+
+/// Renders the auto-approve page, or redirects straight away when `prompt=none`.
+pub async fn authorize_page(params: AuthorizeParams) -> Result<Box<dyn Reply>, Rejection> {
+    if params.prompt.as_deref() == Some("none") {
+        return approve(params).await;
+    }
+
+    let hidden: String = [
+        ("response_type", &params.response_type),
+        ("client_id", &params.client_id),
+        ("redirect_uri", &params.redirect_uri),
+        ("scope", &params.scope),
+        ("state", &params.state),
+        ("nonce", &params.nonce),
+        ("code_challenge", &params.code_challenge),
+        ("code_challenge_method", &params.code_challenge_method),
+    ].iter()
+        .filter_map(|(name, value)| value.as_ref().map(|value| {
+            format!(r#"<input type="hidden" name="{}" value="{}">"#, name, html_escape(value))
+        }))
+        .collect();
+
+    let username = params.login_hint.as_deref().unwrap_or(DEFAULT_SUBJECT);
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><title>mockiapi - Sign in</title></head>
+<body>
+  <h1>mockiapi identity provider</h1>
+  <p><b>{}</b> is requesting access ({}).</p>
+  <form method="post" action="/oauth/authorize">
+    {}
+    <label>Username <input name="username" value="{}"></label>
+    <button type="submit">Approve</button>
+  </form>
+</body>
+</html>"#,
+        html_escape(params.client_id.as_deref().unwrap_or("unknown client")),
+        html_escape(params.scope.as_deref().unwrap_or("no scope")),
+        hidden,
+        html_escape(username),
+    );
+
+    Ok(Box::new(reply::html(page)))
+}
+
+/// Issues an authorization code and redirects back to the client.
+pub async fn approve(params: AuthorizeParams) -> Result<Box<dyn Reply>, Rejection> {
+    let Some(redirect_uri) = params.redirect_uri.clone() else {
+        return Ok(oauth_error("invalid_request", "redirect_uri is required"));
+    };
+    if params.response_type.as_deref() != Some("code") {
+        return Ok(oauth_error("unsupported_response_type", "Only response_type=code is supported"));
+    }
+
+    let subject = params.username
+        .or(params.login_hint)
+        .filter(|u| !u.is_empty())
+        .unwrap_or_else(|| DEFAULT_SUBJECT.to_string());
+
+    let code = oidc_provider().issue_code(AuthorizationCode {
+        client_id: params.client_id.unwrap_or_default(),
+        redirect_uri: Some(redirect_uri.clone()),
+        subject,
+        scope: params.scope,
+        nonce: params.nonce,
+        code_challenge: params.code_challenge,
+        code_challenge_method: params.code_challenge_method,
+        expires_at: 0,
+    }).await;
+
+    let mut query = vec![("code", code)];
+    if let Some(state) = params.state {
+        query.push(("state", state));
+    }
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+    let location = format!("{}{}{}", redirect_uri, separator,
+                           serde_urlencoded::to_string(&query).unwrap_or_default());
+
+    Ok(Box::new(reply::with_header(
+        reply::with_status(reply(), StatusCode::FOUND),
+        LOCATION,
+        location,
+    )))
+}
+
+/// Token endpoint supporting `client_credentials`, `password`, `authorization_code`
+/// and `refresh_token` grants. Any client secret and password is accepted.
+pub async fn token(form: TokenRequest, auth_header: Option<String>) -> Result<Box<dyn Reply>, Rejection> {
+    let provider = oidc_provider();
+    let client_id = form.client_id.clone()
+        .or_else(|| basic_client_id(auth_header.as_deref()))
+        .unwrap_or_default();
+
+    let Some(grant_type) = form.grant_type.as_deref().filter(|g| !g.is_empty()) else {
+        return Ok(oauth_error("invalid_request", "grant_type is required"));
+    };
+    match grant_type {
+        "client_credentials" => {
+            Ok(issue_tokens(&client_id, &client_id, form.scope, form.audience, None, false).await)
+        }
+        "password" => {
+            let Some(username) = form.username.filter(|u| !u.is_empty()) else {
+                return Ok(oauth_error("invalid_request", "username is required"));
+            };
+            Ok(issue_tokens(&client_id, &username, form.scope, form.audience, None, true).await)
+        }
+        "authorization_code" => {
+            let Some(grant) = provider.take_code(form.code.as_deref().unwrap_or_default()).await else {
+                return Ok(oauth_error("invalid_grant", "Unknown or expired authorization code"));
+            };
+            if client_id != grant.client_id {
+                return Ok(oauth_error("invalid_grant", "The authorization code was issued to another client"));
+            }
+            if form.redirect_uri.is_some() && form.redirect_uri != grant.redirect_uri {
+                return Ok(oauth_error("invalid_grant", "redirect_uri does not match the authorization request"));
+            }
+            if !pkce_matches(&grant, form.code_verifier.as_deref()) {
+                return Ok(oauth_error("invalid_grant", "PKCE verification failed"));
+            }
+            Ok(issue_tokens(&grant.client_id, &grant.subject, grant.scope, form.audience, grant.nonce, true).await)
+        }
+        "refresh_token" => {
+            let token = form.refresh_token.as_deref().unwrap_or_default();
+            let Some(grant) = provider.take_refresh_token(token).await else {
+                return Ok(oauth_error("invalid_grant", "Unknown refresh token"));
+            };
+            let scope = form.scope.or(grant.scope);
+            Ok(issue_tokens(&grant.client_id, &grant.subject, scope, form.audience, None, true).await)
+        }
+        other => Ok(oauth_error("unsupported_grant_type", &format!("Unsupported grant_type '{}'", other))),
+    }
+}
+
+/// Returns the claims of a valid access token issued by the provider.
+pub async fn userinfo(auth_header: Option<String>) -> Result<Box<dyn Reply>, Rejection> {
+    let provider = oidc_provider();
+    let claims = auth_header
+        .as_deref()
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .and_then(|token| {
+            let header = jsonwebtoken::decode_header(token).ok()?;
+            let key = provider.decoding_key(header.kid.as_deref())?;
+            let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256);
+            validation.validate_aud = false;
+            validation.set_issuer(&[&provider.issuer]);
+            jsonwebtoken::decode::<serde_json::Value>(token, &key, &validation).ok()
+        });
+
+    match claims {
+        Some(data) => Ok(Box::new(reply::json(&json!({
+            "sub": data.claims["sub"],
+            "preferred_username": data.claims["sub"],
+        })))),
+        None => Ok(Box::new(reply::with_status(
+            reply::json(&OAuthError { error: "invalid_token", error_description: "Missing or invalid access token".into() }),
+            StatusCode::UNAUTHORIZED,
+        ))),
+    }
+}
+
+async fn issue_tokens(
+    client_id: &str,
+    subject: &str,
+    scope: Option<String>,
+    audience: Option<String>,
+    nonce: Option<String>,
+    with_refresh: bool,
+) -> Box<dyn Reply> {
+    let provider = oidc_provider();
+    let audience = audience.unwrap_or_else(|| client_id.to_string());
+
+    let mut claims = json!({
+        "sub": subject,
+        "aud": audience,
+        "client_id": client_id,
+    });
+    if let Some(scope) = &scope {
+        claims["scope"] = json!(scope);
+    }
+    let access_token = provider.sign(claims);
+
+    let is_openid = scope.as_deref()
+        .is_some_and(|s| s.split_whitespace().any(|s| s == "openid"));
+    let id_token = is_openid.then(|| {
+        let mut claims = json!({ "sub": subject, "aud": client_id });
+        if let Some(nonce) = nonce {
+            claims["nonce"] = json!(nonce);
+        }
+        provider.sign(claims)
+    });
+
+    let refresh_token = if with_refresh {
+        Some(provider.issue_refresh_token(RefreshGrant {
+            client_id: client_id.to_string(),
+            subject: subject.to_string(),
+            scope: scope.clone(),
+            expires_at: 0,
+        }).await)
+    } else {
+        None
+    };
+
+    Box::new(reply::json(&TokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: ACCESS_TOKEN_TTL_SECS,
+        refresh_token,
+        id_token,
+        scope,
+    }))
+}
+
+fn pkce_matches(grant: &AuthorizationCode, verifier: Option<&str>) -> bool {
+    let Some(challenge) = &grant.code_challenge else {
+        return true;
+    };
+    let Some(verifier) = verifier else {
+        return false;
+    };
+
+    match grant.code_challenge_method.as_deref() {
+        Some("S256") => URL_SAFE_NO_PAD.encode(digest(&SHA256, verifier.as_bytes())) == *challenge,
+        _ => verifier == challenge,
+    }
+}
+
+/// Client id from `client_secret_basic` authentication.
+fn basic_client_id(auth_header: Option<&str>) -> Option<String> {
+    let encoded = auth_header?.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+    decoded.split(':').next().map(String::from)
+}
+
+fn oauth_error(error: &'static str, description: &str) -> Box<dyn Reply> {
+    Box::new(reply::with_status(
+        reply::json(&OAuthError { error, error_description: description.to_string() }),
+        StatusCode::BAD_REQUEST,
+    ))
+}
+
+fn html_escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,32 @@
+use std::time::Duration;
+use warp::{reply, Rejection, Reply};
+use crate::middlewares::rate_limit::RateLimitTracker;
+use crate::models::{ExhaustRateLimit, InvalidRateLimitRequest, NotFound};
+
+/// Lists the tracked rate limit counters with their remaining allowance.
+pub async fn list_rate_limits(rate_limiter: RateLimitTracker) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&rate_limiter.windows().await))
+}
+
+/// Resets every counter, including pre-exhausted keys.
+pub async fn reset_rate_limits(rate_limiter: RateLimitTracker) -> Result<impl Reply, Rejection> {
+    let removed = rate_limiter.reset(None).await;
+    Ok(reply::json(&format!("Reset {} rate limit counters", removed)))
+}
+
+/// Resets one key, together with its per-client counters.
+pub async fn reset_rate_limit(key: String, rate_limiter: RateLimitTracker) -> Result<impl Reply, Rejection> {
+    let decoded_key = urlencoding::decode(&key)
+        .map_err(|_| warp::reject::custom(NotFound))?
+        .into_owned();
+
+    let removed = rate_limiter.reset(Some(&decoded_key)).await;
+    Ok(reply::json(&format!("Reset {} rate limit counters", removed)))
+}
+
+/// Forces a key to answer 429 for a while, to test how clients cope with throttling.
+pub async fn exhaust_rate_limit(request: ExhaustRateLimit, rate_limiter: RateLimitTracker) -> Result<impl Reply, Rejection> {
+    rate_limiter.exhaust(&request.key, Duration::from_millis(request.duration_ms)).await
+        .map_err(|reason| warp::reject::custom(InvalidRateLimitRequest(reason)))?;
+    Ok(reply::json(&format!("Rate limit {} exhausted for {} ms", request.key, request.duration_ms)))
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use serde_json::{json, Value};
+use warp::{reply, Rejection, Reply};
+use warp::http::{HeaderMap, HeaderValue};
+use warp::http::header::SEC_WEBSOCKET_PROTOCOL;
+use warp::path::{FullPath, Tail};
+use warp::ws::Ws;
+use crate::handlers::graphql_ws::{serve_graphql_ws, GRAPHQL_TRANSPORT_WS};
+use crate::middlewares::authentication::validate_auth;
+use crate::middlewares::subscriptions::{subscription_key, SubscriptionHub};
+use crate::models::{AuthError, AuthProfiles, EndpointKind, Endpoints, Forbidden, InvalidEndpointKind, NotFound, Unauthorized, UnsupportedSubprotocol};
+use crate::utils::find_endpoint;
+
+/// Upgrades a request to a registered GraphQL endpoint to a `graphql-transport-ws` connection.
+///
+/// The endpoint's authentication applies to the upgrade request. Clients offering only other
+/// subprotocols (e.g. the legacy `graphql-ws`) are refused; clients offering none are
+/// accepted without a `Sec-WebSocket-Protocol` in the answer.
+pub async fn upgrade_graphql_ws(
+    ws: Ws,
+    path: FullPath,
+    query_params: Option<HashMap<String, String>>,
+    headers: HeaderMap,
+    endpoints: Endpoints,
+    auth_profiles: AuthProfiles,
+    hub: SubscriptionHub,
+) -> Result<impl Reply, Rejection> {
+    let (_, endpoint) = {
+        let endpoints_map = endpoints.lock().await;
+        let profiles = auth_profiles.lock().await;
+        find_endpoint(&endpoints_map, path.as_str(), &query_params, &profiles)
+    }.ok_or_else(|| warp::reject::custom(NotFound))?;
+
+    if endpoint.kind != EndpointKind::GraphQL {
+        return Err(warp::reject::custom(InvalidEndpointKind(format!("{} is not a GraphQL endpoint", path.as_str()))));
+    }
+
+    let offered = headers.get_all(SEC_WEBSOCKET_PROTOCOL).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|protocol| !protocol.is_empty())
+        .collect::<Vec<_>>();
+    if !offered.is_empty() && !offered.contains(&GRAPHQL_TRANSPORT_WS) {
+        return Err(warp::reject::custom(UnsupportedSubprotocol(offered.join(", "))));
+    }
+
+    if let Some(auth) = &endpoint.authentication {
+        let profiles = auth_profiles.lock().await;
+        match validate_auth(Some(auth.clone()), &headers, &query_params, &profiles) {
+            Ok(_) => {}
+            Err(AuthError::Unauthenticated(failure)) => return Err(warp::reject::custom(Unauthorized(failure))),
+            Err(AuthError::Forbidden(failure)) => return Err(warp::reject::custom(Forbidden(failure))),
+        }
+    }
+
+    let path = path.as_str().to_string();
+    let upgrade = ws.on_upgrade(move |socket| serve_graphql_ws(socket, path, endpoint, hub));
+    let mut response = upgrade.into_response();
+    if !offered.is_empty() {
+        response.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(GRAPHQL_TRANSPORT_WS));
+    }
+    Ok(response)
+}
+
+/// Pushes an ad-hoc event (`data`, `errors`, ...) to every subscriber of an operation on one
+/// endpoint. The tail is the endpoint path followed by the operation, e.g. `api/graphql/onPrice`.
+pub async fn push_subscription_event(tail: Tail, event: Value, hub: SubscriptionHub) -> Result<impl Reply, Rejection> {
+    let (path, operation) = tail.as_str().rsplit_once('/')
+        .ok_or_else(|| warp::reject::custom(NotFound))?;
+    let decode = |segment: &str| urlencoding::decode(segment)
+        .map(|decoded| decoded.into_owned())
+        .map_err(|_| warp::reject::custom(NotFound));
+    let (path, operation) = (format!("/{}", decode(path)?), decode(operation)?);
+
+    let delivered = hub.publish(&subscription_key(&path, &operation), event).await;
+    Ok(reply::json(&json!({ "path": path, "operation": operation, "delivered": delivered })))
+}
+
+/// Lists the endpoints and operations with open subscriptions and how many subscribers each has.
+pub async fn list_subscriptions(hub: SubscriptionHub) -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&hub.counts().await))
+}
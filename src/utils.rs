@@ -3,10 +3,15 @@ use std::time::Duration;
 use tokio::time::sleep;
 use log::info;
 use warp::{Filter, Rejection, Reply};
-use warp::http::{Response, StatusCode};
+use warp::http::{HeaderValue, Response, StatusCode};
+use warp::http::header::{CONTENT_TYPE, WWW_AUTHENTICATE};
+use serde_json::Value;
 use warp::hyper::Body;
-use crate::models::{Endpoint, InvalidGraphQLRequest, MethodNotAllowed, NotFound, RateLimited, Unauthorized};
-use crate::middlewares::rate_limit::RateLimitTracker;
+use crate::models::{AdminUnauthorized, AuthFailure, AuthProfile, Endpoint, Forbidden, InvalidEndpointKind, InvalidGraphQLRequest, InvalidGraphQLSchema, InvalidGrpcMatchers, InvalidGrpcRequest, InvalidProtoDescriptors, InvalidRateLimitRequest, MethodNotAllowed, NotFound, RateLimited, Unauthorized, UnsupportedSubprotocol};
+use crate::middlewares::authentication::api_key_query_param;
+use crate::middlewares::rate_limit::{apply_rate_limit_headers, RateLimitTracker};
+use crate::middlewares::subscriptions::SubscriptionHub;
+use crate::middlewares::persisted_queries::PersistedQueries;
 
 /// Adds a delay to the request handling if the `Endpoint` specifies a delay.
 ///
@@ -17,7 +22,7 @@ use crate::middlewares::rate_limit::RateLimitTracker;
 /// # Arguments
 ///
 /// * `endpoint` - A reference to an `Endpoint` object that contains configuration data,
-/// including an optional delay in milliseconds.
+///   including an optional delay in milliseconds.
 ///
 /// # Notes
 /// This function uses `tokio::time::sleep` to asynchronously wait, so it does not block
@@ -51,6 +56,19 @@ pub fn with_rate_limiter(rate_limiter: RateLimitTracker) ->
     warp::any().map(move || rate_limiter.clone())
 }
 
+/// Creates a `warp` filter that provides access to the shared `SubscriptionHub`, so that
+/// WebSocket connections and the admin push route reach the same subscribers.
+pub fn with_subscriptions(hub: SubscriptionHub) ->
+           impl Filter<Extract = (SubscriptionHub,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || hub.clone())
+}
+
+/// Creates a `warp` filter that provides access to the shared store of persisted queries.
+pub fn with_persisted_queries(persisted_queries: PersistedQueries) ->
+           impl Filter<Extract = (PersistedQueries,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || persisted_queries.clone())
+}
+
 /// Reconstructs the full URL by appending query parameters to the base path.
 ///
 /// # Arguments
@@ -60,22 +78,85 @@ pub fn with_rate_limiter(rate_limiter: RateLimitTracker) ->
 /// # Returns
 /// * A `String` representing the full URL with query parameters if any exist.
 pub fn reconstruct_full_url(path: &str, query_params: &Option<HashMap<String, String>>) -> String {
-    if let Some(params) = query_params {
-        if !params.is_empty() {
-            let query_string = serde_urlencoded::to_string(params).unwrap_or_default();
-            return format!("{}?{}", path, query_string);
-        }
+    if let Some(params) = query_params
+        && !params.is_empty() {
+        let query_string = serde_urlencoded::to_string(params).unwrap_or_default();
+        return format!("{}?{}", path, query_string);
     }
     path.to_string()
 }
 
+/// Finds the endpoint registered for a request path and its query parameters.
+///
+/// An exact match on the reconstructed URL wins. Otherwise an endpoint registered for the
+/// same path matches when the request has exactly its query parameters, in any order, leaving
+/// out the query parameter the endpoint reads its API key from (see `api_key_query_param`).
+/// When several endpoints qualify, the most specific one (with the most query parameters) is
+/// returned; among equally specific ones, the longest registration, then the first in
+/// alphabetical order.
+///
+/// # Arguments
+/// * `endpoints` - The registered endpoints, keyed by path (optionally with a query string).
+/// * `path` - The request path.
+/// * `query_params` - The request query parameters, if any.
+/// * `profiles` - The auth profiles, for endpoints that take their API key settings from one.
+///
+/// # Returns
+/// * The key the endpoint is registered under together with the endpoint itself.
+pub fn find_endpoint(
+    endpoints: &HashMap<String, Endpoint>,
+    path: &str,
+    query_params: &Option<HashMap<String, String>>,
+    profiles: &HashMap<String, AuthProfile>,
+) -> Option<(String, Endpoint)> {
+    let full_url = reconstruct_full_url(path, query_params);
+    if let Some(endpoint) = endpoints.get(&full_url) {
+        return Some((full_url, endpoint.clone()));
+    }
+
+    let empty = HashMap::new();
+    let request_params = query_params.as_ref().unwrap_or(&empty);
+
+    endpoints.iter()
+        .filter_map(|(key, endpoint)| {
+            let (registered_path, registered_query) = key.split_once('?').unwrap_or((key, ""));
+            if registered_path != path {
+                return None;
+            }
+            let registered: HashMap<String, String> = serde_urlencoded::from_str(registered_query).ok()?;
+            let api_key_param = endpoint.authentication.as_deref()
+                .and_then(|auth| api_key_query_param(auth, profiles));
+            let presented: HashMap<&String, &String> = request_params.iter()
+                .filter(|(k, _)| api_key_param.as_ref() != Some(*k) || registered.contains_key(*k))
+                .collect();
+            (presented.len() == registered.len() && registered.iter().all(|(k, v)| presented.get(k) == Some(&v)))
+                .then_some((registered.len(), key, endpoint))
+        })
+        .max_by(|(a_specificity, a_key, _), (b_specificity, b_key, _)| {
+            a_specificity.cmp(b_specificity)
+                .then(a_key.len().cmp(&b_key.len()))
+                .then(b_key.cmp(a_key))
+        })
+        .map(|(_, key, endpoint)| (key.clone(), endpoint.clone()))
+}
+
 /// Handles rejections in the Warp web framework by returning appropriate HTTP responses.
 ///
 /// This function inspects the provided `Rejection` and determines the appropriate HTTP status
 /// and response body. It handles the following rejection types:
 ///
-/// - `Unauthorized`: Returns a `401 Unauthorized` response.
-/// - `RateLimited`: Returns a `429 Too Many Requests` response.
+/// - `Unauthorized`: Returns a `401 Unauthorized` response with its `WWW-Authenticate` challenges.
+/// - `AdminUnauthorized`: Returns a `401 Unauthorized` response with a `WWW-Authenticate` challenge.
+/// - `Forbidden`: Returns a `403 Forbidden` response.
+/// - `RateLimited`: Returns a `429 Too Many Requests` response with `Retry-After` and rate limit headers.
+/// - `InvalidGraphQLSchema`: Returns a `400 Bad Request` response with the parse error.
+/// - `InvalidProtoDescriptors`: Returns a `400 Bad Request` response with the load error.
+/// - `InvalidGrpcMatchers`: Returns a `400 Bad Request` response with the parse error.
+/// - `InvalidEndpointKind`: Returns a `400 Bad Request` response with the reason.
+/// - `InvalidGraphQLRequest`: Returns a `400 Bad Request` response.
+/// - `InvalidGrpcRequest`: Returns a `400 Bad Request` response with the parse error.
+/// - `InvalidRateLimitRequest`: Returns a `400 Bad Request` response with the reason.
+/// - `UnsupportedSubprotocol`: Returns a `400 Bad Request` response with the offered subprotocols.
 /// - `NotFound`: Returns a `404 Not Found` response.
 /// - Any other rejection is propagated unchanged.
 ///
@@ -91,17 +172,20 @@ pub fn reconstruct_full_url(path: &str, query_params: &Option<HashMap<String, St
 /// This ensures that if an error occurs during request processing, `handle_rejection`
 /// will return an appropriate response instead of simply failing.
 pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
-    if err.find::<Unauthorized>().is_some() {
+    if let Some(Unauthorized(failure)) = err.find::<Unauthorized>() {
+        return Ok(auth_failure_response(StatusCode::UNAUTHORIZED, failure, "Unauthorized\n"));
+    } else if let Some(admin) = err.find::<AdminUnauthorized>() {
         let response: Response<Body> = Response::builder()
             .status(StatusCode::UNAUTHORIZED)
+            .header(WWW_AUTHENTICATE, admin.challenge)
             .body(Body::from("Unauthorized\n"))
             .unwrap();
         return Ok(response);
-    } else if err.find::<RateLimited>().is_some() {
-        let response: Response<Body> = Response::builder()
-            .status(StatusCode::TOO_MANY_REQUESTS)
-            .body(Body::from("Rate limit exceeded\n"))
-            .unwrap();
+    } else if let Some(Forbidden(failure)) = err.find::<Forbidden>() {
+        return Ok(auth_failure_response(StatusCode::FORBIDDEN, failure, "Forbidden\n"));
+    } else if let Some(limited) = err.find::<RateLimited>() {
+        let mut response = custom_body_response(StatusCode::TOO_MANY_REQUESTS, &limited.body, "Rate limit exceeded\n");
+        apply_rate_limit_headers(response.headers_mut(), &limited.decision);
         return Ok(response);
     } else if err.find::<NotFound>().is_some() {
         let response: Response<Body> = Response::builder()
@@ -115,13 +199,84 @@ pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
             .body(Body::from("Method not allowed\n"))
             .unwrap();
         return Ok(response);
+    } else if let Some(InvalidGraphQLSchema(reason)) = err.find::<InvalidGraphQLSchema>() {
+        let response: Response<Body> = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(format!("Invalid GraphQL schema: {}\n", reason)))
+            .unwrap();
+        return Ok(response);
+    } else if let Some(InvalidProtoDescriptors(reason)) = err.find::<InvalidProtoDescriptors>() {
+        let response: Response<Body> = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(format!("Invalid protobuf descriptors: {}\n", reason)))
+            .unwrap();
+        return Ok(response);
+    } else if let Some(InvalidGrpcMatchers(reason)) = err.find::<InvalidGrpcMatchers>() {
+        let response: Response<Body> = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(format!("Invalid gRPC matchers: {}\n", reason)))
+            .unwrap();
+        return Ok(response);
+    } else if let Some(InvalidEndpointKind(reason)) = err.find::<InvalidEndpointKind>() {
+        let response: Response<Body> = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(format!("Invalid endpoint kind: {}\n", reason)))
+            .unwrap();
+        return Ok(response);
     } else if err.find::<InvalidGraphQLRequest>().is_some() {
         let response: Response<Body> = Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::from("Invalid gRPC request\n"))
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("Invalid GraphQL request\n"))
+            .unwrap();
+        return Ok(response);
+    } else if let Some(InvalidGrpcRequest(reason)) = err.find::<InvalidGrpcRequest>() {
+        let response: Response<Body> = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(format!("Invalid gRPC request: {}\n", reason)))
+            .unwrap();
+        return Ok(response);
+    } else if let Some(InvalidRateLimitRequest(reason)) = err.find::<InvalidRateLimitRequest>() {
+        let response: Response<Body> = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(format!("Invalid rate limit request: {}\n", reason)))
+            .unwrap();
+        return Ok(response);
+    } else if let Some(UnsupportedSubprotocol(offered)) = err.find::<UnsupportedSubprotocol>() {
+        let response: Response<Body> = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(format!("Unsupported WebSocket subprotocol: {} (expected graphql-transport-ws)\n", offered)))
             .unwrap();
         return Ok(response);
     }
 
     Err(err)
-}
\ No newline at end of file
+}
+
+/// Builds a 401/403 response from an authentication failure.
+///
+/// Every challenge becomes its own `WWW-Authenticate` header.
+fn auth_failure_response(status: StatusCode, failure: &AuthFailure, default_body: &'static str) -> Response<Body> {
+    let mut response = custom_body_response(status, &failure.body, default_body);
+    for challenge in &failure.challenges {
+        if let Ok(value) = HeaderValue::from_str(challenge) {
+            response.headers_mut().append(WWW_AUTHENTICATE, value);
+        }
+    }
+    response
+}
+
+/// Builds an error response whose body may be customized on the endpoint: strings are
+/// sent as plain text, anything else as JSON.
+fn custom_body_response(status: StatusCode, custom: &Option<Value>, default_body: &'static str) -> Response<Body> {
+    let (content_type, body) = match custom {
+        Some(Value::String(text)) => ("text/plain; charset=utf-8", Body::from(text.clone())),
+        Some(json) => ("application/json", Body::from(json.to_string())),
+        None => ("text/plain; charset=utf-8", Body::from(default_body)),
+    };
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(body)
+        .unwrap()
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use warp::{http::StatusCode, test::request, Filter};
+use mockiapi::middlewares::admin::{with_admin_auth, AdminCredentials};
+use mockiapi::middlewares::credentials::{hash_auth_data, hash_password, verify_password};
+use mockiapi::middlewares::grpc_registry::GrpcRegistry;
+use mockiapi::models::{AuthProfile, AuthProfiles, Endpoint, EndpointKind};
+use mockiapi::models::grpc::{GrpcMatcher, GrpcMockKey, GrpcMockResponse};
+use mockiapi::routes::auth_profiles::{list_auth_profiles, put_auth_profile};
+use mockiapi::routes::endpoints::{delete_endpoint, list_endpoint, with_endpoints};
+use mockiapi::routes::grpc_mocks::{delete_grpc_mocks, list_grpc_mocks, put_grpc_mock};
+use mockiapi::utils::handle_rejection;
+
+#[test]
+fn test_password_hashing() {
+    let hashed = hash_password("s3cret");
+
+    assert!(hashed.starts_with("pbkdf2-sha256$"));
+    assert_ne!(hashed, hash_password("s3cret"));
+    assert_eq!(hash_password(&hashed), hashed);
+    assert!(verify_password(&hashed, "s3cret"));
+    assert!(!verify_password(&hashed, "wrong"));
+    assert!(verify_password("plain", "plain"));
+}
+
+#[tokio::test]
+async fn test_list_redacts_credentials() {
+    let auth = hash_auth_data(&json!({ "username": "admin", "password": "p4ss", "tokenData": "abc" }).to_string());
+    let mut endpoints_map = HashMap::new();
+    endpoints_map.insert(
+        "/secure".to_string(),
+        Endpoint {
+            method: vec!["GET".to_string()],
+            file: "uploads/file.json".to_string(),
+            status_code: Some(200),
+            rate_limit: None,
+            authentication: Some(auth.clone()),
+            delay: None,
+            with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
+        },
+    );
+    let endpoints = Arc::new(Mutex::new(endpoints_map));
+
+    let filter = warp::path!("list").and(with_endpoints(endpoints.clone())).and_then(list_endpoint);
+    let res = request().path("/list").reply(&filter).await;
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+
+    assert!(!body.contains("p4ss"));
+    assert!(!body.contains("abc"));
+    assert!(!body.contains("pbkdf2"));
+    let listed: Value = serde_json::from_str(&body).unwrap();
+    let listed_auth: Value = serde_json::from_str(listed["/secure"]["authentication"].as_str().unwrap()).unwrap();
+    assert_eq!(listed_auth["username"], "admin");
+    assert_eq!(listed_auth["password"], "********");
+
+    // The stored settings are untouched
+    assert_eq!(endpoints.lock().await["/secure"].authentication.as_deref(), Some(auth.as_str()));
+}
+
+#[tokio::test]
+async fn test_profiles_are_hashed_and_redacted() {
+    let profiles = AuthProfiles::default();
+    let profile: AuthProfile = serde_json::from_value(json!({
+        "users": [{ "username": "alice", "password": "alice-pw" }],
+        "apiKey": { "keys": ["k1", "k2"] }
+    })).unwrap();
+    put_auth_profile("team".to_string(), profile, profiles.clone()).await.unwrap();
+
+    let stored = profiles.lock().await["team"].clone();
+    assert!(verify_password(&stored.users[0].password, "alice-pw"));
+    assert_ne!(stored.users[0].password, "alice-pw");
+
+    let filter = warp::any().map(move || profiles.clone()).and_then(list_auth_profiles);
+    let listed: Value = serde_json::from_slice(request().reply(&filter).await.body()).unwrap();
+    assert_eq!(listed["team"]["users"][0]["password"], "********");
+    assert_eq!(listed["team"]["apiKey"]["keys"], json!(["********", "********"]));
+}
+
+#[tokio::test]
+async fn test_admin_credentials_protect_management_routes() {
+    let admin = AdminCredentials {
+        token: Some("admin-token".to_string()),
+        username: Some("root".to_string()),
+        password: Some("toor".to_string()),
+    };
+    let filter = warp::path!("list")
+        .and(with_admin_auth(admin))
+        .map(|| "listed")
+        .recover(handle_rejection);
+
+    let res = request().path("/list").reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.headers()["www-authenticate"], "Basic realm=\"mockiapi admin\"");
+
+    let res = request().path("/list").header("Authorization", "Bearer admin-token").reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let basic = format!("Basic {}", STANDARD.encode("root:toor"));
+    let res = request().path("/list").header("Authorization", basic).reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = request().path("/list").header("Authorization", "Bearer nope").reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let open = warp::path!("list").and(with_admin_auth(AdminCredentials::default())).map(|| "listed");
+    assert_eq!(request().path("/list").reply(&open).await.status(), StatusCode::OK);
+}
+
+fn grpc_endpoint(key: GrpcMockKey) -> Endpoint {
+    Endpoint {
+        method: vec!["POST".to_string()],
+        file: "missing.json".to_string(),
+        status_code: None,
+        rate_limit: None,
+        authentication: None,
+        delay: None,
+        with_dynamic_vars: None,
+        graphql_schema: None,
+        grpc: Some(key),
+        kind: EndpointKind::Grpc,
+    }
+}
+
+#[tokio::test]
+async fn test_grpc_mocks_lifecycle() {
+    let registry = Arc::new(GrpcRegistry::new());
+    let endpoints = Arc::new(Mutex::new(HashMap::new()));
+    let by_id = vec![GrpcMatcher { field: "id".into(), equals: Some(json!("b1")), regex: None }];
+
+    // Mocks registered by endpoints, and through the admin API
+    registry.register_mock("books.v1.BookService", "GetBook", GrpcMockResponse { output: json!({ "id": "b1" }), matchers: by_id.clone(), ..Default::default() }).await;
+    endpoints.lock().await.insert("/grpc/b1".to_string(), grpc_endpoint(GrpcMockKey {
+        service: "books.v1.BookService".into(),
+        method: "GetBook".into(),
+        matchers: by_id.clone(),
+    }));
+    let default: GrpcMockResponse = serde_json::from_value(json!({ "output": { "id": "any" }, "delay_ms": null, "status": null })).unwrap();
+    put_grpc_mock("books.v1.BookService".into(), "GetBook".into(), default, registry.clone()).await.unwrap();
+    put_grpc_mock("UserService".into(), "GetUser".into(), GrpcMockResponse::default(), registry.clone()).await.unwrap();
+
+    let list = warp::any().map({
+        let registry = registry.clone();
+        move || registry.clone()
+    }).and_then(list_grpc_mocks);
+    let listed: Value = serde_json::from_slice(request().reply(&list).await.body()).unwrap();
+    assert_eq!(listed["books.v1.BookService"]["GetBook"].as_array().unwrap().len(), 2);
+    assert_eq!(listed["books.v1.BookService"]["GetBook"][0]["matchers"][0]["field"], "id");
+    assert!(listed["UserService"]["GetUser"].is_array());
+
+    // Replacing keeps one mock per set of matchers
+    let replaced = GrpcMockResponse { output: json!({ "id": "b1", "title": "v2" }), matchers: by_id.clone(), ..Default::default() };
+    put_grpc_mock("books.v1.BookService".into(), "GetBook".into(), replaced, registry.clone()).await.unwrap();
+    let mock = registry.get_mock("books.v1.BookService", "GetBook", &json!({ "id": "b1" })).await.unwrap();
+    assert_eq!(mock.output["title"], "v2");
+    let invalid = GrpcMockResponse { matchers: vec![GrpcMatcher { field: "id".into(), equals: None, regex: Some("(".into()) }], ..Default::default() };
+    assert!(put_grpc_mock("UserService".into(), "GetUser".into(), invalid, registry.clone()).await.is_err());
+
+    // Deleting the endpoint removes its mock only
+    delete_endpoint("%2Fgrpc%2Fb1".into(), endpoints.clone(), registry.clone()).await.unwrap();
+    let mock = registry.get_mock("books.v1.BookService", "GetBook", &json!({ "id": "b1" })).await.unwrap();
+    assert_eq!(mock.output["id"], "any");
+
+    // Deleting the mocks of a method removes the endpoints owning them
+    endpoints.lock().await.insert("/grpc/users".to_string(), grpc_endpoint(GrpcMockKey {
+        service: "UserService".into(),
+        method: "GetUser".into(),
+        matchers: Vec::new(),
+    }));
+    delete_grpc_mocks("UserService".into(), "GetUser".into(), endpoints.clone(), registry.clone()).await.unwrap();
+    assert!(endpoints.lock().await.is_empty());
+    assert!(registry.get_mock("UserService", "GetUser", &json!({})).await.is_none());
+    assert!(delete_grpc_mocks("UserService".into(), "GetUser".into(), endpoints.clone(), registry.clone()).await.is_err());
+    assert_eq!(registry.list_mocks().await.keys().collect::<Vec<_>>(), ["books.v1.BookService"]);
+}
@@ -6,10 +6,12 @@ use std::time::{Duration};
 use serde_json::{json, Value};
 use tokio::time::Instant;
 use mockiapi::middlewares::grpc_registry::GrpcRegistry;
-use mockiapi::models::{Endpoint, RateLimit};
+use mockiapi::models::grpc::GrpcMockResponse;
+use mockiapi::middlewares::persisted_queries::new_persisted_queries;
+use mockiapi::models::{AuthProfiles, Endpoint, EndpointKind, RateLimit, RateLimitKey};
 use mockiapi::middlewares::rate_limit::{new_rate_limit};
 use mockiapi::routes::dynamic_response::{serve_dynamic_response};
-use mockiapi::utils::handle_rejection;
+use mockiapi::utils::{find_endpoint, handle_rejection, with_persisted_queries};
 
 #[tokio::test]
 async fn test_non_existent_endpoint() {
@@ -25,11 +27,14 @@ async fn test_non_existent_endpoint() {
     let filter = warp::method()
         .and(warp::path::full())
         .and(warp::any().map(|| None))
-        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
         .and(warp::any().map(move || rate_limiter.clone()))
         .and(warp::any().map(|| None))
         .and(registry_filter.clone())
+        .and(with_persisted_queries(new_persisted_queries()))
         .and_then(serve_dynamic_response)
         .recover(handle_rejection);
 
@@ -55,6 +60,9 @@ async fn test_valid_request_without_auth() {
             authentication: None,
             delay: None,
             with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
         },
     );
     let endpoints = Arc::new(Mutex::new(endpoints_map));
@@ -68,11 +76,14 @@ async fn test_valid_request_without_auth() {
     let filter = warp::method()
         .and(warp::path::full())
         .and(warp::any().map(|| None))
-        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
         .and(warp::any().map(move || rate_limiter.clone()))
         .and(warp::any().map(|| None))
         .and(registry_filter.clone())
+        .and(with_persisted_queries(new_persisted_queries()))
         .and_then(serve_dynamic_response)
         .recover(handle_rejection);
 
@@ -98,6 +109,9 @@ async fn test_valid_request_with_basic_auth() {
             authentication: Some(String::from("{\"username\": \"user\", \"password\": \"pass\"}")),
             delay: None,
             with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
         },
     );
     let endpoints = Arc::new(Mutex::new(endpoints_map));
@@ -111,11 +125,14 @@ async fn test_valid_request_with_basic_auth() {
     let filter = warp::method()
         .and(warp::path::full())
         .and(warp::any().map(|| None))
-        .and(warp::any().map(|| Some("Basic dXNlcjpwYXNz".to_string()))) // Base64 for user:pass
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
         .and(warp::any().map(move || rate_limiter.clone()))
         .and(warp::any().map(|| None))
         .and(registry_filter.clone())
+        .and(with_persisted_queries(new_persisted_queries()))
         .and_then(serve_dynamic_response);
 
     let res = request()
@@ -141,6 +158,9 @@ async fn test_invalid_authentication() {
             authentication: Some(String::from("{\"username\": \"user1\", \"password\": \"password1\"}")),
             delay: None,
             with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
         },
     );
     let endpoints = Arc::new(Mutex::new(endpoints_map));
@@ -154,11 +174,14 @@ async fn test_invalid_authentication() {
     let filter = warp::method()
         .and(warp::path::full())
         .and(warp::any().map(|| None))
-        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
         .and(warp::any().map(move || rate_limiter.clone()))
         .and(warp::any().map(|| None))
         .and(registry_filter.clone())
+        .and(with_persisted_queries(new_persisted_queries()))
         .and_then(serve_dynamic_response)
         .recover(handle_rejection);
 
@@ -185,6 +208,9 @@ async fn test_valid_request_with_bearer_auth() {
             authentication: Some(String::from("{\"tokenData\": \"SOME_LONG_TOKEN\"}")),
             delay: None,
             with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
         },
     );
     let endpoints = Arc::new(Mutex::new(endpoints_map));
@@ -198,11 +224,14 @@ async fn test_valid_request_with_bearer_auth() {
     let filter = warp::method()
         .and(warp::path::full())
         .and(warp::any().map(|| None))
-        .and(warp::any().map(|| Some("Bearer SOME_LONG_TOKEN".to_string())))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
         .and(warp::any().map(move || rate_limiter.clone()))
         .and(warp::any().map(|| None))
         .and(registry_filter.clone())
+        .and(with_persisted_queries(new_persisted_queries()))
         .and_then(serve_dynamic_response);
 
     let res = request()
@@ -227,10 +256,14 @@ async fn test_rate_limit_exceeded() {
             rate_limit: Some(RateLimit {
                 requests: 1,
                 window_ms: 1,
+                ..Default::default()
             }), // Allow only one request
             authentication: None,
             delay: None,
             with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
         },
     );
     let endpoints = Arc::new(Mutex::new(endpoints_map));
@@ -244,11 +277,14 @@ async fn test_rate_limit_exceeded() {
     let filter = warp::method()
         .and(warp::path::full())
         .and(warp::any().map(|| None))
-        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
         .and(warp::any().map(move || rate_limiter.clone()))
         .and(warp::any().map(|| None))
         .and(registry_filter.clone())
+        .and(with_persisted_queries(new_persisted_queries()))
         .and_then(serve_dynamic_response)
         .recover(handle_rejection);
     // First request should pass
@@ -282,6 +318,9 @@ async fn test_request_with_delay() {
             authentication: None,
             delay: Some(2000), // 2 seconds delay
             with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
         },
     );
     let endpoints = Arc::new(Mutex::new(endpoints_map));
@@ -298,11 +337,14 @@ async fn test_request_with_delay() {
             .map(Some) // Wrap in Some()
             .or(warp::any().map(|| None)) // Use None when no query params
             .unify())
-        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
         .and(warp::any().map(move || rate_limiter.clone()))
         .and(warp::any().map(|| None))
         .and(registry_filter.clone())
+        .and(with_persisted_queries(new_persisted_queries()))
         .and_then(serve_dynamic_response);
 
     let start_time = Instant::now();
@@ -330,6 +372,9 @@ async fn test_extract_params_from_request() {
             authentication: None,
             delay: None,
             with_dynamic_vars: Some(true),
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
         },
     );
     let endpoints = Arc::new(Mutex::new(endpoints_map));
@@ -343,11 +388,14 @@ async fn test_extract_params_from_request() {
     let filter = warp::method()
         .and(warp::path::full())
         .and(warp::query::<HashMap<String, String>>().map(Some))
-        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
         .and(warp::any().map(move || rate_limiter.clone()))
         .and(warp::any().map(|| None))
         .and(registry_filter.clone())
+        .and(with_persisted_queries(new_persisted_queries()))
         .and_then(serve_dynamic_response)
         .recover(handle_rejection);
 
@@ -381,6 +429,9 @@ async fn test_extract_params_from_request_body() {
             authentication: None,
             delay: None,
             with_dynamic_vars: Some(true),
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
         },
     );
     let endpoints = Arc::new(Mutex::new(endpoints_map));
@@ -394,13 +445,16 @@ async fn test_extract_params_from_request_body() {
     let filter = warp::method()
         .and(warp::path::full())
         .and(warp::query::<HashMap<String, String>>().map(Some))
-        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
         .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
         .and(warp::any().map(move || rate_limiter.clone()))
         .and(warp::body::bytes().map(Some)
             .or(warp::any().map(|| None)) // Use None when no query params
             .unify())
         .and(registry_filter.clone())
+        .and(with_persisted_queries(new_persisted_queries()))
         .and_then(serve_dynamic_response)
         .recover(handle_rejection);
 
@@ -427,4 +481,240 @@ async fn test_extract_params_from_request_body() {
     assert_eq!(json_body["item"], "456");
     assert_eq!(json_body["id"], "789");
     assert_eq!(json_body["name"], "John");
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_rate_limit_keyed_per_client_and_method() {
+    let mut endpoints_map = HashMap::new();
+    endpoints_map.insert(
+        "/per-client".to_string(),
+        Endpoint {
+            method: vec!["GET".to_string(), "DELETE".to_string()],
+            file: "uploads/file.json".to_string(),
+            status_code: Some(200),
+            rate_limit: Some(RateLimit {
+                requests: 1,
+                window_ms: 60_000,
+                key_by: vec![RateLimitKey::Ip, RateLimitKey::Header("X-Tenant".to_string())],
+                ..Default::default()
+            }),
+            authentication: None,
+            delay: None,
+            with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
+        },
+    );
+    let endpoints = Arc::new(Mutex::new(endpoints_map));
+    let rate_limiter = new_rate_limit();
+    let registry = Arc::new(GrpcRegistry::new());
+
+    let filter = warp::method()
+        .and(warp::path::full())
+        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
+        .and(warp::any().map(move || rate_limiter.clone()))
+        .and(warp::any().map(|| None))
+        .and(warp::any().map(move || Arc::clone(&registry)))
+        .and(with_persisted_queries(new_persisted_queries()))
+        .and_then(serve_dynamic_response)
+        .recover(handle_rejection);
+
+    let call = |method: &str, ip: [u8; 4], tenant: &str| {
+        request()
+            .method(method)
+            .path("/per-client")
+            .remote_addr((ip, 4000).into())
+            .header("X-Tenant", tenant)
+    };
+
+    assert_eq!(call("GET", [10, 0, 0, 1], "acme").reply(&filter).await.status(), StatusCode::OK);
+    assert_eq!(call("GET", [10, 0, 0, 1], "acme").reply(&filter).await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Another client, another tenant or another method each get their own counter
+    assert_eq!(call("GET", [10, 0, 0, 2], "acme").reply(&filter).await.status(), StatusCode::OK);
+    assert_eq!(call("GET", [10, 0, 0, 1], "globex").reply(&filter).await.status(), StatusCode::OK);
+    assert_eq!(call("DELETE", [10, 0, 0, 1], "acme").reply(&filter).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_rate_limit_headers_and_custom_body() {
+    let mut endpoints_map = HashMap::new();
+    endpoints_map.insert(
+        "/quota".to_string(),
+        Endpoint {
+            method: vec!["GET".to_string()],
+            file: "uploads/file.json".to_string(),
+            status_code: Some(200),
+            rate_limit: Some(RateLimit {
+                requests: 2,
+                window_ms: 60_000,
+                exceeded_body: Some(json!({ "error": "slow down" })),
+                ..Default::default()
+            }),
+            authentication: None,
+            delay: None,
+            with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
+        },
+    );
+    let endpoints = Arc::new(Mutex::new(endpoints_map));
+    let rate_limiter = new_rate_limit();
+    let registry = Arc::new(GrpcRegistry::new());
+
+    let filter = warp::method()
+        .and(warp::path::full())
+        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
+        .and(warp::any().map(move || rate_limiter.clone()))
+        .and(warp::any().map(|| None))
+        .and(warp::any().map(move || Arc::clone(&registry)))
+        .and(with_persisted_queries(new_persisted_queries()))
+        .and_then(serve_dynamic_response)
+        .recover(handle_rejection);
+
+    let res = request().path("/quota").reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["ratelimit-limit"], "2");
+    assert_eq!(res.headers()["ratelimit-remaining"], "1");
+    assert_eq!(res.headers()["x-ratelimit-remaining"], "1");
+    assert_eq!(res.headers()["ratelimit-reset"], "60");
+    assert!(res.headers().get("retry-after").is_none());
+
+    let res = request().path("/quota").reply(&filter).await;
+    assert_eq!(res.headers()["ratelimit-remaining"], "0");
+
+    let res = request().path("/quota").reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()["retry-after"], "60");
+    assert_eq!(res.headers()["ratelimit-remaining"], "0");
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["error"], "slow down");
+}
+
+fn kind_endpoint(kind: EndpointKind, file: &str) -> Endpoint {
+    Endpoint {
+        method: vec!["POST".to_string(), "PUT".to_string()],
+        file: file.to_string(),
+        status_code: None,
+        rate_limit: None,
+        authentication: None,
+        delay: None,
+        with_dynamic_vars: None,
+        graphql_schema: None,
+        grpc: None,
+        kind,
+    }
+}
+
+#[tokio::test]
+async fn test_requests_dispatched_on_the_endpoint_kind() {
+    let mut endpoints_map = HashMap::new();
+    endpoints_map.insert("/orders".to_string(), kind_endpoint(EndpointKind::Rest, "uploads/file.json"));
+    endpoints_map.insert("/api/graphql".to_string(), kind_endpoint(EndpointKind::GraphQL, "uploads/graphql.json"));
+    endpoints_map.insert("/grpc".to_string(), kind_endpoint(EndpointKind::Grpc, "uploads/file.json"));
+    let endpoints = Arc::new(Mutex::new(endpoints_map));
+    let rate_limiter = new_rate_limit();
+    let registry = Arc::new(GrpcRegistry::new());
+    registry.register_mock("BookService", "GetBook", GrpcMockResponse {
+        output: json!({ "id": "b123" }),
+        ..Default::default()
+    }).await;
+
+    let filter = warp::method()
+        .and(warp::path::full())
+        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::any().map(move || endpoints.clone()))
+        .and(warp::any().map(AuthProfiles::default))
+        .and(warp::any().map(move || rate_limiter.clone()))
+        .and(warp::body::bytes().map(Some))
+        .and(warp::any().map(move || Arc::clone(&registry)))
+        .and(with_persisted_queries(new_persisted_queries()))
+        .and_then(serve_dynamic_response)
+        .recover(handle_rejection);
+
+    // REST endpoints answer their file, whatever the body looks like
+    for body in [json!({ "item": "456" }), json!({ "query": "query getUser { id }" }), json!({ "service": "BookService", "rpc": "GetBook", "request": {} })] {
+        let res = request().method("PUT").path("/orders").json(&body).reply(&filter).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let json_body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(json_body["first_name"], "John");
+    }
+
+    let res = request().method("POST").path("/api/graphql").json(&json!({ "query": "query getUser { id }" })).reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let json_body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert!(json_body["data"].is_object());
+    let res = request().method("POST").path("/api/graphql").json(&json!({ "query": "query getNothing { id }" })).reply(&filter).await;
+    let json_body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(json_body["errors"][0]["message"], "No mock for this operation.");
+    let res = request().method("POST").path("/api/graphql").json(&json!({ "item": "456" })).reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.body(), "Invalid GraphQL request\n");
+
+    let call = json!({ "service": "BookService", "rpc": "GetBook", "request": { "id": "b123" } });
+    let res = request().method("POST").path("/grpc").json(&call).reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(serde_json::from_slice::<Value>(res.body()).unwrap(), json!({ "id": "b123" }));
+    let unknown = json!({ "service": "BookService", "rpc": "BuyBook", "request": {} });
+    let res = request().method("POST").path("/grpc").json(&unknown).reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_slice::<Value>(res.body()).unwrap(), json!({ "error": "Mock not found" }));
+    let res = request().method("POST").path("/grpc").json(&json!({ "item": "456" })).reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8_lossy(res.body()).starts_with("Invalid gRPC request: missing field"));
+}
+
+#[test]
+fn test_endpoint_kind_names() {
+    assert_eq!("GraphQL".parse::<EndpointKind>(), Ok(EndpointKind::GraphQL));
+    assert_eq!(" grpc ".parse::<EndpointKind>(), Ok(EndpointKind::Grpc));
+    assert!("soap".parse::<EndpointKind>().unwrap_err().contains("expected rest, graphql or grpc"));
+    assert_eq!(serde_json::to_value(EndpointKind::Rest).unwrap(), json!("rest"));
+
+    // Endpoints saved without a kind are REST ones
+    let endpoint: Endpoint = serde_json::from_value(json!({
+        "method": ["GET"], "file": "uploads/file.json", "status_code": null,
+        "delay": null, "rate_limit": null, "with_dynamic_vars": null
+    })).unwrap();
+    assert_eq!(endpoint.kind, EndpointKind::Rest);
+}
+
+#[test]
+fn test_equally_specific_endpoints_resolve_deterministically() {
+    // Each endpoint reads its API key from the parameter the other one is registered with
+    let keyed_by = |key: &str, param: &str| Endpoint {
+        authentication: Some(json!({ "apiKey": { "in": "query", "name": param, "keys": ["k"] } }).to_string()),
+        ..kind_endpoint(EndpointKind::Rest, key)
+    };
+    let resolve = |region: &str, keys: &[(&str, &str)]| {
+        let query: HashMap<String, String> = [("status", "open"), ("region", region)].iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        // Every map iterates in its own order
+        let endpoints_map: HashMap<String, Endpoint> = keys.iter()
+            .map(|(key, param)| (key.to_string(), keyed_by(key, param)))
+            .collect();
+        find_endpoint(&endpoints_map, "/orders", &Some(query), &HashMap::new()).map(|(key, _)| key)
+    };
+
+    for _ in 0..20 {
+        // The longest registration wins, then the first in alphabetical order
+        let keys = [("/orders?status=open", "region"), ("/orders?region=eu", "status")];
+        assert_eq!(resolve("eu", &keys).as_deref(), Some("/orders?status=open"));
+        let keys = [("/orders?status=open", "region"), ("/orders?region=euro", "status")];
+        assert_eq!(resolve("euro", &keys).as_deref(), Some("/orders?region=euro"));
+    }
+    // Other parameters are matched exactly
+    assert_eq!(resolve("eu", &[("/orders?region=eu", "api_key"), ("/orders", "api_key")]), None);
+}
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+use serde_json::{json, Value};
+use mockiapi::handlers::graphql::{handle_graphql, read_graphql_body};
+use mockiapi::handlers::graphql_schema::GraphQLSchema;
+use mockiapi::middlewares::persisted_queries::new_persisted_queries;
+use mockiapi::models::{Endpoint, EndpointKind};
+
+const SDL: &str = r#"
+    "A person"
+    type User implements Node {
+        id: ID!
+        name: String!
+        age: Int
+        role: Role!
+        friends(first: Int = 2): [User!]!
+        oldName: String @deprecated(reason: "Use name")
+    }
+
+    interface Node {
+        id: ID!
+    }
+
+    type Post implements Node {
+        id: ID!
+        title: String!
+    }
+
+    union SearchResult = User | Post
+
+    enum Role {
+        ADMIN
+        MEMBER
+    }
+
+    input UserInput {
+        name: String!
+    }
+
+    type Query {
+        user(id: ID!): User
+        search(term: String!): [SearchResult!]!
+    }
+
+    type Mutation {
+        createUser(input: UserInput!): User!
+    }
+"#;
+
+fn endpoint() -> Endpoint {
+    Endpoint {
+        method: vec!["POST".to_string()],
+        file: String::new(),
+        status_code: None,
+        authentication: None,
+        delay: None,
+        rate_limit: None,
+        with_dynamic_vars: None,
+        graphql_schema: None,
+        grpc: None,
+        kind: EndpointKind::GraphQL,
+    }
+}
+
+/// Answers a request the way the server does for an endpoint with the test schema.
+async fn answer(request: Value, mocks: Value) -> (u16, Value) {
+    let schema = GraphQLSchema::parse(SDL).unwrap();
+    let payload = read_graphql_body(&request.to_string()).unwrap();
+    let response = handle_graphql(payload, &endpoint(), &mocks.to_string(), Some(&schema), &new_persisted_queries()).await;
+    (response.status().as_u16(), serde_json::from_str(response.body()).unwrap())
+}
+
+async fn run(request: Value, mocks: Value) -> Value {
+    answer(request, mocks).await.1
+}
+
+#[test]
+fn test_schema_must_be_valid() {
+    assert!(GraphQLSchema::parse(SDL).is_ok());
+    assert!(GraphQLSchema::parse("type Query { user: Missing }").unwrap_err().contains("Missing"));
+    assert!(GraphQLSchema::parse("type Mutation { ok: Boolean }").is_err());
+    assert!(GraphQLSchema::parse("type Query {").is_err());
+}
+
+#[tokio::test]
+async fn test_invalid_operations_return_graphql_errors() {
+    let response = run(json!({ "query": "query getUser { user(id: 1) { id email } }" }), Value::Null).await;
+    assert!(response.get("data").is_none());
+    assert_eq!(response["errors"][0]["message"], "Cannot query field \"email\" on type \"User\".");
+    assert_eq!(response["errors"][0]["locations"][0], json!({ "line": 1, "column": 34 }));
+
+    let response = run(json!({ "query": "{ user { name friends } }" }), Value::Null).await;
+    let messages: Vec<&str> = response["errors"].as_array().unwrap().iter()
+        .map(|error| error["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(messages, [
+        "Field \"user\" argument \"id\" of type \"ID!\" is required, but it was not provided.",
+        "Field \"friends\" of type \"[User!]!\" must have a selection of subfields. Did you mean \"friends { ... }\"?",
+    ]);
+
+    let response = run(json!({ "query": "query getUser($id: ID!) { user(id: $id) { name } }" }), Value::Null).await;
+    assert_eq!(response["errors"][0]["message"], "Variable \"$id\" of required type \"ID!\" was not provided.");
+
+    let response = run(json!({ "query": "{ user(id: 1) { name" }), Value::Null).await;
+    assert!(response["errors"][0]["message"].as_str().unwrap().starts_with("Syntax Error"));
+}
+
+#[tokio::test]
+async fn test_data_is_generated_from_types() {
+    let response = run(json!({ "query": "{ user(id: 1) { __typename id name role friends { id } } }" }), Value::Null).await;
+    let user = &response["data"]["user"];
+
+    assert_eq!(user["__typename"], "User");
+    assert!(user["id"].is_string());
+    assert!(user["name"].is_string());
+    assert_eq!(user["role"], "ADMIN");
+    let friends = user["friends"].as_array().unwrap();
+    assert_eq!(friends.len(), 2);
+    assert_ne!(friends[0]["id"], friends[1]["id"]);
+
+    let response = run(json!({ "query": "{ search(term: \"a\") { __typename ... on Post { title } ... on User { name } } }" }), Value::Null).await;
+    let result = &response["data"]["search"][0];
+    assert_eq!(result["__typename"], "User");
+    assert!(result["name"].is_string());
+    assert!(result.get("title").is_none());
+}
+
+#[tokio::test]
+async fn test_mock_overrides_take_precedence() {
+    let mocks = json!({
+        "query": {
+            "getUser": {
+                "data": { "user": { "name": "Ada", "friends": [{ "name": "Grace" }] } },
+                "cases": [{ "when": { "id": "2" }, "data": { "user": null } }]
+            },
+            "find": { "data": { "search": [{ "__typename": "Post", "title": "Hello" }] } }
+        }
+    });
+
+    let request = json!({ "query": "query getUser($id: ID!) { user(id: $id) { name age friends { name role } } }", "variables": { "id": "1" } });
+    let user = run(request, mocks.clone()).await["data"]["user"].clone();
+    assert_eq!(user["name"], "Ada");
+    assert!(user["age"].is_number());
+    assert_eq!(user["friends"], json!([{ "name": "Grace", "role": "ADMIN" }]));
+
+    let request = json!({ "query": "query getUser($id: ID!) { user(id: $id) { name } }", "variables": { "id": "2" } });
+    assert_eq!(run(request, mocks.clone()).await["data"]["user"], Value::Null);
+
+    let request = json!({ "query": "query find { search(term: \"x\") { ... on Post { id title } ... on User { name } } }" });
+    let post = run(request, mocks).await["data"]["search"][0].clone();
+    assert_eq!(post["title"], "Hello");
+    assert!(post["id"].is_string());
+    assert!(post.get("name").is_none());
+}
+
+#[tokio::test]
+async fn test_introspection() {
+    let query = r#"
+        query IntrospectionQuery {
+            __schema {
+                queryType { name }
+                mutationType { name }
+                subscriptionType { name }
+                types { ...FullType }
+                directives { name locations }
+            }
+        }
+        fragment FullType on __Type {
+            kind
+            name
+            fields(includeDeprecated: true) { name isDeprecated deprecationReason type { ...TypeRef } }
+            enumValues(includeDeprecated: true) { name }
+            possibleTypes { name }
+        }
+        fragment TypeRef on __Type { kind name ofType { kind name ofType { kind name } } }
+    "#;
+    let schema = run(json!({ "query": query }), Value::Null).await["data"]["__schema"].clone();
+
+    assert_eq!(schema["queryType"]["name"], "Query");
+    assert_eq!(schema["mutationType"]["name"], "Mutation");
+    assert_eq!(schema["subscriptionType"], Value::Null);
+
+    let types = schema["types"].as_array().unwrap();
+    let user = types.iter().find(|t| t["name"] == "User").unwrap();
+    assert_eq!(user["kind"], "OBJECT");
+    let friends = user["fields"].as_array().unwrap().iter().find(|f| f["name"] == "friends").unwrap();
+    assert_eq!(friends["type"], json!({
+        "kind": "NON_NULL", "name": null,
+        "ofType": { "kind": "LIST", "name": null, "ofType": { "kind": "NON_NULL", "name": null } }
+    }));
+    let old_name = user["fields"].as_array().unwrap().iter().find(|f| f["name"] == "oldName").unwrap();
+    assert_eq!((&old_name["isDeprecated"], &old_name["deprecationReason"]), (&json!(true), &json!("Use name")));
+
+    let node = types.iter().find(|t| t["name"] == "Node").unwrap();
+    assert_eq!(node["possibleTypes"], json!([{ "name": "Post" }, { "name": "User" }]));
+    assert!(types.iter().any(|t| t["name"] == "String" && t["kind"] == "SCALAR"));
+    assert!(schema["directives"].as_array().unwrap().iter().any(|d| d["name"] == "include"));
+
+    let response = run(json!({ "query": "{ __type(name: \"Role\") { kind enumValues { name } } }" }), Value::Null).await;
+    assert_eq!(response["data"]["__type"], json!({ "kind": "ENUM", "enumValues": [{ "name": "ADMIN" }, { "name": "MEMBER" }] }));
+}
+
+#[tokio::test]
+async fn test_mocked_errors_with_schema() {
+    let mocks = json!({
+        "query": {
+            "getUser": {
+                "data": { "user": { "age": null } },
+                "errors": [{ "message": "Age is private", "path": ["user", "age"], "extensions": { "code": "FORBIDDEN" } }],
+                "status": 207
+            }
+        }
+    });
+    let request = json!({ "query": "query getUser { user(id: 1) { name age } }" });
+    let (status, response) = answer(request, mocks).await;
+
+    assert_eq!(status, 207);
+    assert_eq!(response["data"]["user"]["age"], Value::Null);
+    assert!(response["data"]["user"]["name"].is_string());
+    assert_eq!(response["errors"][0]["locations"], json!([{ "line": 1, "column": 36 }]));
+}
+
+#[tokio::test]
+async fn test_schemas_are_parsed_once_and_dropped_with_their_endpoint() {
+    let schema_file = "uploads/cached-schema-test.graphql";
+    let cached = GraphQLSchema::cache(schema_file, GraphQLSchema::parse(SDL).unwrap());
+
+    // The file was never written: the schema comes from the cache
+    let loaded = GraphQLSchema::load(schema_file).await.unwrap();
+    assert!(Arc::ptr_eq(&cached, &loaded));
+
+    GraphQLSchema::evict(schema_file);
+    assert!(GraphQLSchema::load(schema_file).await.is_none());
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use warp::Filter;
+use warp::http::StatusCode;
+use mockiapi::handlers::graphql_subscription::{plan_subscription, render_event};
+use mockiapi::middlewares::subscriptions::{new_subscription_hub, SubscriptionHub};
+use mockiapi::models::{AuthProfiles, Endpoint, EndpointKind, GraphQLRequest};
+use mockiapi::routes::endpoints::with_endpoints;
+use mockiapi::routes::subscriptions::{push_subscription_event, upgrade_graphql_ws};
+use mockiapi::routes::auth_profiles::with_auth_profiles;
+use mockiapi::utils::{handle_rejection, with_subscriptions};
+
+fn mocks() -> Value {
+    json!({
+        "subscription": {
+            "onPrice": {
+                "interval": 30,
+                "events": [
+                    { "data": { "onPrice": { "symbol": "{{symbol}}", "value": 1, "currency": "EUR" } } },
+                    { "data": { "onPrice": { "symbol": "{{symbol}}", "value": 2 } } }
+                ]
+            },
+            "onAlert": {
+                "events": [{ "errors": [{ "message": "Feed lost" }], "delay": 5 }],
+                "complete": true
+            }
+        },
+        "query": {
+            "getPrice": { "data": { "price": 42 } }
+        }
+    })
+}
+
+fn request(query: &str, variables: Value) -> GraphQLRequest {
+    serde_json::from_value(json!({ "query": query, "variables": variables })).unwrap()
+}
+
+#[test]
+fn test_plan_subscription_events() {
+    let price = request("subscription onPrice($symbol: String) { onPrice(symbol: $symbol) { symbol value } }", json!({ "symbol": "ACME" }));
+    let plan = plan_subscription(&price, &mocks().to_string(), None).unwrap();
+    assert_eq!(plan.key, "onPrice");
+    assert!(!plan.complete);
+    let delays: Vec<Duration> = plan.events.iter().map(|event| event.delay).collect();
+    assert_eq!(delays, [Duration::ZERO, Duration::from_millis(30)]);
+    assert_eq!(
+        render_event(&price, &plan.events[0].mock, None),
+        json!({ "data": { "onPrice": { "symbol": "ACME", "value": 1 } } })
+    );
+
+    let alert = request("subscription { onAlert { level } }", Value::Null);
+    let plan = plan_subscription(&alert, &mocks().to_string(), None).unwrap();
+    assert!(plan.complete);
+    assert_eq!(plan.events[0].delay, Duration::from_millis(5));
+
+    let query = request("query getPrice { price }", Value::Null);
+    let plan = plan_subscription(&query, &mocks().to_string(), None).unwrap();
+    assert!(plan.complete);
+    assert_eq!(render_event(&query, &plan.events[0].mock, None), json!({ "data": { "price": 42 } }));
+
+    let unknown = request("subscription onNothing { nothing }", Value::Null);
+    let errors = plan_subscription(&unknown, &mocks().to_string(), None).unwrap_err();
+    assert_eq!(errors[0]["message"], "No mock for subscription \"onNothing\".");
+}
+
+fn ws_filter(hub: SubscriptionHub) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let file = std::env::temp_dir().join("mockiapi_subscriptions.json");
+    std::fs::write(&file, mocks().to_string()).unwrap();
+
+    let mut endpoints_map = HashMap::new();
+    endpoints_map.insert(
+        "/graphql".to_string(),
+        Endpoint {
+            method: vec!["POST".to_string()],
+            file: file.to_string_lossy().into_owned(),
+            status_code: None,
+            authentication: None,
+            delay: None,
+            rate_limit: None,
+            with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::GraphQL,
+        },
+    );
+    endpoints_map.insert(
+        "/orders".to_string(),
+        Endpoint {
+            method: vec!["GET".to_string()],
+            file: file.to_string_lossy().into_owned(),
+            status_code: None,
+            authentication: None,
+            delay: None,
+            rate_limit: None,
+            with_dynamic_vars: None,
+            graphql_schema: None,
+            grpc: None,
+            kind: EndpointKind::Rest,
+        },
+    );
+    let endpoints = Arc::new(Mutex::new(endpoints_map));
+    let auth_profiles = AuthProfiles::default();
+
+    warp::ws()
+        .and(warp::path::full())
+        .and(warp::any().map(|| None))
+        .and(warp::header::headers_cloned())
+        .and(with_endpoints(endpoints))
+        .and(with_auth_profiles(auth_profiles))
+        .and(with_subscriptions(hub))
+        .and_then(upgrade_graphql_ws)
+}
+
+async fn recv_json(client: &mut warp::test::WsClient) -> Value {
+    let message = tokio::time::timeout(Duration::from_secs(2), client.recv()).await.unwrap().unwrap();
+    serde_json::from_str(message.to_str().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn test_graphql_transport_ws_protocol() {
+    let hub = new_subscription_hub();
+    let mut client = warp::test::ws()
+        .path("/graphql")
+        .header("sec-websocket-protocol", "graphql-transport-ws")
+        .handshake(ws_filter(hub.clone()))
+        .await
+        .unwrap();
+
+    client.send_text(json!({ "type": "connection_init" }).to_string()).await;
+    assert_eq!(recv_json(&mut client).await, json!({ "type": "connection_ack" }));
+
+    client.send_text(json!({ "type": "ping" }).to_string()).await;
+    assert_eq!(recv_json(&mut client).await, json!({ "type": "pong" }));
+
+    client.send_text(json!({
+        "id": "1",
+        "type": "subscribe",
+        "payload": { "query": "subscription onPrice($symbol: String) { onPrice(symbol: $symbol) { symbol value } }", "variables": { "symbol": "ACME" } }
+    }).to_string()).await;
+    let first = recv_json(&mut client).await;
+    assert_eq!(first, json!({ "id": "1", "type": "next", "payload": { "data": { "onPrice": { "symbol": "ACME", "value": 1 } } } }));
+    let second = recv_json(&mut client).await;
+    assert_eq!(second["payload"]["data"]["onPrice"]["value"], 2);
+
+    // Pushed events reach the open subscription, shaped after its selection
+    assert_eq!(hub.counts().await["/graphql|onPrice"], 1);
+    assert_eq!(hub.publish("/other|onPrice", json!({ "data": {} })).await, 0);
+    let push = warp::path!("graphql" / "subscriptions" / ..)
+        .and(warp::path::tail())
+        .and(warp::body::json())
+        .and(with_subscriptions(hub.clone()))
+        .and_then(push_subscription_event);
+    let res = warp::test::request()
+        .method("POST")
+        .path("/graphql/subscriptions/graphql/onPrice")
+        .json(&json!({ "data": { "onPrice": { "symbol": "{{symbol}}", "value": 99, "currency": "USD" } } }))
+        .reply(&push)
+        .await;
+    let delivered: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(delivered, json!({ "path": "/graphql", "operation": "onPrice", "delivered": 1 }));
+    let pushed = recv_json(&mut client).await;
+    assert_eq!(pushed["payload"], json!({ "data": { "onPrice": { "symbol": "ACME", "value": 99 } } }));
+
+    // A scripted subscription completes by itself
+    client.send_text(json!({ "id": "2", "type": "subscribe", "payload": { "query": "subscription { onAlert { level } }" } }).to_string()).await;
+    let alert = recv_json(&mut client).await;
+    assert_eq!(alert["payload"]["errors"][0]["message"], "Feed lost");
+    assert_eq!(recv_json(&mut client).await, json!({ "id": "2", "type": "complete" }));
+
+    // Unknown operations get an error message
+    client.send_text(json!({ "id": "3", "type": "subscribe", "payload": { "query": "subscription { nothing }" } }).to_string()).await;
+    let error = recv_json(&mut client).await;
+    assert_eq!((&error["id"], &error["type"]), (&json!("3"), &json!("error")));
+
+    client.send_text(json!({ "id": "1", "type": "complete" }).to_string()).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(hub.publish("/graphql|onPrice", json!({ "data": {} })).await, 0);
+}
+
+#[tokio::test]
+async fn test_subscribe_before_init_is_refused() {
+    let mut client = warp::test::ws()
+        .path("/graphql")
+        .handshake(ws_filter(new_subscription_hub()))
+        .await
+        .unwrap();
+
+    client.send_text(json!({ "id": "1", "type": "subscribe", "payload": { "query": "subscription { onAlert { level } }" } }).to_string()).await;
+    let closed = tokio::time::timeout(Duration::from_secs(2), client.recv_closed()).await.unwrap();
+    assert!(closed.is_ok());
+}
+
+#[tokio::test]
+async fn test_only_graphql_endpoints_upgrade() {
+    let res = warp::test::request()
+        .path("/orders")
+        .header("connection", "upgrade")
+        .header("upgrade", "websocket")
+        .header("sec-websocket-version", "13")
+        .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+        .reply(&ws_filter(new_subscription_hub()).recover(handle_rejection))
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.body(), "Invalid endpoint kind: /orders is not a GraphQL endpoint\n");
+}
+
+#[tokio::test]
+async fn test_upgrade_negotiates_the_subprotocol() {
+    let upgrade = |protocols: Option<&str>| {
+        let request = warp::test::request()
+            .path("/graphql")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==");
+        match protocols {
+            Some(protocols) => request.header("sec-websocket-protocol", protocols),
+            None => request,
+        }
+    };
+    let filter = ws_filter(new_subscription_hub()).recover(handle_rejection);
+
+    let res = upgrade(Some("graphql-ws, graphql-transport-ws")).reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(res.headers()["sec-websocket-protocol"], "graphql-transport-ws");
+
+    // The legacy subscriptions-transport-ws protocol is not spoken
+    let res = upgrade(Some("graphql-ws")).reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.body(), "Unsupported WebSocket subprotocol: graphql-ws (expected graphql-transport-ws)\n");
+
+    let res = upgrade(None).reply(&filter).await;
+    assert_eq!(res.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert!(res.headers().get("sec-websocket-protocol").is_none());
+}
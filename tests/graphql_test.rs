@@ -1,4 +1,7 @@
-use mockiapi::handlers::graphql::process_graphql;
+use std::collections::HashMap;
+use mockiapi::handlers::graphql::{handle_graphql, process_graphql, read_graphql_body, read_graphql_query_params};
+use mockiapi::middlewares::persisted_queries::{new_persisted_queries, query_hash, PersistedQueryStore, MAX_PERSISTED_QUERIES, MAX_PERSISTED_QUERY_LEN};
+use mockiapi::models::{Endpoint, EndpointKind, GraphQLPayload};
 
 fn mock_json_data() -> &'static str {
     r#"
@@ -130,4 +133,316 @@ fn test_nested_fields() {
     let json: serde_json::Value = serde_json::from_str(&result).unwrap();
     assert_eq!(json.as_object().unwrap().len(), 1);
     assert_eq!(json["data"]["profile"]["bio"], "Hello");
-}
\ No newline at end of file
+}
+
+fn cases_mock() -> &'static str {
+    r#"
+        {
+          "query": {
+            "getUser": {
+              "data": { "user": { "id": "{{id}}", "name": "Default User" } },
+              "cases": [
+                { "when": { "id": "2" }, "data": { "user": { "id": "2", "name": "Jane" } } },
+                { "when": { "id": "3", "active": true }, "data": { "user": { "id": "3", "name": "Active Joe" } } }
+              ]
+            }
+          },
+          "mutation": {
+            "createUser": {
+              "data": { "createUser": { "name": "{{input.name}}", "age": "{{input.age}}" } }
+            }
+          }
+        }
+    "#
+}
+
+#[test]
+fn test_case_selected_by_variable() {
+    let request = r#"{
+        "query": "query getUser($id: ID!) { user(id: $id) { id name } }",
+        "variables": { "id": "2" }
+    }"#;
+    let json: serde_json::Value = serde_json::from_str(&process_graphql(request, cases_mock()).unwrap().unwrap()).unwrap();
+    assert_eq!(json["data"]["user"]["name"], "Jane");
+}
+
+#[test]
+fn test_case_selected_by_literal_argument() {
+    let request = r#"{ "query": "query getUser { user(id: 3, active: true) { name } }" }"#;
+    let json: serde_json::Value = serde_json::from_str(&process_graphql(request, cases_mock()).unwrap().unwrap()).unwrap();
+    assert_eq!(json["data"]["user"]["name"], "Active Joe");
+
+    // Not every condition holds: fall back to the default, templated with the argument
+    let request = r#"{ "query": "query getUser($id: ID = 3) { user(id: $id) { id name } }" }"#;
+    let json: serde_json::Value = serde_json::from_str(&process_graphql(request, cases_mock()).unwrap().unwrap()).unwrap();
+    assert_eq!(json["data"]["user"]["name"], "Default User");
+    assert_eq!(json["data"]["user"]["id"], 3);
+}
+
+#[test]
+fn test_variables_in_templates() {
+    let request = r#"{
+        "query": "mutation createUser($input: UserInput!) { createUser(input: $input) { name age } }",
+        "variables": { "input": { "name": "Ada", "age": 36 } }
+    }"#;
+    let json: serde_json::Value = serde_json::from_str(&process_graphql(request, cases_mock()).unwrap().unwrap()).unwrap();
+    assert_eq!(json["data"]["createUser"]["name"], "Ada");
+    assert_eq!(json["data"]["createUser"]["age"], 36);
+}
+
+#[test]
+fn test_string_arguments_keep_their_type_in_templates() {
+    for id in ["007", "42", "true", "null"] {
+        let request = serde_json::json!({
+            "query": "query getUser($id: ID!) { user(id: $id) { id } }",
+            "variables": { "id": id }
+        }).to_string();
+        let json: serde_json::Value = serde_json::from_str(&process_graphql(&request, cases_mock()).unwrap().unwrap()).unwrap();
+        assert_eq!(json["data"]["user"]["id"], id);
+    }
+}
+
+fn shape(query: &str, variables: serde_json::Value) -> serde_json::Value {
+    let request = serde_json::json!({ "query": query, "variables": variables }).to_string();
+    serde_json::from_str(&process_graphql(&request, mock_json_data()).unwrap().unwrap()).unwrap()
+}
+
+#[test]
+fn test_nested_selection_and_aliases() {
+    let json = shape("query getUser { userId: id address { town: city zip } phone }", serde_json::Value::Null);
+    assert_eq!(json["data"], serde_json::json!({
+        "userId": "123",
+        "address": { "town": "Anytown", "zip": "12345" },
+        "phone": null
+    }));
+
+    let json = shape("query getUsers { name }", serde_json::Value::Null);
+    assert_eq!(json["data"], serde_json::json!([{ "name": "John Doe" }, { "name": "Jane Smith" }]));
+}
+
+#[test]
+fn test_fragments_are_expanded() {
+    let json = shape(
+        "query getUser { id ...Contact address { ... on Address { city } ... on Company { zip } } } fragment Contact on User { email address { state } }",
+        serde_json::Value::Null,
+    );
+    assert_eq!(json["data"], serde_json::json!({
+        "id": "123",
+        "email": "john@example.com",
+        "address": { "state": "CA", "city": "Anytown", "zip": "12345" }
+    }));
+}
+
+#[test]
+fn test_include_and_skip_directives() {
+    let query = "query getUser($withAddress: Boolean!, $hideEmail: Boolean = true) { id email @skip(if: $hideEmail) address @include(if: $withAddress) { city } }";
+
+    let json = shape(query, serde_json::json!({ "withAddress": false }));
+    assert_eq!(json["data"], serde_json::json!({ "id": "123" }));
+
+    let json = shape(query, serde_json::json!({ "withAddress": true, "hideEmail": false }));
+    assert_eq!(json["data"], serde_json::json!({
+        "id": "123",
+        "email": "john@example.com",
+        "address": { "city": "Anytown" }
+    }));
+}
+
+fn errors_mock() -> &'static str {
+    r#"
+        {
+          "query": {
+            "getUser": {
+              "data": { "user": { "id": "1", "email": null } },
+              "errors": [{ "message": "Not allowed to read email", "path": ["user", "email"], "extensions": { "code": "FORBIDDEN" } }],
+              "cases": [
+                { "when": { "id": "404" }, "data": { "user": null }, "errors": [{ "message": "User {{id}} not found", "path": ["user"] }] },
+                { "when": { "id": "500" }, "errors": { "message": "Internal error", "extensions": { "code": "INTERNAL_SERVER_ERROR" } }, "status": 503, "delay": 10 }
+              ]
+            }
+          }
+        }
+    "#
+}
+
+#[tokio::test]
+async fn test_errors_with_partial_data() {
+    let request = r#"{ "query": "query getUser($id: ID) {\n  user(id: $id) {\n    id\n    mail: email\n  }\n}", "variables": { "id": "1" } }"#;
+    let (status, json) = answer(read_graphql_body(request).unwrap(), errors_mock(), &new_persisted_queries()).await;
+
+    assert_eq!(json["data"], serde_json::json!({ "user": { "id": "1", "mail": null } }));
+    assert_eq!(json["errors"][0]["extensions"]["code"], "FORBIDDEN");
+    // Pointing at the response key the client selected the field under, and located
+    assert_eq!(json["errors"][0]["path"], serde_json::json!(["user", "mail"]));
+    assert_eq!(json["errors"][0]["locations"], serde_json::json!([{ "line": 4, "column": 5 }]));
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn test_error_cases_with_status_and_delay() {
+    let request = r#"{ "query": "query getUser($id: ID) { user(id: $id) { id } }", "variables": { "id": "404" } }"#;
+    let (_, json) = answer(read_graphql_body(request).unwrap(), errors_mock(), &new_persisted_queries()).await;
+    assert_eq!(json["data"], serde_json::json!({ "user": null }));
+    assert_eq!(json["errors"][0]["message"], "User 404 not found");
+    assert_eq!(json["errors"][0]["locations"][0]["column"], 26);
+
+    let request = r#"{ "query": "query getUser($id: ID) { user(id: $id) { id } }", "variables": { "id": "500" } }"#;
+    let started = std::time::Instant::now();
+    let (status, json) = answer(read_graphql_body(request).unwrap(), errors_mock(), &new_persisted_queries()).await;
+    assert!(json.get("data").is_none());
+    assert_eq!(json["errors"][0]["extensions"]["code"], "INTERNAL_SERVER_ERROR");
+    assert_eq!(status, 503);
+    assert!(started.elapsed() >= std::time::Duration::from_millis(10));
+}
+
+#[test]
+fn test_operation_detection_from_the_document() {
+    // Anonymous shorthand, looked up by its first field
+    let mocks = r#"{ "query": { "user": { "data": { "user": { "id": "1", "name": "Ada" } } } } }"#;
+    let json = parse(&process_graphql(r#"{ "query": "{ user { id } }" }"#, mocks).unwrap().unwrap());
+    assert_eq!(json["data"], serde_json::json!({ "user": { "id": "1" } }));
+
+    // Leading comments and fragments defined before the operation
+    let request = r##"{ "query": "# fetch the user\nfragment Names on User { name }\nmutation createUser { success ...Names }" }"##;
+    let json = parse(&process_graphql(request, mock_json_data()).unwrap().unwrap());
+    assert_eq!(json["data"], serde_json::json!({ "success": true, "name": null }));
+
+    // operationName picks among several operations
+    let request = r#"{ "query": "query getUser { id } query getUsers { name }", "operationName": "getUsers" }"#;
+    let json = parse(&process_graphql(request, mock_json_data()).unwrap().unwrap());
+    assert_eq!(json["data"][1]["name"], "Jane Smith");
+}
+
+#[test]
+fn test_invalid_operations_get_graphql_errors() {
+    let request = r#"{ "query": "query getUser { id" }"#;
+    let json = parse(&process_graphql(request, mock_json_data()).unwrap().unwrap());
+    assert!(json.get("data").is_none());
+    assert!(json["errors"][0]["message"].as_str().unwrap().starts_with("Syntax Error"));
+
+    let request = r#"{ "query": "query getUser { id } query getUsers { name }" }"#;
+    let json = parse(&process_graphql(request, mock_json_data()).unwrap().unwrap());
+    assert_eq!(json["errors"][0]["message"], "Must provide operation name if query contains multiple operations.");
+
+    let request = r#"{ "query": "query getUser { id }", "operationName": "getUsers" }"#;
+    let json = parse(&process_graphql(request, mock_json_data()).unwrap().unwrap());
+    assert_eq!(json["errors"][0]["message"], "Unknown operation named \"getUsers\".");
+}
+
+fn endpoint() -> Endpoint {
+    Endpoint {
+        method: vec!["GET".to_string(), "POST".to_string()],
+        file: String::new(),
+        status_code: None,
+        authentication: None,
+        delay: None,
+        rate_limit: None,
+        with_dynamic_vars: None,
+        graphql_schema: None,
+        grpc: None,
+        kind: EndpointKind::GraphQL,
+    }
+}
+
+async fn answer(payload: GraphQLPayload, mocks: &str, persisted_queries: &PersistedQueryStore) -> (u16, serde_json::Value) {
+    let response = handle_graphql(payload, &endpoint(), mocks, None, persisted_queries).await;
+    (response.status().as_u16(), parse(response.body()))
+}
+
+#[tokio::test]
+async fn test_batched_operations() {
+    let body = r#"[
+        { "query": "query getUser { id }" },
+        { "query": "mutation createUser { success }" },
+        { "query": "query nonExistent { id }" }
+    ]"#;
+    let payload = read_graphql_body(body).unwrap();
+    let (status, json) = answer(payload, mock_json_data(), &new_persisted_queries()).await;
+    assert_eq!(status, 200);
+    assert_eq!(json[0], serde_json::json!({ "data": { "id": "123" } }));
+    assert_eq!(json[1], serde_json::json!({ "data": { "success": true } }));
+    assert!(json[2]["errors"][0]["message"].is_string());
+
+    // The batch takes the highest status its operations simulate
+    let body = r#"[
+        { "query": "query getUser($id: ID) { user(id: $id) { id } }", "variables": { "id": "500" } },
+        { "query": "query getUser($id: ID) { user(id: $id) { id } }", "variables": { "id": "404" } }
+    ]"#;
+    let (status, json) = answer(read_graphql_body(body).unwrap(), errors_mock(), &new_persisted_queries()).await;
+    assert_eq!(status, 503);
+    assert_eq!(json.as_array().unwrap().len(), 2);
+
+    assert!(read_graphql_body("[]").is_none());
+    assert!(read_graphql_body(r#"[{ "query": "{ id }" }, { "service": "Greeter" }]"#).is_none());
+    assert!(read_graphql_body(r#"{ "service": "Greeter" }"#).is_none());
+}
+
+#[tokio::test]
+async fn test_query_string_requests() {
+    let params = HashMap::from([
+        ("query".to_string(), "query getUser($id: ID) { user(id: $id) { id } }".to_string()),
+        ("operationName".to_string(), "getUser".to_string()),
+        ("variables".to_string(), r#"{ "id": "404" }"#.to_string()),
+    ]);
+    let payload = read_graphql_query_params(&params).unwrap();
+    let (_, json) = answer(payload, errors_mock(), &new_persisted_queries()).await;
+    assert_eq!(json["errors"][0]["message"], "User 404 not found");
+
+    let params = HashMap::from([("id".to_string(), "1".to_string())]);
+    assert!(read_graphql_query_params(&params).is_none());
+    let params = HashMap::from([
+        ("query".to_string(), "{ id }".to_string()),
+        ("variables".to_string(), "not json".to_string()),
+    ]);
+    assert!(read_graphql_query_params(&params).is_none());
+}
+
+#[tokio::test]
+async fn test_automatic_persisted_queries() {
+    let persisted_queries = new_persisted_queries();
+    let query = "query getUser { id name }";
+    let hash = query_hash(query);
+    let extensions = format!(r#"{{ "persistedQuery": {{ "version": 1, "sha256Hash": "{}" }} }}"#, hash);
+
+    // The hash alone is unknown at first
+    let params = HashMap::from([("extensions".to_string(), extensions.clone())]);
+    let (status, json) = answer(read_graphql_query_params(&params).unwrap(), mock_json_data(), &persisted_queries).await;
+    assert_eq!(status, 200);
+    assert_eq!(json["errors"][0]["message"], "PersistedQueryNotFound");
+    assert_eq!(json["errors"][0]["extensions"]["code"], "PERSISTED_QUERY_NOT_FOUND");
+
+    // Sending the query along with its hash registers it
+    let body = format!(r#"{{ "query": "{}", "extensions": {} }}"#, query, extensions);
+    let (_, json) = answer(read_graphql_body(&body).unwrap(), mock_json_data(), &persisted_queries).await;
+    assert_eq!(json["data"], serde_json::json!({ "id": "123", "name": "John Doe" }));
+
+    // Then the hash is enough
+    let (_, json) = answer(read_graphql_query_params(&params).unwrap(), mock_json_data(), &persisted_queries).await;
+    assert_eq!(json["data"]["name"], "John Doe");
+
+    // A query that does not match its hash is refused
+    let body = format!(r#"{{ "query": "query getUser {{ id }}", "extensions": {} }}"#, extensions);
+    let (status, json) = answer(read_graphql_body(&body).unwrap(), mock_json_data(), &persisted_queries).await;
+    assert_eq!(status, 400);
+    assert_eq!(json["errors"][0]["message"], "provided sha does not match query");
+}
+
+#[tokio::test]
+async fn test_persisted_queries_are_bounded() {
+    let store = new_persisted_queries();
+    let too_long = format!("query getUser {{ id {} }}", " ".repeat(MAX_PERSISTED_QUERY_LEN));
+    assert!(store.insert(&query_hash(&too_long), too_long).await.is_err());
+
+    let queries: Vec<String> = (0..=MAX_PERSISTED_QUERIES).map(|i| format!("query q{} {{ id }}", i)).collect();
+    for query in &queries {
+        store.insert(&query_hash(query), query.clone()).await.unwrap();
+    }
+    // The oldest query made room for the newest
+    assert_eq!(store.get(&query_hash(&queries[0])).await, None);
+    assert_eq!(store.get(&query_hash(&queries[1])).await.as_ref(), Some(&queries[1]));
+    assert_eq!(store.get(&query_hash(&queries[MAX_PERSISTED_QUERIES])).await.as_ref(), Some(&queries[MAX_PERSISTED_QUERIES]));
+}
+
+fn parse(body: &str) -> serde_json::Value {
+    serde_json::from_str(body).unwrap()
+}
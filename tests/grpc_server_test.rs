@@ -0,0 +1,852 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use bytes::{Bytes, BytesMut};
+use hyper::body::HttpBody;
+use hyper::{Body, Client, HeaderMap, Request, StatusCode};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage};
+use prost_reflect::prost_types::FileDescriptorProto;
+use serde_json::json;
+use tokio::net::TcpListener;
+use mockiapi::handlers::grpc_server::{decode_frames, encode_frame, serve_grpc};
+use mockiapi::handlers::proto::{compile_protos, decode_descriptor_set};
+use mockiapi::middlewares::grpc_registry::GrpcRegistry;
+use mockiapi::models::MultipartHandler;
+use mockiapi::models::grpc::{GrpcMatcher, GrpcMockResponse, GrpcStreamMessage};
+use mockiapi::models::grpc_reflection::{MessageRequest, MessageResponse, ServerReflectionRequest, ServerReflectionResponse};
+
+const BOOKS_PROTO: &str = r#"
+syntax = "proto3";
+package books.v1;
+
+import "google/protobuf/timestamp.proto";
+import "common.proto";
+
+service BookService {
+  rpc GetBook (GetBookRequest) returns (Book);
+  rpc DeleteBook (GetBookRequest) returns (Book);
+  rpc ListBooks (GetBookRequest) returns (stream Book);
+  rpc UploadBooks (stream Book) returns (UploadSummary);
+  rpc SyncBooks (stream Book) returns (stream Book);
+}
+
+message UploadSummary {
+  int32 count = 1;
+}
+
+message GetBookRequest {
+  string id = 1;
+}
+
+message Book {
+  string id = 1;
+  string title = 2;
+  int32 pages = 3;
+  repeated string tags = 4;
+  google.protobuf.Timestamp published = 5;
+  common.Format format = 6;
+}
+"#;
+
+const COMMON_PROTO: &str = r#"
+syntax = "proto3";
+package common;
+
+enum Format {
+  FORMAT_UNSPECIFIED = 0;
+  HARDCOVER = 1;
+  EBOOK = 2;
+}
+"#;
+
+fn protos() -> Vec<(String, String)> {
+    vec![
+        ("books.proto".to_string(), BOOKS_PROTO.to_string()),
+        ("common.proto".to_string(), COMMON_PROTO.to_string()),
+    ]
+}
+
+async fn start_server() -> (SocketAddr, Arc<GrpcRegistry>) {
+    let registry = Arc::new(GrpcRegistry::new());
+    registry.add_descriptors(compile_protos(&protos()).unwrap()).await.unwrap();
+    registry.register_mock("BookService", "GetBook", GrpcMockResponse {
+        output: json!({
+            "id": "b123",
+            "title": "The Rust Programming Language",
+            "pages": 560,
+            "tags": ["rust", "programming"],
+            "published": "2018-08-12T00:00:00Z",
+            "format": "EBOOK",
+            "publisher": "ignored by the encoder"
+        }),
+        delay_ms: None,
+        status: None,
+        ..Default::default()
+    }).await;
+    registry.register_mock("books.v1.BookService", "DeleteBook", GrpcMockResponse {
+        output: json!({ "message": "Books are forever" }),
+        delay_ms: None,
+        status: Some(403),
+        ..Default::default()
+    }).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve_grpc(listener, registry.clone()));
+    (addr, registry)
+}
+
+struct Call {
+    status: StatusCode,
+    headers: HeaderMap,
+    messages: Vec<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+async fn call(addr: SocketAddr, path: &str, content_type: &str, message: &[u8]) -> Call {
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    let request = Request::post(format!("http://{}{}", addr, path))
+        .header("content-type", content_type)
+        .header("te", "trailers")
+        .body(Body::from(encode_frame(message)))
+        .unwrap();
+    let mut response = client.request(request).await.unwrap();
+
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response.body_mut().data().await {
+        body.extend_from_slice(&chunk.unwrap());
+    }
+    Call {
+        status: response.status(),
+        headers: response.headers().clone(),
+        messages: decode_frames(&body.freeze()).unwrap(),
+        trailers: response.body_mut().trailers().await.unwrap(),
+    }
+}
+
+fn pool() -> DescriptorPool {
+    compile_protos(&protos()).unwrap().pool().unwrap()
+}
+
+fn get_book_request(id: &str) -> Vec<u8> {
+    let descriptor = pool().get_message_by_name("books.v1.GetBookRequest").unwrap();
+    DynamicMessage::deserialize(descriptor, json!({ "id": id })).unwrap().encode_to_vec()
+}
+
+fn grpc_status(call: &Call) -> Option<&str> {
+    call.trailers.as_ref().unwrap_or(&call.headers).get("grpc-status")?.to_str().ok()
+}
+
+#[tokio::test]
+async fn test_unary_call_answers_the_mock_as_protobuf() {
+    let (addr, _) = start_server().await;
+
+    let call = call(addr, "/books.v1.BookService/GetBook", "application/grpc", &get_book_request("b123")).await;
+    assert_eq!(call.status, StatusCode::OK);
+    assert_eq!(call.headers["content-type"], "application/grpc");
+    assert_eq!(grpc_status(&call), Some("0"));
+    assert_eq!(call.messages.len(), 1);
+
+    let descriptor = pool().get_message_by_name("books.v1.Book").unwrap();
+    let book = DynamicMessage::decode(descriptor, call.messages[0].clone()).unwrap();
+    assert_eq!(serde_json::to_value(&book).unwrap(), json!({
+        "id": "b123",
+        "title": "The Rust Programming Language",
+        "pages": 560,
+        "tags": ["rust", "programming"],
+        "published": "2018-08-12T00:00:00Z",
+        "format": "EBOOK"
+    }));
+}
+
+#[tokio::test]
+async fn test_failed_calls_end_with_a_grpc_status() {
+    let (addr, registry) = start_server().await;
+
+    // The mocked HTTP status maps to a gRPC code, sent as trailers-only
+    let call_result = call(addr, "/books.v1.BookService/DeleteBook", "application/grpc", &get_book_request("b123")).await;
+    assert_eq!(call_result.status, StatusCode::OK);
+    assert!(call_result.messages.is_empty());
+    assert_eq!(grpc_status(&call_result), Some("7"));
+    assert_eq!(call_result.headers["grpc-message"], "Books%20are%20forever");
+
+    let unknown = call(addr, "/books.v1.BookService/BuyBook", "application/grpc", &[]).await;
+    assert_eq!(grpc_status(&unknown), Some("12"));
+
+    let undecodable = call(addr, "/books.v1.BookService/GetBook", "application/grpc", &[0xff, 0xff]).await;
+    assert_eq!(grpc_status(&undecodable), Some("13"));
+
+    let not_grpc = call(addr, "/books.v1.BookService/GetBook", "application/json", &[]).await;
+    assert_eq!(not_grpc.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // A described method without a mock is unimplemented until one is registered
+    let method = registry.find_method("books.v1.BookService", "GetBook").await.unwrap();
+    assert!(registry.find_mock(&method, &json!({})).await.is_some());
+    let other = Arc::new(GrpcRegistry::new());
+    other.add_descriptors(compile_protos(&protos()).unwrap()).await.unwrap();
+    assert!(other.find_mock(&method, &json!({})).await.is_none());
+}
+
+#[tokio::test]
+async fn test_status_codes_details_and_metadata() {
+    let (addr, registry) = start_server().await;
+
+    // Metadata comes with the headers, custom trailers along with the status
+    registry.register_mock("books.v1.BookService", "GetBook", GrpcMockResponse {
+        output: json!({ "id": "b123" }),
+        metadata: [("x-request-id".to_string(), "r-1".to_string())].into(),
+        trailers: [("x-served-by".to_string(), "mockiapi".to_string())].into(),
+        ..Default::default()
+    }).await;
+    let success = call(addr, "/books.v1.BookService/GetBook", "application/grpc", &get_book_request("b123")).await;
+    assert_eq!(success.headers["x-request-id"], "r-1");
+    let trailers = success.trailers.as_ref().unwrap();
+    assert_eq!(trailers["x-served-by"], "mockiapi");
+    assert_eq!(trailers["grpc-status"], "0");
+
+    registry.register_mock("books.v1.BookService", "GetBook", GrpcMockResponse {
+        output: json!({}),
+        code: Some("UNAVAILABLE".parse().unwrap()),
+        message: Some("Try again later".to_string()),
+        details: vec![
+            json!({ "@type": "type.googleapis.com/google.rpc.ErrorInfo", "reason": "MAINTENANCE", "domain": "books.example.com" }),
+            json!({ "@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "5s" }),
+        ],
+        trailers: [("x-served-by".to_string(), "mockiapi".to_string())].into(),
+        ..Default::default()
+    }).await;
+    let failure = call(addr, "/books.v1.BookService/GetBook", "application/grpc", &get_book_request("b123")).await;
+    assert!(failure.messages.is_empty());
+    assert_eq!(grpc_status(&failure), Some("14"));
+    assert_eq!(failure.headers["grpc-message"], "Try%20again%20later");
+    assert_eq!(failure.headers["x-served-by"], "mockiapi");
+
+    let details = STANDARD_NO_PAD.decode(failure.headers["grpc-status-details-bin"].as_bytes()).unwrap();
+    let descriptor = pool().get_message_by_name("google.rpc.Status").unwrap();
+    let status = DynamicMessage::decode(descriptor, details.as_slice()).unwrap();
+    assert_eq!(serde_json::to_value(&status).unwrap(), json!({
+        "code": 14,
+        "message": "Try again later",
+        "details": [
+            { "@type": "type.googleapis.com/google.rpc.ErrorInfo", "reason": "MAINTENANCE", "domain": "books.example.com" },
+            { "@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "5s" },
+        ]
+    }));
+
+    // Details of an unknown type cannot be encoded
+    registry.register_mock("books.v1.BookService", "GetBook", GrpcMockResponse {
+        output: json!({}),
+        code: Some("NOT_FOUND".parse().unwrap()),
+        details: vec![json!({ "@type": "type.googleapis.com/acme.Unknown" })],
+        ..Default::default()
+    }).await;
+    let invalid = call(addr, "/books.v1.BookService/GetBook", "application/grpc", &get_book_request("b123")).await;
+    assert_eq!(grpc_status(&invalid), Some("13"));
+}
+
+#[tokio::test]
+async fn test_descriptor_loading() {
+    // Descriptor sets load like compiled protos, and reloading a file replaces it
+    let bytes = compile_protos(&protos()).unwrap().encode();
+    let registry = GrpcRegistry::new();
+    registry.add_descriptors(decode_descriptor_set(&bytes).unwrap()).await.unwrap();
+    assert!(registry.find_method("books.v1.BookService", "GetBook").await.is_some());
+
+    let renamed = BOOKS_PROTO.replace("rpc GetBook", "rpc FetchBook");
+    let protos = [("books.proto".to_string(), renamed), ("common.proto".to_string(), COMMON_PROTO.to_string())];
+    registry.add_descriptors(compile_protos(&protos).unwrap()).await.unwrap();
+    assert!(registry.find_method("books.v1.BookService", "GetBook").await.is_none());
+    assert!(registry.find_method("books.v1.BookService", "FetchBook").await.is_some());
+
+    assert!(compile_protos(&[("broken.proto".to_string(), "message {".to_string())]).is_err());
+    // Imports are uploaded together
+    let missing_import = compile_protos(&[("books.proto".to_string(), BOOKS_PROTO.to_string())]).unwrap_err();
+    assert!(missing_import.contains("common.proto"));
+    assert!(compile_protos(&[("../escape.proto".to_string(), COMMON_PROTO.to_string())]).is_err());
+    assert!(decode_descriptor_set(b"not a descriptor set").is_err());
+}
+
+#[tokio::test]
+async fn test_registration_loads_protos_only_once_valid() {
+    let boundary = "mockiapi-boundary";
+    let mut body = String::new();
+    for (name, proto) in protos() {
+        body.push_str(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"proto\"; filename=\"{}\"\r\n\r\n{}\r\n",
+            boundary, name, proto
+        ));
+    }
+    // A path but no mock file: the registration is refused
+    body.push_str(&format!("--{}\r\nContent-Disposition: form-data; name=\"path\"\r\n\r\n/grpc/books\r\n--{}--\r\n", boundary, boundary));
+    let form = warp::test::request()
+        .method("POST")
+        .header("content-type", format!("multipart/form-data; boundary={}", boundary))
+        .body(body)
+        .filter(&warp::multipart::form())
+        .await
+        .unwrap();
+
+    let registry = Arc::new(GrpcRegistry::new());
+    let endpoints = Arc::new(tokio::sync::Mutex::new(std::collections::HashMap::new()));
+    assert!(MultipartHandler::parse(form, endpoints, registry.clone()).await.is_err());
+    assert!(registry.find_method("books.v1.BookService", "GetBook").await.is_none());
+}
+
+/// A reflection stream, asked one question at a time like grpcurl does.
+struct ReflectionStream {
+    sender: hyper::body::Sender,
+    response: hyper::Response<Body>,
+    buffer: BytesMut,
+}
+
+impl ReflectionStream {
+    async fn open(addr: SocketAddr, version: &str) -> Self {
+        let client = Client::builder().http2_only(true).build_http::<Body>();
+        let (sender, body) = Body::channel();
+        let request = Request::post(format!("http://{}/grpc.reflection.{}.ServerReflection/ServerReflectionInfo", addr, version))
+            .header("content-type", "application/grpc")
+            .body(body)
+            .unwrap();
+        let response = client.request(request).await.unwrap();
+        Self { sender, response, buffer: BytesMut::new() }
+    }
+
+    async fn ask(&mut self, request: MessageRequest) -> MessageResponse {
+        let request = ServerReflectionRequest { host: String::new(), message_request: Some(request) };
+        self.sender.send_data(encode_frame(&request.encode_to_vec())).await.unwrap();
+        loop {
+            if self.buffer.len() >= 5 {
+                let length = u32::from_be_bytes([self.buffer[1], self.buffer[2], self.buffer[3], self.buffer[4]]) as usize;
+                if self.buffer.len() >= 5 + length {
+                    let frame = self.buffer.split_to(5 + length).split_off(5);
+                    let response = ServerReflectionResponse::decode(frame.freeze()).unwrap();
+                    assert_eq!(response.original_request, Some(request));
+                    return response.message_response.unwrap();
+                }
+            }
+            let chunk = self.response.body_mut().data().await.unwrap().unwrap();
+            self.buffer.extend_from_slice(&chunk);
+        }
+    }
+}
+
+fn file_names(response: MessageResponse) -> Vec<String> {
+    let MessageResponse::FileDescriptorResponse(files) = response else {
+        panic!("Expected files, got {:?}", response);
+    };
+    files.file_descriptor_proto.iter()
+        .map(|file| FileDescriptorProto::decode(file.as_slice()).unwrap().name().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_server_reflection() {
+    let (addr, _) = start_server().await;
+
+    for version in ["v1", "v1alpha"] {
+        let mut stream = ReflectionStream::open(addr, version).await;
+        assert_eq!(stream.response.headers()["content-type"], "application/grpc");
+
+        let MessageResponse::ListServicesResponse(services) = stream.ask(MessageRequest::ListServices(String::new())).await else {
+            panic!("Expected the services");
+        };
+        let names: Vec<&str> = services.service.iter().map(|service| service.name.as_str()).collect();
+        assert_eq!(names, ["books.v1.BookService"]);
+
+        // A file comes with its imports
+        let mut files = file_names(stream.ask(MessageRequest::FileContainingSymbol("books.v1.BookService".to_string())).await);
+        assert_eq!(files[0], "books.proto");
+        files.sort();
+        assert_eq!(files, ["books.proto", "common.proto", "google/protobuf/timestamp.proto"]);
+
+        for symbol in ["books.v1.BookService.GetBook", "books.v1.Book", "books.v1.Book.title", "common.Format"] {
+            assert!(!file_names(stream.ask(MessageRequest::FileContainingSymbol(symbol.to_string())).await).is_empty());
+        }
+        assert_eq!(file_names(stream.ask(MessageRequest::FileByFilename("common.proto".to_string())).await), ["common.proto"]);
+
+        let MessageResponse::ErrorResponse(error) = stream.ask(MessageRequest::FileContainingSymbol("books.v1.Author".to_string())).await else {
+            panic!("Expected an error");
+        };
+        assert_eq!(error.error_code, 5);
+
+        let MessageResponse::AllExtensionNumbersResponse(extensions) = stream.ask(MessageRequest::AllExtensionNumbersOfType("books.v1.Book".to_string())).await else {
+            panic!("Expected the extension numbers");
+        };
+        assert!(extensions.extension_number.is_empty());
+
+        // Closing the request side ends the call
+        drop(stream.sender);
+        while stream.response.body_mut().data().await.is_some() {}
+        let trailers = stream.response.body_mut().trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+    }
+}
+
+/// A call sending messages as the test goes, and reading the ones answered as they arrive.
+struct StreamingCall {
+    sender: Option<hyper::body::Sender>,
+    pending: Option<tokio::task::JoinHandle<hyper::Response<Body>>>,
+    response: Option<hyper::Response<Body>>,
+    buffer: BytesMut,
+}
+
+impl StreamingCall {
+    fn open(addr: SocketAddr, path: &str) -> Self {
+        let client = Client::builder().http2_only(true).build_http::<Body>();
+        let (sender, body) = Body::channel();
+        let request = Request::post(format!("http://{}{}", addr, path))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .body(body)
+            .unwrap();
+        // The headers only come once the server starts answering
+        let pending = tokio::spawn(async move { client.request(request).await.unwrap() });
+        Self { sender: Some(sender), pending: Some(pending), response: None, buffer: BytesMut::new() }
+    }
+
+    async fn send(&mut self, message: serde_json::Value) {
+        let descriptor = pool().get_message_by_name("books.v1.Book").unwrap();
+        let message = DynamicMessage::deserialize(descriptor, message).unwrap().encode_to_vec();
+        self.sender.as_mut().unwrap().send_data(encode_frame(&message)).await.unwrap();
+    }
+
+    fn close(&mut self) {
+        self.sender = None;
+    }
+
+    async fn response(&mut self) -> &mut hyper::Response<Body> {
+        if let Some(pending) = self.pending.take() {
+            self.response = Some(pending.await.unwrap());
+        }
+        self.response.as_mut().unwrap()
+    }
+
+    /// The next message answered, as JSON of the given type, or `None` once the server is done.
+    async fn next(&mut self, message: &str) -> Option<serde_json::Value> {
+        loop {
+            if self.buffer.len() >= 5 {
+                let length = u32::from_be_bytes([self.buffer[1], self.buffer[2], self.buffer[3], self.buffer[4]]) as usize;
+                if self.buffer.len() >= 5 + length {
+                    let frame = self.buffer.split_to(5 + length).split_off(5);
+                    let descriptor = pool().get_message_by_name(message).unwrap();
+                    return Some(serde_json::to_value(DynamicMessage::decode(descriptor, frame.freeze()).unwrap()).unwrap());
+                }
+            }
+            let chunk = self.response().await.body_mut().data().await?.unwrap();
+            self.buffer.extend_from_slice(&chunk);
+        }
+    }
+
+    async fn grpc_status(&mut self) -> String {
+        let response = self.response().await;
+        let trailers = response.body_mut().trailers().await.unwrap();
+        let headers = trailers.as_ref().unwrap_or(response.headers());
+        headers["grpc-status"].to_str().unwrap().to_string()
+    }
+}
+
+#[tokio::test]
+async fn test_server_streaming_sends_the_mocked_messages() {
+    let (addr, registry) = start_server().await;
+    registry.register_mock("BookService", "ListBooks", GrpcMockResponse {
+        output: json!({}),
+        stream: vec![
+            GrpcStreamMessage { output: json!({ "id": "b1" }), delay_ms: None },
+            GrpcStreamMessage { output: json!({ "id": "b2" }), delay_ms: Some(100) },
+            GrpcStreamMessage { output: json!({ "id": "b3" }), delay_ms: Some(100) },
+        ],
+        ..Default::default()
+    }).await;
+
+    let start = Instant::now();
+    let listed = call(addr, "/books.v1.BookService/ListBooks", "application/grpc", &get_book_request("any")).await;
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(grpc_status(&listed), Some("0"));
+    let descriptor = pool().get_message_by_name("books.v1.Book").unwrap();
+    let ids: Vec<String> = listed.messages.iter()
+        .map(|message| DynamicMessage::decode(descriptor.clone(), message.clone()).unwrap())
+        .map(|book| book.get_field_by_name("id").unwrap().as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(ids, ["b1", "b2", "b3"]);
+
+    // A failing stream sends its messages before the status
+    registry.register_mock("BookService", "ListBooks", GrpcMockResponse {
+        output: json!({}),
+        code: Some("ABORTED".parse().unwrap()),
+        stream: vec![GrpcStreamMessage { output: json!({ "id": "b1" }), delay_ms: None }],
+        ..Default::default()
+    }).await;
+    let aborted = call(addr, "/books.v1.BookService/ListBooks", "application/grpc", &get_book_request("any")).await;
+    assert_eq!(aborted.messages.len(), 1);
+    assert_eq!(aborted.trailers.as_ref().unwrap()["grpc-status"], "10");
+}
+
+#[tokio::test]
+async fn test_client_streaming_answers_after_the_client_messages() {
+    let (addr, registry) = start_server().await;
+    registry.register_mock("BookService", "UploadBooks", GrpcMockResponse {
+        output: json!({ "count": 2 }),
+        ..Default::default()
+    }).await;
+
+    // Without `respond_after`, the answer comes once the client is done
+    let mut upload = StreamingCall::open(addr, "/books.v1.BookService/UploadBooks");
+    upload.send(json!({ "id": "b1" })).await;
+    upload.send(json!({ "id": "b2" })).await;
+    upload.close();
+    assert_eq!(upload.next("books.v1.UploadSummary").await, Some(json!({ "count": 2 })));
+    assert_eq!(upload.grpc_status().await, "0");
+
+    registry.register_mock("BookService", "UploadBooks", GrpcMockResponse {
+        output: json!({ "count": 2 }),
+        respond_after: Some(2),
+        ..Default::default()
+    }).await;
+    let mut upload = StreamingCall::open(addr, "/books.v1.BookService/UploadBooks");
+    upload.send(json!({ "id": "b1" })).await;
+    upload.send(json!({ "id": "b2" })).await;
+    // Still sending, but answered already
+    let summary = tokio::time::timeout(Duration::from_secs(5), upload.next("books.v1.UploadSummary")).await.unwrap();
+    assert_eq!(summary, Some(json!({ "count": 2 })));
+    assert_eq!(upload.grpc_status().await, "0");
+}
+
+#[tokio::test]
+async fn test_bidirectional_streaming_echoes_the_client_messages() {
+    let (addr, registry) = start_server().await;
+    registry.register_mock("BookService", "SyncBooks", GrpcMockResponse {
+        output: json!({ "title": "synced" }),
+        echo: true,
+        metadata: [("x-sync".to_string(), "on".to_string())].into(),
+        ..Default::default()
+    }).await;
+
+    let mut sync = StreamingCall::open(addr, "/books.v1.BookService/SyncBooks");
+    sync.send(json!({ "id": "b1", "title": "draft" })).await;
+    assert_eq!(sync.response().await.headers()["x-sync"], "on");
+    assert_eq!(sync.next("books.v1.Book").await, Some(json!({ "id": "b1", "title": "synced" })));
+
+    sync.send(json!({ "id": "b2", "pages": 10 })).await;
+    assert_eq!(sync.next("books.v1.Book").await, Some(json!({ "id": "b2", "title": "synced", "pages": 10 })));
+
+    sync.close();
+    assert_eq!(sync.next("books.v1.Book").await, None);
+    assert_eq!(sync.grpc_status().await, "0");
+}
+
+#[tokio::test]
+async fn test_native_calls_matched_and_templated_with_the_request() {
+    let (addr, registry) = start_server().await;
+    registry.register_mock("BookService", "GetBook", GrpcMockResponse {
+        output: json!({ "id": "{{request.id}}", "title": "Unreleased" }),
+        matchers: vec![GrpcMatcher { field: "id".into(), equals: None, regex: Some("^draft-".into()) }],
+        with_dynamic_vars: true,
+        ..Default::default()
+    }).await;
+
+    let descriptor = pool().get_message_by_name("books.v1.Book").unwrap();
+    let draft = call(addr, "/books.v1.BookService/GetBook", "application/grpc", &get_book_request("draft-7")).await;
+    let book = DynamicMessage::decode(descriptor.clone(), draft.messages[0].clone()).unwrap();
+    assert_eq!(serde_json::to_value(&book).unwrap(), json!({ "id": "draft-7", "title": "Unreleased" }));
+
+    // Other requests still get the default mock
+    let published = call(addr, "/books.v1.BookService/GetBook", "application/grpc", &get_book_request("b123")).await;
+    let book = DynamicMessage::decode(descriptor, published.messages[0].clone()).unwrap();
+    assert_eq!(book.get_field_by_name("title").unwrap().as_str(), Some("The Rust Programming Language"));
+}
+
+/// A gRPC-Web answer: its data frames, and the trailers of its last frame.
+struct WebCall {
+    headers: HeaderMap,
+    messages: Vec<Bytes>,
+    trailers: String,
+}
+
+async fn web_call(addr: SocketAddr, path: &str, content_type: &str, message: &[u8]) -> WebCall {
+    let text = content_type.starts_with("application/grpc-web-text");
+    let frame = encode_frame(message);
+    let body = match text {
+        true => Bytes::from(STANDARD.encode(frame)),
+        false => frame,
+    };
+    // Browsers call over HTTP/1.1
+    let request = Request::post(format!("http://{}{}", addr, path))
+        .header("content-type", content_type)
+        .header("x-grpc-web", "1")
+        .body(Body::from(body))
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    assert_eq!(response.version(), hyper::Version::HTTP_11);
+    let headers = response.headers().clone();
+    let mut body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    if text {
+        // Each chunk is padded on its own
+        let decoded: Vec<u8> = body.chunks(4).flat_map(|group| STANDARD.decode(group).unwrap()).collect();
+        body = Bytes::from(decoded);
+    }
+
+    let mut messages = Vec::new();
+    let mut trailers = String::new();
+    while body.len() >= 5 {
+        let length = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        let frame = body.slice(5..5 + length);
+        match body[0] {
+            0x80 => trailers = String::from_utf8(frame.to_vec()).unwrap(),
+            _ => messages.push(frame),
+        }
+        body = body.slice(5 + length..);
+    }
+    WebCall { headers, messages, trailers }
+}
+
+#[tokio::test]
+async fn test_grpc_web_calls_in_binary_and_text() {
+    let (addr, registry) = start_server().await;
+    let descriptor = pool().get_message_by_name("books.v1.Book").unwrap();
+
+    for content_type in ["application/grpc-web+proto", "application/grpc-web-text"] {
+        let web = web_call(addr, "/books.v1.BookService/GetBook", content_type, &get_book_request("b123")).await;
+        assert_eq!(web.headers["content-type"], content_type);
+        assert_eq!(web.headers["access-control-allow-origin"], "*");
+        assert_eq!(web.messages.len(), 1);
+        let book = DynamicMessage::decode(descriptor.clone(), web.messages[0].clone()).unwrap();
+        assert_eq!(book.get_field_by_name("id").unwrap().as_str(), Some("b123"));
+        assert_eq!(web.trailers, "grpc-status: 0\r\n");
+    }
+
+    // Failures without messages keep their status in the headers
+    let denied = web_call(addr, "/books.v1.BookService/DeleteBook", "application/grpc-web+proto", &get_book_request("b123")).await;
+    assert!(denied.messages.is_empty());
+    assert_eq!(denied.headers["grpc-status"], "7");
+    assert!(denied.headers["access-control-expose-headers"].to_str().unwrap().contains("grpc-status"));
+
+    registry.register_mock("BookService", "ListBooks", GrpcMockResponse {
+        output: json!({}),
+        stream: vec![
+            GrpcStreamMessage { output: json!({ "id": "b1" }), delay_ms: None },
+            GrpcStreamMessage { output: json!({ "id": "b2" }), delay_ms: None },
+        ],
+        trailers: [("x-served-by".to_string(), "mockiapi".to_string())].into(),
+        ..Default::default()
+    }).await;
+    let listed = web_call(addr, "/books.v1.BookService/ListBooks", "application/grpc-web-text+proto", &get_book_request("any")).await;
+    assert_eq!(listed.messages.len(), 2);
+    assert!(listed.trailers.contains("x-served-by: mockiapi\r\n"));
+    assert!(listed.trailers.contains("grpc-status: 0\r\n"));
+
+    let preflight = Request::options(format!("http://{}/books.v1.BookService/GetBook", addr))
+        .header("origin", "http://localhost:3000")
+        .header("access-control-request-method", "POST")
+        .header("access-control-request-headers", "content-type,x-grpc-web")
+        .body(Body::empty())
+        .unwrap();
+    let preflight = Client::new().request(preflight).await.unwrap();
+    assert_eq!(preflight.status(), StatusCode::NO_CONTENT);
+    assert_eq!(preflight.headers()["access-control-allow-origin"], "*");
+    assert_eq!(preflight.headers()["access-control-allow-headers"], "content-type,x-grpc-web");
+}
+
+const LIBRARY_PROTO: &str = r#"
+syntax = "proto3";
+package library.v1;
+
+import "google/api/annotations.proto";
+import "books.proto";
+
+service Library {
+  rpc GetShelf (GetShelfRequest) returns (Shelf) {
+    option (google.api.http) = {
+      get: "/v1/{name=shelves/*}"
+      additional_bindings { get: "/v2/{name=shelves/*}" }
+    };
+  }
+  rpc ListShelfBooks (ListShelfBooksRequest) returns (ListShelfBooksResponse) {
+    option (google.api.http) = { get: "/v1/{shelf=shelves/*}/books" response_body: "books" };
+  }
+  rpc CreateShelf (CreateShelfRequest) returns (Shelf) {
+    option (google.api.http) = { post: "/v1/shelves" body: "shelf" };
+  }
+  rpc PublishShelf (PublishShelfRequest) returns (Shelf) {
+    option (google.api.http) = { post: "/v1/{name=shelves/*}:publish" body: "*" };
+  }
+}
+
+message Shelf {
+  string name = 1;
+  string theme = 2;
+  int64 book_count = 3;
+}
+
+message GetShelfRequest {
+  string name = 1;
+}
+
+message ListShelfBooksRequest {
+  string shelf = 1;
+  int32 page_size = 2;
+  bool include_drafts = 3;
+  repeated string tags = 4;
+}
+
+message ListShelfBooksResponse {
+  repeated books.v1.Book books = 1;
+  string next_page_token = 2;
+}
+
+message CreateShelfRequest {
+  Shelf shelf = 1;
+  string request_id = 2;
+}
+
+message PublishShelfRequest {
+  string name = 1;
+  string note = 2;
+}
+"#;
+
+async fn rest_call(addr: SocketAddr, method: &str, path: &str, body: &str) -> (StatusCode, HeaderMap, serde_json::Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path))
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    let (status, headers) = (response.status(), response.headers().clone());
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, headers, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+}
+
+#[tokio::test]
+async fn test_http_json_transcoding_from_the_http_options() {
+    let (addr, registry) = start_server().await;
+    let mut protos = protos();
+    protos.push(("library.proto".to_string(), LIBRARY_PROTO.to_string()));
+    registry.add_descriptors(compile_protos(&protos).unwrap()).await.unwrap();
+
+    registry.register_mock("library.v1.Library", "GetShelf", GrpcMockResponse {
+        output: json!({}),
+        code: Some("NOT_FOUND".parse().unwrap()),
+        message: Some("No such shelf".into()),
+        ..Default::default()
+    }).await;
+    registry.register_mock("library.v1.Library", "GetShelf", GrpcMockResponse {
+        output: json!({ "name": "{{request.name}}", "theme": "rust", "bookCount": 3, "ignored": true }),
+        matchers: vec![GrpcMatcher { field: "name".into(), equals: Some(json!("shelves/s1")), regex: None }],
+        with_dynamic_vars: true,
+        ..Default::default()
+    }).await;
+
+    // Path variables fill the request, and the output is the JSON of the message
+    for path in ["/v1/shelves/s1", "/v2/shelves/s1"] {
+        let (status, headers, body) = rest_call(addr, "GET", path, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "application/json");
+        assert_eq!(headers["access-control-allow-origin"], "*");
+        assert_eq!(body, json!({ "name": "shelves/s1", "theme": "rust", "bookCount": "3" }));
+    }
+
+    // Failures answer the status, with the HTTP status of its code
+    let (status, _, body) = rest_call(addr, "GET", "/v1/shelves/s2", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body, json!({ "code": 5, "message": "No such shelf", "details": [] }));
+
+    // Query parameters fill the other fields, typed by the descriptor
+    registry.register_mock("library.v1.Library", "ListShelfBooks", GrpcMockResponse {
+        output: json!({ "books": [{ "id": "b1", "title": "{{request.shelf}}" }], "nextPageToken": "p2" }),
+        matchers: vec![
+            GrpcMatcher { field: "page_size".into(), equals: Some(json!(2)), regex: None },
+            GrpcMatcher { field: "include_drafts".into(), equals: Some(json!(true)), regex: None },
+            GrpcMatcher { field: "$.tags[1]".into(), equals: Some(json!("go")), regex: None },
+        ],
+        with_dynamic_vars: true,
+        ..Default::default()
+    }).await;
+    let (status, _, body) = rest_call(addr, "GET", "/v1/shelves/s1/books?page_size=2&includeDrafts=true&tags=rust&tags=go&_=1700000000", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!([{ "id": "b1", "title": "shelves/s1" }]));
+    let (status, _, body) = rest_call(addr, "GET", "/v1/shelves/s1/books?page_size=3", "").await;
+    assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+    assert_eq!(body["code"], 12);
+
+    // The body fills its field, or the whole request
+    registry.register_mock("library.v1.Library", "CreateShelf", GrpcMockResponse {
+        output: json!({ "name": "{{request.shelf.name}}", "theme": "{{request.request_id}}" }),
+        with_dynamic_vars: true,
+        ..Default::default()
+    }).await;
+    let (status, _, body) = rest_call(addr, "POST", "/v1/shelves?request_id=r1", r#"{ "name": "shelves/new" }"#).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "name": "shelves/new", "theme": "r1" }));
+
+    registry.register_mock("library.v1.Library", "PublishShelf", GrpcMockResponse {
+        output: json!({ "name": "{{request.name}}", "theme": "{{request.note}}" }),
+        with_dynamic_vars: true,
+        ..Default::default()
+    }).await;
+    let (status, _, body) = rest_call(addr, "POST", "/v1/shelves/s1:publish", r#"{ "note": "spring" }"#).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "name": "shelves/s1", "theme": "spring" }));
+
+    // Requests that are not a valid message are invalid arguments
+    let (status, _, body) = rest_call(addr, "POST", "/v1/shelves", "{ not json").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], 3);
+    let (status, _, _) = rest_call(addr, "POST", "/v1/shelves/s1:publish", r#"{ "unknown": 1 }"#).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Other requests match no route
+    let (status, _, _) = rest_call(addr, "DELETE", "/v1/shelves/s1", "").await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+async fn test_matchers_read_proto_field_names_and_default_values() {
+    let (addr, registry) = start_server().await;
+    let mut protos = protos();
+    protos.push(("library.proto".to_string(), LIBRARY_PROTO.to_string()));
+    registry.add_descriptors(compile_protos(&protos).unwrap()).await.unwrap();
+
+    registry.register_mock("library.v1.Library", "CreateShelf", GrpcMockResponse {
+        output: json!({ "name": "{{request.shelf.name}}", "theme": "{{request.shelf.book_count}}" }),
+        matchers: vec![
+            GrpcMatcher { field: "request_id".into(), equals: Some(json!("")), regex: None },
+            GrpcMatcher { field: "shelf.book_count".into(), equals: Some(json!(0)), regex: None },
+        ],
+        with_dynamic_vars: true,
+        ..Default::default()
+    }).await;
+
+    // Fields left out of the request are matched and templated with their default value
+    let (status, _, body) = rest_call(addr, "POST", "/v1/shelves", r#"{ "name": "shelves/new" }"#).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "name": "shelves/new", "theme": "0" }));
+
+    let (status, _, _) = rest_call(addr, "POST", "/v1/shelves?request_id=r1", r#"{ "name": "shelves/new" }"#).await;
+    assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+}
+
+#[tokio::test]
+async fn test_oversized_messages_are_refused_before_being_read() {
+    let (addr, _) = start_server().await;
+
+    // A frame header announcing 4 GiB, without the message
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    let request = Request::post(format!("http://{}/books.v1.BookService/GetBook", addr))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(Body::from(vec![0, 0xff, 0xff, 0xff, 0xff]))
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.headers()["grpc-status"], "8");
+
+    let oversized = vec![0u8; 4 * 1024 * 1024 + 1];
+    for content_type in ["application/grpc-web+proto", "application/grpc-web-text"] {
+        let web = web_call(addr, "/books.v1.BookService/GetBook", content_type, &oversized).await;
+        assert!(web.messages.is_empty());
+        assert_eq!(web.headers["grpc-status"], "8");
+    }
+}
@@ -2,8 +2,10 @@ use std::sync::Arc;
 use serde_json::json;
 use warp::http::StatusCode;
 use mockiapi::handlers::grpc::grpc_handler;
+use mockiapi::handlers::grpc_transcoding::PathTemplate;
 use mockiapi::middlewares::grpc_registry::GrpcRegistry;
-use mockiapi::models::grpc::{GrpcMockRequest, GrpcMockResponse};
+use mockiapi::middlewares::json_path::JsonPath;
+use mockiapi::models::grpc::{GrpcCode, GrpcMatcher, GrpcMockRequest, GrpcMockResponse};
 
 #[tokio::test]
 async fn test_grpc_handler_success() {
@@ -16,6 +18,7 @@ async fn test_grpc_handler_success() {
             output: json!({"id": 1, "name": "Alice"}),
             delay_ms: None,
             status: Some(200),
+            ..Default::default()
         }
     ).await;
 
@@ -68,6 +71,7 @@ async fn test_grpc_handler_with_delay() {
             output: json!({"status": "ok"}),
             delay_ms: Some(200),
             status: Some(200),
+            ..Default::default()
         }
     ).await;
 
@@ -97,6 +101,7 @@ async fn test_grpc_handler_custom_status_code() {
             output: json!({"error": "Conflict"}),
             delay_ms: None,
             status: Some(409),
+            ..Default::default()
         }
     ).await;
 
@@ -135,6 +140,7 @@ async fn test_null_input_payload() {
         output: json!({"ok": true}),
         delay_ms: None,
         status: Some(200),
+        ..Default::default()
     }).await;
 
     let req = GrpcMockRequest {
@@ -156,6 +162,7 @@ async fn test_input_with_extra_fields() {
         output: json!({"message": "Received"}),
         delay_ms: None,
         status: Some(200),
+        ..Default::default()
     }).await;
 
     let req = GrpcMockRequest {
@@ -177,6 +184,7 @@ async fn test_large_input_payload() {
         output: json!({"status": "ok"}),
         delay_ms: None,
         status: Some(200),
+        ..Default::default()
     }).await;
 
     let big_string = "x".repeat(100_000);
@@ -189,4 +197,174 @@ async fn test_large_input_payload() {
     let boxed_reply = grpc_handler(req, registry).await.unwrap();
     let res = warp::reply::Reply::into_response(boxed_reply);
     assert_eq!(res.status(), StatusCode::OK);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_grpc_status_codes_in_the_json_shim() {
+    let registry = Arc::new(GrpcRegistry::new());
+
+    let mock: GrpcMockResponse = serde_json::from_value(json!({
+        "output": {},
+        "delay_ms": null,
+        "status": null,
+        "code": "DEADLINE_EXCEEDED",
+        "message": "Upstream timed out",
+        "details": [{ "@type": "type.googleapis.com/google.rpc.DebugInfo", "detail": "db" }],
+        "metadata": { "x-request-id": "r-1" },
+        "trailers": { "x-served-by": "mockiapi" }
+    })).unwrap();
+    assert_eq!(mock.code, Some(GrpcCode::DeadlineExceeded));
+    registry.register_mock("UserService", "SlowCase", mock).await;
+
+    let req = GrpcMockRequest {
+        service: "UserService".into(),
+        method: "SlowCase".into(),
+        input: json!({}),
+    };
+    let boxed_reply = grpc_handler(req, registry.clone()).await.unwrap();
+    let res = warp::reply::Reply::into_response(boxed_reply);
+
+    assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(res.headers()["grpc-status"], "4");
+    assert_eq!(res.headers()["grpc-metadata-x-request-id"], "r-1");
+    assert_eq!(res.headers()["grpc-trailer-x-served-by"], "mockiapi");
+
+    let body = warp::hyper::body::to_bytes(res.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json, json!({
+        "code": 4,
+        "message": "Upstream timed out",
+        "details": [{ "@type": "type.googleapis.com/google.rpc.DebugInfo", "detail": "db" }]
+    }));
+}
+
+#[test]
+fn test_grpc_code_names_and_numbers() {
+    assert_eq!("unavailable".parse::<GrpcCode>(), Ok(GrpcCode::Unavailable));
+    assert_eq!("14".parse::<GrpcCode>(), Ok(GrpcCode::Unavailable));
+    assert!("TEAPOT".parse::<GrpcCode>().is_err());
+    assert_eq!(serde_json::to_value(GrpcCode::NotFound).unwrap(), json!("NOT_FOUND"));
+    assert_eq!(serde_json::from_value::<GrpcCode>(json!(5)).unwrap(), GrpcCode::NotFound);
+    assert_eq!(GrpcCode::ResourceExhausted.to_http_status(), 429);
+}
+
+async fn call_output(registry: &Arc<GrpcRegistry>, method: &str, input: serde_json::Value) -> (StatusCode, serde_json::Value) {
+    let req = GrpcMockRequest {
+        service: "BookService".into(),
+        method: method.into(),
+        input,
+    };
+    let res = warp::reply::Reply::into_response(grpc_handler(req, registry.clone()).await.unwrap());
+    let status = res.status();
+    let body = warp::hyper::body::to_bytes(res.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn matcher(field: &str, equals: Option<serde_json::Value>, regex: Option<&str>) -> GrpcMatcher {
+    GrpcMatcher { field: field.into(), equals, regex: regex.map(String::from) }
+}
+
+#[tokio::test]
+async fn test_grpc_mocks_matched_on_the_input() {
+    let registry = Arc::new(GrpcRegistry::new());
+    registry.register_mock("BookService", "GetBook", GrpcMockResponse {
+        output: json!({ "title": "Any book" }),
+        ..Default::default()
+    }).await;
+    registry.register_mock("BookService", "GetBook", GrpcMockResponse {
+        output: json!({ "title": "The Rust Programming Language" }),
+        matchers: vec![matcher("id", Some(json!("b123")), None)],
+        ..Default::default()
+    }).await;
+    registry.register_mock("BookService", "GetBook", GrpcMockResponse {
+        output: json!({ "title": "A Rust book" }),
+        matchers: vec![matcher("$.filter.tags[*]", Some(json!("rust")), None), matcher("author.name", None, Some("^Steve"))],
+        ..Default::default()
+    }).await;
+
+    let (_, book) = call_output(&registry, "GetBook", json!({ "id": "b123" })).await;
+    assert_eq!(book["title"], "The Rust Programming Language");
+
+    let (_, book) = call_output(&registry, "GetBook", json!({ "filter": { "tags": ["async", "rust"] }, "author": { "name": "Steve Klabnik" } })).await;
+    assert_eq!(book["title"], "A Rust book");
+
+    // Every matcher must match, else the default answers
+    let (_, book) = call_output(&registry, "GetBook", json!({ "filter": { "tags": ["rust"] }, "author": { "name": "Carol" } })).await;
+    assert_eq!(book["title"], "Any book");
+    let (_, book) = call_output(&registry, "GetBook", json!({})).await;
+    assert_eq!(book["title"], "Any book");
+
+    // Registering the same matchers again replaces the mock
+    registry.register_mock("BookService", "GetBook", GrpcMockResponse {
+        output: json!({ "title": "Second edition" }),
+        matchers: vec![matcher("id", Some(json!("b123")), None)],
+        ..Default::default()
+    }).await;
+    let (_, book) = call_output(&registry, "GetBook", json!({ "id": "b123" })).await;
+    assert_eq!(book["title"], "Second edition");
+}
+
+#[tokio::test]
+async fn test_grpc_mocks_without_a_default() {
+    let registry = Arc::new(GrpcRegistry::new());
+    registry.register_mock("BookService", "CountBooks", GrpcMockResponse {
+        output: json!({ "count": 3 }),
+        matchers: vec![matcher("shelf", Some(json!(7)), None)],
+        ..Default::default()
+    }).await;
+
+    // Numbers also match their text, as protobuf JSON writes 64-bit integers as strings
+    let (status, count) = call_output(&registry, "CountBooks", json!({ "shelf": "7" })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(count["count"], 3);
+
+    let (status, _) = call_output(&registry, "CountBooks", json!({ "shelf": 8 })).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_grpc_output_templated_with_the_input() {
+    let registry = Arc::new(GrpcRegistry::new());
+    registry.register_mock("BookService", "GetBook", GrpcMockResponse {
+        output: json!({ "id": "{{request.id}}", "author": { "name": "{{request.author.name}}" }, "pages": 100 }),
+        with_dynamic_vars: true,
+        ..Default::default()
+    }).await;
+
+    let (_, book) = call_output(&registry, "GetBook", json!({ "id": "b9", "author": { "name": "Ferris \"the crab\"" } })).await;
+    assert_eq!(book, json!({ "id": "b9", "author": { "name": "Ferris \"the crab\"" }, "pages": 100 }));
+}
+
+#[test]
+fn test_json_path_selectors() {
+    let value = json!({ "a": { "list": [1, 2, { "b": "deep" }] }, "b": "top" });
+    let select = |path: &str| JsonPath::parse(path).unwrap().select(&value).into_iter().cloned().collect::<Vec<_>>();
+
+    assert_eq!(select("$.a.list[0]"), [json!(1)]);
+    assert_eq!(select("$.a.list[-1].b"), [json!("deep")]);
+    assert_eq!(select("$['a']['list'][*]").len(), 3);
+    assert_eq!(select("$..b"), [json!("top"), json!("deep")]);
+    assert_eq!(select("$.a.missing"), Vec::<serde_json::Value>::new());
+    assert_eq!(JsonPath::from_field("a.list.1").unwrap().select(&value), [&json!(2)]);
+    assert!(JsonPath::parse("a.b").is_err());
+    assert!(JsonPath::parse("$.a[").is_err());
+}
+
+#[test]
+fn test_http_path_templates() {
+    let template = PathTemplate::parse("/v1/{name=shelves/*/books/*}:publish").unwrap();
+    assert_eq!(template.matches("/v1/shelves/s1/books/b%201:publish"), Some(vec![("name".to_string(), "shelves/s1/books/b 1".to_string())]));
+    assert_eq!(template.matches("/v1/shelves/s1/books/b1"), None);
+    assert_eq!(template.matches("/v1/shelves/s1:publish"), None);
+
+    let template = PathTemplate::parse("/v1/{shelf}/files/{path=**}").unwrap();
+    assert_eq!(template.matches("/v1/s1/files/a/b/c"), Some(vec![
+        ("shelf".to_string(), "s1".to_string()),
+        ("path".to_string(), "a/b/c".to_string()),
+    ]));
+    assert_eq!(template.matches("/v1//files/a"), None);
+
+    assert!(PathTemplate::parse("v1/shelves").is_err());
+    assert!(PathTemplate::parse("/v1/{name=shelves/*").is_err());
+    assert!(PathTemplate::parse("/v1/**/books").is_err());
+}
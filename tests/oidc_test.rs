@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::digest::{digest, SHA256};
+use serde_json::{json, Value};
+use warp::{http::StatusCode, test::request, Filter};
+use warp::http::HeaderMap;
+use warp::http::header::AUTHORIZATION;
+use mockiapi::middlewares::authentication::validate_auth;
+use mockiapi::middlewares::oidc_provider::oidc_provider;
+use mockiapi::routes::oidc;
+
+fn token_filter() -> impl Filter<Extract = (Box<dyn warp::Reply>,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("oauth" / "token"))
+        .and(warp::body::form())
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(oidc::token)
+}
+
+async fn request_token(form: &str) -> (StatusCode, Value) {
+    let res = request()
+        .method("POST")
+        .path("/oauth/token")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(form)
+        .reply(&token_filter())
+        .await;
+    (res.status(), serde_json::from_slice(res.body()).unwrap())
+}
+
+fn bearer(token: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+    headers
+}
+
+fn provider_auth(extra: Value) -> Option<String> {
+    Some(json!({ "jwt": extra }).to_string())
+}
+
+#[tokio::test]
+async fn test_discovery_and_jwks() {
+    let filter = warp::path!(".well-known" / "openid-configuration")
+        .and_then(oidc::openid_configuration)
+        .or(warp::path!(".well-known" / "jwks.json").and_then(oidc::jwks));
+
+    let res = request().path("/.well-known/openid-configuration").reply(&filter).await;
+    let discovery: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(discovery["issuer"], oidc_provider().issuer);
+    assert!(discovery["jwks_uri"].as_str().unwrap().ends_with("/.well-known/jwks.json"));
+
+    let res = request().path("/.well-known/jwks.json").reply(&filter).await;
+    let jwks: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(jwks["keys"][0]["kty"], "EC");
+    assert_eq!(jwks["keys"][0]["alg"], "ES256");
+}
+
+#[tokio::test]
+async fn test_client_credentials_token_is_accepted_by_bearer_validation() {
+    let (status, body) = request_token("grant_type=client_credentials&client_id=svc&scope=orders:read").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.get("refresh_token").is_none());
+
+    let header = bearer(body["access_token"].as_str().unwrap());
+    let auth = provider_auth(json!({ "issuer": oidc_provider().issuer, "requiredScopes": ["orders:read"] }));
+    let principal = validate_auth(auth, &header, &None, &HashMap::new()).unwrap();
+    assert_eq!(principal.subject.as_deref(), Some("svc"));
+
+    let needs_write = provider_auth(json!({ "requiredScopes": ["orders:write"] }));
+    assert!(validate_auth(needs_write, &header, &None, &HashMap::new()).is_err());
+}
+
+#[tokio::test]
+async fn test_password_grant_and_refresh_rotation() {
+    let (status, body) = request_token("grant_type=password&client_id=web&username=alice&password=x&scope=openid").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["id_token"].is_string());
+
+    let refresh = body["refresh_token"].as_str().unwrap().to_string();
+    let (status, refreshed) = request_token(&format!("grant_type=refresh_token&refresh_token={}", refresh)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let header = bearer(refreshed["access_token"].as_str().unwrap());
+    assert_eq!(validate_auth(provider_auth(json!({})), &header, &None, &HashMap::new()).unwrap().subject.as_deref(), Some("alice"));
+
+    // Refresh tokens are single use
+    let (status, error) = request_token(&format!("grant_type=refresh_token&refresh_token={}", refresh)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"], "invalid_grant");
+}
+
+#[tokio::test]
+async fn test_authorization_code_flow_with_pkce() {
+    let verifier = "a-very-long-code-verifier-used-for-this-test";
+    let challenge = URL_SAFE_NO_PAD.encode(digest(&SHA256, verifier.as_bytes()));
+
+    let authorize = warp::post()
+        .and(warp::path!("oauth" / "authorize"))
+        .and(warp::body::form())
+        .and_then(oidc::approve);
+    let res = request()
+        .method("POST")
+        .path("/oauth/authorize")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(format!(
+            "response_type=code&client_id=spa&redirect_uri=http://app/cb&state=xyz&username=bob&code_challenge={}&code_challenge_method=S256",
+            challenge
+        ))
+        .reply(&authorize)
+        .await;
+
+    assert_eq!(res.status(), StatusCode::FOUND);
+    let location = res.headers()["location"].to_str().unwrap().to_string();
+    assert!(location.starts_with("http://app/cb?code="));
+    assert!(location.ends_with("&state=xyz"));
+    let code = location.split("code=").nth(1).unwrap().split('&').next().unwrap();
+
+    let (status, _) = request_token(&format!(
+        "grant_type=authorization_code&client_id=spa&code={}&redirect_uri=http://app/cb&code_verifier=wrong", code
+    )).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // The failed attempt consumed the code, so run the flow again with the right verifier
+    let res = request()
+        .method("POST")
+        .path("/oauth/authorize")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(format!(
+            "response_type=code&client_id=spa&redirect_uri=http://app/cb&username=bob&code_challenge={}&code_challenge_method=S256",
+            challenge
+        ))
+        .reply(&authorize)
+        .await;
+    let location = res.headers()["location"].to_str().unwrap().to_string();
+    let code = location.split("code=").nth(1).unwrap();
+
+    let (status, body) = request_token(&format!(
+        "grant_type=authorization_code&client_id=spa&code={}&redirect_uri=http://app/cb&code_verifier={}", code, verifier
+    )).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let header = bearer(body["access_token"].as_str().unwrap());
+    let principal = validate_auth(provider_auth(json!({ "audience": "spa" })), &header, &None, &HashMap::new()).unwrap();
+    assert_eq!(principal.subject.as_deref(), Some("bob"));
+}
+
+#[tokio::test]
+async fn test_token_without_scope_omits_the_scope_claim() {
+    let (status, body) = request_token("grant_type=client_credentials&client_id=svc").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let payload = body["access_token"].as_str().unwrap().split('.').nth(1).unwrap();
+    let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+    assert_eq!(claims["sub"], "svc");
+    assert!(claims.get("scope").is_none());
+}
+
+#[tokio::test]
+async fn test_token_without_grant_type_is_an_invalid_request() {
+    let (status, body) = request_token("client_id=svc&scope=orders:read").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "invalid_request");
+}
+
+#[tokio::test]
+async fn test_authorization_code_is_bound_to_its_client() {
+    let authorize = warp::post()
+        .and(warp::path!("oauth" / "authorize"))
+        .and(warp::body::form())
+        .and_then(oidc::approve);
+    let res = request()
+        .method("POST")
+        .path("/oauth/authorize")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body("response_type=code&client_id=spa&redirect_uri=http://app/cb&username=bob")
+        .reply(&authorize)
+        .await;
+    let location = res.headers()["location"].to_str().unwrap().to_string();
+    let code = location.split("code=").nth(1).unwrap();
+
+    let (status, body) = request_token(&format!(
+        "grant_type=authorization_code&client_id=other&code={}&redirect_uri=http://app/cb", code
+    )).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "invalid_grant");
+}
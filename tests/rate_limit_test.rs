@@ -0,0 +1,180 @@
+use std::time::Duration;
+use tokio::time::sleep;
+use mockiapi::middlewares::rate_limit::{new_rate_limit, RateLimiter};
+use mockiapi::models::{RateLimit, RateLimitAlgorithm};
+
+fn limit(requests: usize, window_ms: u64, algorithm: RateLimitAlgorithm) -> RateLimit {
+    RateLimit { requests, window_ms, algorithm, ..Default::default() }
+}
+
+#[test]
+fn test_parse_rate_limit() {
+    let parsed: RateLimit = "5/1000".parse().unwrap();
+    assert_eq!((parsed.requests, parsed.window_ms), (5, 1000));
+    assert_eq!(parsed.algorithm, RateLimitAlgorithm::FixedWindow);
+
+    let parsed: RateLimit = r#"{"requests": 2, "window_ms": 10, "algorithm": "tokenBucket", "burst": 4}"#.parse().unwrap();
+    assert_eq!(parsed.algorithm, RateLimitAlgorithm::TokenBucket);
+    assert_eq!(parsed.burst, Some(4));
+
+    assert!("five".parse::<RateLimit>().is_err());
+    assert!("slidingLog".parse::<RateLimitAlgorithm>().is_ok());
+}
+
+#[tokio::test]
+async fn test_sliding_log_has_no_boundary_burst() {
+    let tracker = new_rate_limit();
+    let limit = limit(2, 300, RateLimitAlgorithm::SlidingLog);
+
+    assert!(tracker.check("k", &limit).await.allowed);
+    sleep(Duration::from_millis(200)).await;
+    assert!(tracker.check("k", &limit).await.allowed);
+    assert!(!tracker.check("k", &limit).await.allowed);
+
+    // The first hit has left the window, the second one has not
+    sleep(Duration::from_millis(150)).await;
+    let decision = tracker.check("k", &limit).await;
+    assert!(decision.allowed);
+    assert_eq!(decision.remaining, 0);
+    assert!(!tracker.check("k", &limit).await.allowed);
+}
+
+#[tokio::test]
+async fn test_sliding_window_weighs_previous_window() {
+    let tracker = new_rate_limit();
+    let limit = limit(4, 200, RateLimitAlgorithm::SlidingWindow);
+
+    for _ in 0..4 {
+        assert!(tracker.check("k", &limit).await.allowed);
+    }
+    assert!(!tracker.check("k", &limit).await.allowed);
+
+    // Early in the next window most of the previous count still applies,
+    // where a fixed window would already allow a new burst
+    sleep(Duration::from_millis(220)).await;
+    assert!(!tracker.check("k", &limit).await.allowed);
+
+    sleep(Duration::from_millis(200)).await;
+    assert!(tracker.check("k", &limit).await.allowed);
+}
+
+#[tokio::test]
+async fn test_token_bucket_burst_and_refill() {
+    let tracker = new_rate_limit();
+    let limit = RateLimit { burst: Some(3), ..limit(1, 100, RateLimitAlgorithm::TokenBucket) };
+
+    for _ in 0..3 {
+        assert!(tracker.check("k", &limit).await.allowed);
+    }
+    let refused = tracker.check("k", &limit).await;
+    assert!(!refused.allowed);
+    assert!(refused.retry_after.unwrap() <= Duration::from_millis(100));
+
+    sleep(Duration::from_millis(120)).await;
+    assert!(tracker.check("k", &limit).await.allowed);
+    assert!(!tracker.check("k", &limit).await.allowed);
+}
+
+#[tokio::test]
+async fn test_fixed_window_resets_after_window() {
+    let tracker = RateLimiter::new(None);
+    let limit = limit(1, 50, RateLimitAlgorithm::FixedWindow);
+
+    assert!(tracker.check("k", &limit).await.allowed);
+    assert!(!tracker.check("k", &limit).await.allowed);
+    assert!(tracker.check("other", &limit).await.allowed);
+
+    sleep(Duration::from_millis(60)).await;
+    assert!(tracker.check("k", &limit).await.allowed);
+}
+
+#[tokio::test]
+async fn test_token_bucket_with_more_requests_than_u32() {
+    let tracker = new_rate_limit();
+    let limit = limit(1 << 32, 1000, RateLimitAlgorithm::TokenBucket);
+
+    let decision = tracker.check("k", &limit).await;
+    assert!(decision.allowed);
+    assert_eq!(decision.remaining, (1 << 32) - 1);
+}
+
+#[tokio::test]
+async fn test_list_and_reset_windows() {
+    let tracker = new_rate_limit();
+    let limit = limit(3, 60_000, RateLimitAlgorithm::FixedWindow);
+
+    tracker.check("/orders|GET|ip=10.0.0.1", &limit).await;
+    tracker.check("/orders|GET|ip=10.0.0.1", &limit).await;
+    tracker.check("/orders|GET|ip=10.0.0.2", &limit).await;
+    tracker.check("/orders-archive|GET", &limit).await;
+
+    let windows = tracker.windows().await;
+    assert_eq!(windows.len(), 3);
+    assert_eq!(windows[1].key, "/orders|GET|ip=10.0.0.1");
+    assert_eq!((windows[1].count, windows[1].remaining), (2, 1));
+    assert!(windows[1].reset_ms <= 60_000);
+
+    // Listing does not count requests
+    assert_eq!(tracker.windows().await[1].count, 2);
+
+    assert_eq!(tracker.reset(Some("/orders|GET")).await, 2);
+    assert_eq!(tracker.windows().await.len(), 1);
+    assert_eq!(tracker.reset(None).await, 1);
+}
+
+#[tokio::test]
+async fn test_exhaust_forces_refusals_then_expires() {
+    let tracker = new_rate_limit();
+    let limit = limit(100, 60_000, RateLimitAlgorithm::SlidingLog);
+
+    assert!(tracker.check("/orders|GET|ip=10.0.0.1", &limit).await.allowed);
+    tracker.exhaust("/orders|GET", Duration::from_millis(100)).await.unwrap();
+
+    let refused = tracker.check("/orders|GET|ip=10.0.0.1", &limit).await;
+    assert!(!refused.allowed);
+    assert_eq!(refused.remaining, 0);
+    assert!(refused.retry_after.unwrap() <= Duration::from_millis(100));
+    assert!(tracker.windows().await[0].exhausted_ms.is_some());
+
+    sleep(Duration::from_millis(120)).await;
+    let decision = tracker.check("/orders|GET|ip=10.0.0.1", &limit).await;
+    assert!(decision.allowed);
+    // Refused requests were not counted
+    assert_eq!(decision.remaining, 98);
+}
+
+#[tokio::test]
+async fn test_exhaust_lists_unseen_keys_and_refuses_endless_durations() {
+    let tracker = new_rate_limit();
+    tracker.exhaust("/orders|GET", Duration::from_secs(60)).await.unwrap();
+
+    let windows = tracker.windows().await;
+    assert_eq!(windows.len(), 1);
+    assert_eq!(windows[0].key, "/orders|GET");
+    assert!(windows[0].algorithm.is_none());
+    assert!(windows[0].exhausted_ms.is_some_and(|left| left <= 60_000));
+
+    assert!(tracker.exhaust("/orders|GET", Duration::MAX).await.is_err());
+}
+
+#[tokio::test]
+async fn test_huge_windows_and_bursts_do_not_overflow() {
+    let tracker = new_rate_limit();
+    let algorithms = [
+        RateLimitAlgorithm::FixedWindow,
+        RateLimitAlgorithm::SlidingLog,
+        RateLimitAlgorithm::SlidingWindow,
+        RateLimitAlgorithm::TokenBucket,
+    ];
+    for (i, algorithm) in algorithms.into_iter().enumerate() {
+        let limit = limit(1, u64::MAX, algorithm);
+        let key = format!("huge-window-{}", i);
+        assert!(tracker.check(&key, &limit).await.allowed);
+        assert!(!tracker.check(&key, &limit).await.allowed);
+    }
+
+    let burst = RateLimit { burst: Some(usize::MAX), ..limit(1, 1000, RateLimitAlgorithm::TokenBucket) };
+    let decision = tracker.check("huge-burst", &burst).await;
+    assert!(decision.allowed);
+    assert_eq!(tracker.windows().await.len(), 5);
+}